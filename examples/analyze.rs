@@ -0,0 +1,23 @@
+use anyhow::Error;
+use clap::Parser;
+use hyper_scheduler::analysis::{analyze, TaskSet};
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct AnalyzeArgs {
+    /// 任务集文件(JSON，扩展名是.toml时按TOML)
+    #[arg(short, long, default_value = "examples/taskset.json")]
+    taskset: String,
+}
+
+// cargo run --package hyper-scheduler --example analyze -- --taskset examples/taskset.json
+// cargo run --package hyper-scheduler --example analyze -- --taskset examples/taskset.toml
+// 部署前检查任务集能否调度，输出JSON格式的分析报告
+fn main() -> Result<(), Error> {
+    let args = AnalyzeArgs::parse();
+    let set = TaskSet::from_file(&args.taskset)
+        .map_err(|err| Error::msg(format!("invalid task set {}: {}", args.taskset, err)))?;
+    let report = analyze(&set).map_err(|err| Error::msg(format!("analysis failed: {}", err)))?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
@@ -29,6 +29,7 @@ async fn main() {
         expected_execution_time: t1.to_string(),
         expected_deadline: t2.to_string(),
         //expected_deadline: rng.gen_range(2100..20000).to_string(),
+        ..Default::default()
     };
 
     // 部署服务
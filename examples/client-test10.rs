@@ -30,6 +30,7 @@ async fn main() {
             results_length: "1".to_owned(),
             expected_execution_time: t1.to_string(),
             expected_deadline: t2.to_string(),
+            ..Default::default()
         };
 
         cfgs.push((client, call_config));
@@ -46,6 +47,7 @@ async fn main() {
         results_length: "1".to_owned(),
         expected_execution_time: t1.to_string(),
         expected_deadline: t2.to_string(),
+        ..Default::default()
     };
 
     cfgs.push((client, call_config));
@@ -24,6 +24,7 @@ async fn main() {
         export_func: "fib_r".to_owned(),
         param_type: "i32".to_owned(),
         params: vec![num.to_string()],
+        args: Vec::new(),
         results_length: "1".to_owned(),
         expected_deadline: t2.to_string(),
     };
@@ -35,6 +35,7 @@ async fn main() {
             results_length: "1".to_owned(),
             expected_execution_time: "1500".to_owned(),
             expected_deadline: "2000".to_owned(),
+            ..Default::default()
         };
         let _ = client.call(&call_cfg).await;
     }
@@ -28,6 +28,7 @@ async fn main() {
             results_length: "0".to_owned(),
             expected_execution_time: "80".to_owned(),
             expected_deadline: "4000".to_owned(),
+            ..Default::default()
         };
         cfgs.push((client, call_cfg));
         // let _ = client.call(&call_cfg).await;
@@ -37,6 +37,7 @@ async fn main() {
         results_length: "1".to_owned(),
         expected_execution_time: "35".to_owned(),
         expected_deadline: "50".to_owned(),
+        ..Default::default()
     };
     cfgs.push((client1, scc1));
 
@@ -50,6 +51,7 @@ async fn main() {
         results_length: "1".to_owned(),
         expected_execution_time: "4".to_owned(),
         expected_deadline: "15".to_owned(),
+        ..Default::default()
     };
     cfgs.push((client2, scc2));
 
@@ -63,6 +65,7 @@ async fn main() {
         results_length: "1".to_owned(),
         expected_execution_time: "1".to_owned(),
         expected_deadline: "5".to_owned(),
+        ..Default::default()
     };
     cfgs.push((client3, scc3));
 
@@ -76,6 +79,7 @@ async fn main() {
         results_length: "1".to_owned(),
         expected_execution_time: "35".to_owned(),
         expected_deadline: "50".to_owned(),
+        ..Default::default()
     };
     cfgs.push((client4, scc4));
 
@@ -1,8 +1,16 @@
 use clap::Parser;
-use hyper_scheduler::axum::{server::Server, ServerArgs};
+use hyper_scheduler::{
+    axum::{server::Server, ServerArgs},
+    runtime::RuntimeBuilder,
+};
 
 // cargo build --release --package hyper-scheduler --example server
 // sudo ./target/release/examples/server --port 3001 --workers 2 --start-cpu 0 --timer-us 0000
+// sudo ./target/release/examples/server --port 3001 --listener-cpus 0 --scheduler-cpus 1 --worker-cpus 2-3 --worker-cpus 10 --tester-cpus 12
+// sudo ./target/release/examples/server --port 3001 --workers 2 --auto-cpus
+// sudo ./target/release/examples/server --port 3001 --workers 2 --history-dir ./history --history-ttl-hours 24
+// sudo ./target/release/examples/server --port 3001 --workers 2 --watchdog kill
+// sudo ./target/release/examples/server --port 3001 --workers 2 --trap-deadline --trap-budget
 // http://127.0.0.1:3000/status
 fn main() {
     let subscriber = tracing_subscriber::FmtSubscriber::builder()
@@ -10,12 +18,12 @@ fn main() {
         .finish();
     tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
     let args = ServerArgs::parse();
+    let layout = args.cpu_layout().expect("invalid cpu layout");
     tracing::info!(
-        "Hyperwasm is listening on 0.0.0.0:{}, with {} workers running on CPUs {} to {}. The expiration time is {}",
+        "Hyperwasm is listening on 0.0.0.0:{}, with {} workers running on CPUs {}. The expiration time is {}",
         args.port,
-        args.workers,
-        args.start_cpu,
-        args.start_cpu + args.workers + 2,
+        layout.workers.len(),
+        layout.all(),
         args.timer_us
     );
     let rt = tokio::runtime::Builder::new_current_thread()
@@ -24,6 +32,17 @@ fn main() {
         .build()
         .unwrap();
     rt.block_on(async {
-        Server::start(args.port, args.workers, args.start_cpu, args.timer_us).await;
+        let mut builder = RuntimeBuilder::new()
+            .layout(layout)
+            .timer_exp(args.timer_us)
+            .epoch(args.epoch_config())
+            .learned_admission(args.learned_admission);
+        if let Some(config) = args.history_config() {
+            builder = builder.history(config);
+        }
+        if let Some(config) = args.watchdog_config().expect("invalid watchdog") {
+            builder = builder.watchdog(config);
+        }
+        Server::start_with_builder(args.port, builder).await;
     });
 }
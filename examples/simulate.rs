@@ -0,0 +1,27 @@
+use clap::Parser;
+use hyper_scheduler::sim::{simulate, Workload};
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct SimArgs {
+    /// 工作负载文件(JSON)
+    #[arg(short, long, default_value = "examples/workload.json")]
+    workload: String,
+
+    /// 输出完整的调度结果(JSON)
+    #[arg(short, long, default_value_t = false)]
+    json: bool,
+}
+
+// cargo run --package hyper-scheduler --example simulate -- --workload examples/workload.json
+// 不需要root权限和cgroup，相同的工作负载每次得到相同的结果
+fn main() {
+    let args = SimArgs::parse();
+    let workload = Workload::from_file(&args.workload).expect("invalid workload");
+    let report = simulate(&workload).expect("simulation failed");
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+    } else {
+        print!("{}", report);
+    }
+}
@@ -0,0 +1,629 @@
+use crate::{
+    scheduler::MAX_WORKERS,
+    sim::{simulate, SimTask, Workload},
+    task::Criticality,
+};
+use anyhow::Error;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeSet, fs, path::Path};
+
+/// 处理器需求分析最多检查的截止时间点数量
+const MAX_TEST_POINTS: usize = 1_000_000;
+/// 模拟验证时每个周期任务最多到达的次数
+const MAX_SIM_JOBS: u64 = 1000;
+
+/// 任务集中的一个任务，时间单位都是微秒
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct TaskSpec {
+    pub name: String,
+    pub wcet_us: u64,     //LO级别的WCET
+    pub deadline_us: u64, //相对截止时间
+    #[serde(default)]
+    pub period_us: u64, //周期，0表示只到达一次
+    #[serde(default)]
+    pub criticality: String, //关键级别 LO/HI，默认LO
+    #[serde(default)]
+    pub wcet_hi_us: Option<u64>, //HI级别的WCET，默认与LO级别相同
+}
+
+impl TaskSpec {
+    fn demand(&self) -> Demand {
+        Demand {
+            wcet: self.wcet_us,
+            deadline: self.deadline_us,
+            period: self.period_us,
+        }
+    }
+
+    /// 周期任务用 C/T，只到达一次的任务用 C/D
+    fn utilization(&self) -> f64 {
+        let t = if self.period_us > 0 {
+            self.period_us
+        } else {
+            self.deadline_us
+        };
+        if t == 0 {
+            return f64::INFINITY;
+        }
+        self.wcet_us as f64 / t as f64
+    }
+
+    /// C / min(D, T)
+    fn density(&self, wcet: u64) -> f64 {
+        let t = match self.period_us {
+            0 => self.deadline_us,
+            period => period.min(self.deadline_us),
+        };
+        if t == 0 {
+            return f64::INFINITY;
+        }
+        wcet as f64 / t as f64
+    }
+}
+
+/// 待分析的任务集
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct TaskSet {
+    pub workers: u8,
+    pub tasks: Vec<TaskSpec>,
+}
+
+impl TaskSet {
+    /// 从文件读取，扩展名是.toml时按TOML解析，否则按JSON解析
+    pub fn from_file(path: impl AsRef<Path>) -> Result<TaskSet, Error> {
+        let path = path.as_ref();
+        let str = fs::read_to_string(path)?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Self::parse_toml(&str),
+            _ => Self::parse(&str),
+        }
+    }
+
+    pub fn parse(str: &str) -> Result<TaskSet, Error> {
+        Self::validate(serde_json::from_str(str)?)
+    }
+
+    /// TOML格式，任务写成[[tasks]]数组
+    pub fn parse_toml(str: &str) -> Result<TaskSet, Error> {
+        Self::validate(toml::from_str(str)?)
+    }
+
+    fn validate(set: TaskSet) -> Result<TaskSet, Error> {
+        for task in set.tasks.iter() {
+            if Criticality::parse(&task.criticality).is_none() {
+                return Err(Error::msg(format!("Invalid_criticality: {}", task.name)));
+            }
+            if task.deadline_us == 0 || task.wcet_us > task.deadline_us {
+                return Err(Error::msg(format!("Invalid_deadline: {}", task.name)));
+            }
+        }
+        Ok(set)
+    }
+}
+
+/// 处理器需求分析使用的任务参数，单位由调用者决定
+#[derive(Clone, Copy, Debug)]
+pub struct Demand {
+    pub wcet: u64,
+    pub deadline: u64, //相对截止时间
+    pub period: u64,   //0表示只到达一次
+}
+
+/**
+ * 需求界限函数
+ * 长度为t的区间内必须完成的执行时间
+ */
+pub fn dbf(tasks: &[Demand], t: u64) -> u64 {
+    tasks
+        .iter()
+        .filter(|task| t >= task.deadline)
+        .map(|task| {
+            let jobs = match task.period {
+                0 => 1,
+                period => (t - task.deadline) / period + 1,
+            };
+            jobs.saturating_mul(task.wcet)
+        })
+        .fold(0u64, |sum, demand| sum.saturating_add(demand))
+}
+
+/// 处理器需求分析的结果
+#[derive(Clone, Debug, Default)]
+pub struct DemandResult {
+    pub feasible: bool,
+    /// 截止时间点超过MAX_TEST_POINTS，没有检查完，按不可调度处理
+    pub inconclusive: bool,
+    /// 每个任务截止时间点上 t - dbf(t) 的最小值
+    pub slack: Vec<i64>,
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/**
+ * EDF处理器需求分析
+ * 所有截止时间点t都满足 dbf(t) <= t 时可调度
+ * 检查的区间长度取超周期和 max(D, Σ(T-D)U/(1-U)) 中较小的一个
+ */
+pub fn demand_test(tasks: &[Demand]) -> DemandResult {
+    let mut result = DemandResult {
+        feasible: true,
+        inconclusive: false,
+        slack: vec![i64::MAX; tasks.len()],
+    };
+    if tasks.is_empty() {
+        return result;
+    }
+    let u: f64 = tasks
+        .iter()
+        .filter(|task| task.period > 0)
+        .map(|task| task.wcet as f64 / task.period as f64)
+        .sum();
+    let max_deadline = tasks.iter().map(|task| task.deadline).max().unwrap_or(0);
+    let hyperperiod = tasks
+        .iter()
+        .filter(|task| task.period > 0)
+        .try_fold(1u64, |lcm, task| {
+            (lcm / gcd(lcm, task.period)).checked_mul(task.period)
+        });
+    let mut bound = match hyperperiod {
+        Some(hyperperiod) => hyperperiod.saturating_add(max_deadline),
+        None => u64::MAX,
+    };
+    if u > 1.0 {
+        result.feasible = false;
+    } else if u < 1.0 {
+        let busy: f64 = tasks
+            .iter()
+            .filter(|task| task.period > task.deadline)
+            .map(|task| {
+                (task.period - task.deadline) as f64 * task.wcet as f64 / task.period as f64
+            })
+            .sum::<f64>()
+            / (1.0 - u);
+        bound = bound.min((busy.ceil() as u64).max(max_deadline));
+    }
+    // 只到达一次的任务，最后一个截止时间之后没有需求
+    if tasks.iter().all(|task| task.period == 0) {
+        bound = max_deadline;
+    }
+
+    let mut points = BTreeSet::new();
+    for task in tasks.iter() {
+        let mut d = task.deadline;
+        while d <= bound {
+            if points.len() >= MAX_TEST_POINTS {
+                result.feasible = false;
+                result.inconclusive = true;
+                break;
+            }
+            points.insert(d);
+            if task.period == 0 {
+                break;
+            }
+            d = match d.checked_add(task.period) {
+                Some(d) => d,
+                None => break,
+            };
+        }
+    }
+    for t in points {
+        let slack = t as i64 - dbf(tasks, t).min(i64::MAX as u64) as i64;
+        if slack < 0 {
+            result.feasible = false;
+        }
+        for (i, task) in tasks.iter().enumerate() {
+            let is_deadline = t >= task.deadline
+                && match task.period {
+                    0 => t == task.deadline,
+                    period => (t - task.deadline) % period == 0,
+                };
+            if is_deadline {
+                result.slack[i] = result.slack[i].min(slack);
+            }
+        }
+    }
+    result
+}
+
+/// 已经到达的一个任务实例，时间相对于当前时刻，单位由调用者决定
+#[derive(Clone, Copy, Debug)]
+pub struct Job {
+    pub remaining: u64, //剩余执行时间
+    pub deadline: u64,  //距离截止时间的长度，0表示已经超时
+}
+
+/**
+ * 已经到达的任务实例的处理器需求分析
+ * 所有任务都已到达时，EDF可调度当且仅当每个截止时间点d都满足 Σ_{d_i<=d} C_i <= d，
+ * 返回所有截止时间点上 d - Σ_{d_i<=d} C_i 的最小值，非负时可调度
+ * 已经超时的任务仍然占用处理器，但不再检查它们的截止时间
+ */
+pub fn job_slack(jobs: &[Job]) -> i64 {
+    let mut jobs = jobs.to_vec();
+    jobs.sort_by_key(|job| job.deadline);
+    let mut demand: u64 = 0;
+    let mut slack = i64::MAX;
+    for (i, job) in jobs.iter().enumerate() {
+        demand = demand.saturating_add(job.remaining);
+        // 相同截止时间的任务一起检查
+        if job.deadline == 0 || jobs.get(i + 1).map(|next| next.deadline) == Some(job.deadline) {
+            continue;
+        }
+        let s = job.deadline.min(i64::MAX as u64) as i64 - demand.min(i64::MAX as u64) as i64;
+        slack = slack.min(s);
+    }
+    slack
+}
+
+/**
+ * 加入剩余执行时间为remaining的新任务后仍然可调度的最早截止时间
+ * 截止时间越晚越容易满足，所以用二分查找，已有的任务本身不可调度时返回None
+ */
+pub fn earliest_deadline(jobs: &[Job], remaining: u64) -> Option<u64> {
+    let feasible = |deadline: u64| {
+        let mut all = jobs.to_vec();
+        all.push(Job {
+            remaining,
+            deadline,
+        });
+        job_slack(&all) >= 0
+    };
+    let total = jobs
+        .iter()
+        .fold(remaining, |sum, job| sum.saturating_add(job.remaining));
+    let max_deadline = jobs.iter().map(|job| job.deadline).max().unwrap_or(0);
+    let mut hi = total.max(max_deadline).max(1);
+    if !feasible(hi) {
+        return None;
+    }
+    let mut lo = remaining.max(1);
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if feasible(mid) {
+            hi = mid;
+        } else {
+            lo = mid + 1;
+        }
+    }
+    Some(hi)
+}
+
+/**
+ * 响应时间分析，tasks按优先级从高到低排列
+ * R_i = C_i + Σ_{j<i} ⌈R_i / T_j⌉ C_j，只到达一次的高优先级任务只计算一次
+ * 返回每个任务的最坏响应时间，超过截止时间时停止迭代
+ */
+pub fn response_time(tasks: &[Demand]) -> Vec<u64> {
+    let mut response = Vec::with_capacity(tasks.len());
+    for (i, task) in tasks.iter().enumerate() {
+        let mut r = task.wcet;
+        loop {
+            let interference = tasks[..i]
+                .iter()
+                .map(|hp| match hp.period {
+                    0 => hp.wcet,
+                    period => r.div_ceil(period).saturating_mul(hp.wcet),
+                })
+                .fold(0u64, |sum, i| sum.saturating_add(i));
+            let next = task.wcet.saturating_add(interference);
+            if next == r || next > task.deadline {
+                r = next;
+                break;
+            }
+            r = next;
+        }
+        response.push(r);
+    }
+    response
+}
+
+/**
+ * EDF-VD的利用率测试，与准入控制使用同一个判断
+ * u_lo_lo: LO任务在LO级别WCET下的利用率
+ * u_hi_lo: HI任务在LO级别WCET下的利用率
+ * u_hi_hi: HI任务在HI级别WCET下的利用率
+ * 可调度时返回HI任务截止时间的缩放系数x，不需要虚拟截止时间时为1
+ */
+pub fn edf_vd_scale(u_lo_lo: f64, u_hi_lo: f64, u_hi_hi: f64, mode: Criticality) -> Option<f64> {
+    if mode == Criticality::HI {
+        return (u_hi_hi <= 1.0).then_some(1.0);
+    }
+    if u_lo_lo + u_hi_hi <= 1.0 {
+        return Some(1.0);
+    }
+    if u_lo_lo >= 1.0 {
+        return None;
+    }
+    let x = u_hi_lo / (1.0 - u_lo_lo);
+    if x * u_lo_lo + u_hi_hi > 1.0 {
+        return None;
+    }
+    Some(x)
+}
+
+/**
+ * 缩放系数已经固定为x时的EDF-VD测试
+ * worker上的HI任务已经按x设置了虚拟截止时间，新任务不能改变x：
+ * LO模式下 u_lo_lo + u_hi_lo / x <= 1，切换到HI模式后 x * u_lo_lo + u_hi_hi <= 1
+ */
+pub fn edf_vd_fixed(u_lo_lo: f64, u_hi_lo: f64, u_hi_hi: f64, x: f64, mode: Criticality) -> bool {
+    if mode == Criticality::HI {
+        return u_hi_hi <= 1.0;
+    }
+    x > 0.0 && u_lo_lo + u_hi_lo / x <= 1.0 && x * u_lo_lo + u_hi_hi <= 1.0
+}
+
+/// 调度策略
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub enum Policy {
+    /// 分区EDF，处理器需求分析，有HI任务时还需要通过EDF-VD测试
+    EDF,
+    /// 分区单调速率(周期相同时按截止时间)，响应时间分析
+    RM,
+}
+
+/// 一个任务的分析结果
+#[derive(Serialize, Clone, Debug)]
+pub struct TaskResult {
+    pub name: String,
+    pub worker: Option<u8>,
+    pub response_time_us: Option<u64>, //只有RM有
+    pub slack_us: Option<i64>,         //离截止时间的余量
+}
+
+/// 一个worker的分析结果
+#[derive(Serialize, Clone, Debug)]
+pub struct WorkerResult {
+    pub worker: u8,
+    pub tasks: Vec<String>,
+    pub utilization: f64,
+    pub edf_vd_scale: Option<f64>, //只有EDF且有HI任务时有
+}
+
+/// 一种调度策略的分析结果
+#[derive(Serialize, Clone, Debug)]
+pub struct PolicyResult {
+    pub policy: Policy,
+    pub feasible: bool,
+    pub inconclusive: bool, //有任务因为处理器需求分析没有检查完而没有分配
+    pub workers: Vec<WorkerResult>,
+    pub tasks: Vec<TaskResult>,
+    pub suggested_workers: Option<u8>, //能放下所有任务的最少worker数量
+}
+
+/// 用模拟运行验证的结果
+#[derive(Serialize, Clone, Debug)]
+pub struct SimResult {
+    pub jobs: usize,
+    pub admission_ratio: f64,
+    pub rejected: usize,
+    pub deadline_misses: usize,
+}
+
+/// 任务集的分析报告
+#[derive(Serialize, Clone, Debug)]
+pub struct AnalysisReport {
+    pub workers: u8,
+    pub utilization: f64,
+    pub min_workers: u8, //⌈U⌉
+    pub edf: PolicyResult,
+    pub rm: PolicyResult,
+    pub simulation: Option<SimResult>, //按Runtime的准入控制模拟运行一个超周期
+}
+
+/**
+ * 分析任务集
+ * 两种策略都先按利用率从大到小首次适应(first-fit decreasing)分配到worker，
+ * 再分别用处理器需求分析和响应时间分析检查每个worker
+ */
+pub fn analyze(set: &TaskSet) -> Result<AnalysisReport, Error> {
+    if set.workers == 0 {
+        return Err(Error::msg("no workers"));
+    }
+    let utilization: f64 = set.tasks.iter().map(|task| task.utilization()).sum();
+    let min_workers = (utilization.ceil() as u8).max(1);
+    Ok(AnalysisReport {
+        workers: set.workers,
+        utilization,
+        min_workers,
+        edf: analyze_policy(set, Policy::EDF, min_workers),
+        rm: analyze_policy(set, Policy::RM, min_workers),
+        simulation: simulate_set(set),
+    })
+}
+
+fn analyze_policy(set: &TaskSet, policy: Policy, min_workers: u8) -> PolicyResult {
+    let (assignment, unassigned, inconclusive) = partition(set, policy, set.workers);
+    let mut tasks: Vec<TaskResult> = set
+        .tasks
+        .iter()
+        .map(|task| TaskResult {
+            name: task.name.clone(),
+            worker: None,
+            response_time_us: None,
+            slack_us: None,
+        })
+        .collect();
+    let mut workers = Vec::new();
+    for (w, members) in assignment.iter().enumerate() {
+        let (_, results, _) = check_worker(set, policy, members);
+        for (i, (response, slack)) in members.iter().zip(results.iter()) {
+            tasks[*i].worker = Some(w as u8);
+            tasks[*i].response_time_us = response.to_owned();
+            tasks[*i].slack_us = Some(*slack);
+        }
+        workers.push(WorkerResult {
+            worker: w as u8,
+            tasks: members.iter().map(|i| set.tasks[*i].name.clone()).collect(),
+            utilization: members.iter().map(|i| set.tasks[*i].utilization()).sum(),
+            edf_vd_scale: match policy {
+                Policy::EDF => edf_vd_check(set, members).filter(|x| *x < 1.0),
+                Policy::RM => None,
+            },
+        });
+    }
+    let suggested_workers = (min_workers..=MAX_WORKERS).find(|m| partition(set, policy, *m).1 == 0);
+    PolicyResult {
+        policy,
+        feasible: unassigned == 0,
+        inconclusive,
+        workers,
+        tasks,
+        suggested_workers,
+    }
+}
+
+/**
+ * 首次适应递减
+ * 返回每个worker上的任务下标、放不下的任务数量，以及是否有放不下的任务没有检查完
+ */
+fn partition(set: &TaskSet, policy: Policy, workers: u8) -> (Vec<Vec<usize>>, usize, bool) {
+    let mut order: Vec<usize> = (0..set.tasks.len()).collect();
+    order.sort_by(|a, b| {
+        set.tasks[*b]
+            .utilization()
+            .total_cmp(&set.tasks[*a].utilization())
+    });
+    let mut partition: Vec<Vec<usize>> = vec![Vec::new(); workers as usize];
+    let (mut unassigned, mut inconclusive) = (0, false);
+    for i in order {
+        let mut cut = false;
+        let fit = partition.iter().position(|members| {
+            let mut members = members.clone();
+            members.push(i);
+            let (feasible, _, partial) = check_worker(set, policy, &members);
+            cut |= partial;
+            feasible
+        });
+        match fit {
+            Some(w) => partition[w].push(i),
+            None => {
+                unassigned += 1;
+                inconclusive |= cut;
+            }
+        }
+    }
+    (partition, unassigned, inconclusive)
+}
+
+/**
+ * 检查一个worker上的任务
+ * 返回是否可调度、每个任务的(响应时间, 余量)，以及分析是否因为检查点太多没有完成
+ */
+fn check_worker(
+    set: &TaskSet,
+    policy: Policy,
+    members: &[usize],
+) -> (bool, Vec<(Option<u64>, i64)>, bool) {
+    match policy {
+        Policy::EDF => {
+            let demands: Vec<Demand> = members.iter().map(|i| set.tasks[*i].demand()).collect();
+            let result = demand_test(&demands);
+            let feasible = result.feasible && edf_vd_check(set, members).is_some();
+            (
+                feasible,
+                result
+                    .slack
+                    .into_iter()
+                    .map(|slack| (None, slack))
+                    .collect(),
+                result.inconclusive,
+            )
+        }
+        Policy::RM => {
+            // 周期小的优先级高，只到达一次的任务按截止时间
+            let mut order: Vec<usize> = (0..members.len()).collect();
+            let key = |i: usize| {
+                let task = &set.tasks[members[i]];
+                let period = match task.period_us {
+                    0 => task.deadline_us,
+                    period => period,
+                };
+                (period, task.deadline_us, members[i])
+            };
+            order.sort_by_key(|i| key(*i));
+            let demands: Vec<Demand> = order
+                .iter()
+                .map(|i| set.tasks[members[*i]].demand())
+                .collect();
+            let response = response_time(&demands);
+            let mut results = vec![(None, 0); members.len()];
+            let mut feasible = true;
+            for (k, i) in order.iter().enumerate() {
+                let slack = demands[k].deadline as i64 - response[k] as i64;
+                if slack < 0 {
+                    feasible = false;
+                }
+                results[*i] = (Some(response[k]), slack);
+            }
+            (feasible, results, false)
+        }
+    }
+}
+
+/// 对worker上的任务做EDF-VD测试，全是LO任务时不需要
+fn edf_vd_check(set: &TaskSet, members: &[usize]) -> Option<f64> {
+    let (mut u_lo_lo, mut u_hi_lo, mut u_hi_hi) = (0.0, 0.0, 0.0);
+    let mut has_hi = false;
+    for task in members.iter().map(|i| &set.tasks[*i]) {
+        if Criticality::parse(&task.criticality) == Some(Criticality::HI) {
+            has_hi = true;
+            u_hi_lo += task.density(task.wcet_us);
+            u_hi_hi += task.density(task.wcet_hi_us.unwrap_or(task.wcet_us).max(task.wcet_us));
+        } else {
+            u_lo_lo += task.density(task.wcet_us);
+        }
+    }
+    if !has_hi {
+        return Some(1.0);
+    }
+    edf_vd_scale(u_lo_lo, u_hi_lo, u_hi_hi, Criticality::LO)
+}
+
+/**
+ * 按Runtime的准入控制模拟运行
+ * 周期任务到达一个超周期(最多MAX_SIM_JOBS次)，任务按WCET执行
+ */
+fn simulate_set(set: &TaskSet) -> Option<SimResult> {
+    let hyperperiod = set
+        .tasks
+        .iter()
+        .filter(|task| task.period_us > 0)
+        .try_fold(1u64, |lcm, task| {
+            (lcm / gcd(lcm, task.period_us)).checked_mul(task.period_us)
+        })?;
+    let workload = Workload {
+        workers: set.workers,
+        tasks: set
+            .tasks
+            .iter()
+            .map(|task| SimTask {
+                name: task.name.clone(),
+                execution_time_us: task.wcet_us,
+                deadline_us: Some(task.deadline_us),
+                criticality: task.criticality.clone(),
+                expected_execution_time_hi_us: task.wcet_hi_us,
+                period_us: task.period_us,
+                count: match task.period_us {
+                    0 => 1,
+                    period => (hyperperiod / period).clamp(1, MAX_SIM_JOBS) as u32,
+                },
+                ..Default::default()
+            })
+            .collect(),
+        ..Default::default()
+    };
+    let report = simulate(&workload).ok()?;
+    Some(SimResult {
+        jobs: report.jobs,
+        admission_ratio: report.admission_ratio,
+        rejected: report.rejected,
+        deadline_misses: report.deadline_misses,
+    })
+}
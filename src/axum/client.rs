@@ -1,4 +1,6 @@
-use super::{CallConfigRequest, TestRequest};
+use super::{
+    AddWorkerRequest, CallConfigRequest, RetireWorkerRequest, TestRequest, WorkflowRequest,
+};
 use crate::runwasm::RegisterConfig;
 /**
  * Client
@@ -75,6 +77,21 @@ impl Client {
         Ok(())
     }
 
+    pub async fn workflow(&self, request: &WorkflowRequest) -> Result<(), reqwest::Error> {
+        let url = format!("http://{}:{}/workflow", self.local_ip, self.port);
+        let json = serde_json::to_string(request).unwrap();
+        let resp = self
+            .client
+            .post(url)
+            .header("Content-Type", "application/json")
+            .body(json)
+            .send()
+            .await?;
+        let body = resp.text().await?;
+        tracing::info!("res: {}", body);
+        Ok(())
+    }
+
     pub async fn call_latency(
         &self,
         call_config: &CallConfigRequest,
@@ -124,6 +141,46 @@ impl Client {
         Ok(())
     }
 
+    pub async fn add_worker(&self, cpus: &str) -> Result<(), reqwest::Error> {
+        let url = format!("http://{}:{}/workers/add", self.local_ip, self.port);
+        let json = serde_json::to_string(&AddWorkerRequest {
+            cpu: None,
+            cpus: cpus.to_owned(),
+        })
+        .unwrap();
+        let resp = self
+            .client
+            .post(url)
+            .header("Content-Type", "application/json")
+            .body(json)
+            .send()
+            .await?;
+
+        let body = resp.text().await?;
+        tracing::info!("Response: {}", body);
+        Ok(())
+    }
+
+    pub async fn retire_worker(&self, worker_id: u8, mode: &str) -> Result<(), reqwest::Error> {
+        let url = format!("http://{}:{}/workers/retire", self.local_ip, self.port);
+        let json = serde_json::to_string(&RetireWorkerRequest {
+            worker_id,
+            mode: mode.to_owned(),
+        })
+        .unwrap();
+        let resp = self
+            .client
+            .post(url)
+            .header("Content-Type", "application/json")
+            .body(json)
+            .send()
+            .await?;
+
+        let body = resp.text().await?;
+        tracing::info!("Response: {}", body);
+        Ok(())
+    }
+
     pub async fn get_latency(&self) -> Result<(), reqwest::Error> {
         let url = format!("http://{}:{}/warm-start-latency", self.local_ip, self.port);
         let resp = self.client.get(url).send().await?;
@@ -132,4 +189,11 @@ impl Client {
         tracing::info!("Response: {}", body);
         Ok(())
     }
+
+    /// 获取Chrome Trace Event格式的调度事件
+    pub async fn get_trace(&self) -> Result<String, reqwest::Error> {
+        let url = format!("http://{}:{}/trace", self.local_ip, self.port);
+        let resp = self.client.get(url).send().await?;
+        resp.text().await
+    }
 }
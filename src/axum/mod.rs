@@ -1,4 +1,12 @@
+use crate::{
+    cpulist::{CpuLayout, CpuList, CpuTopology},
+    history::{HistoryConfig, HistoryQuery},
+    profile::ProfileStats,
+    runtime::{EpochConfig, WatchdogAction, WatchdogConfig},
+};
+use anyhow::Error;
 use serde::{Deserialize, Serialize};
+use std::{path::PathBuf, time::Duration};
 pub mod client;
 pub mod server;
 use clap::Parser;
@@ -13,10 +21,126 @@ pub struct ServerArgs {
     pub workers: u8,
 
     #[arg(short, long, default_value_t = 0)]
-    pub start_cpu: u8,
+    pub start_cpu: u16,
 
     #[arg(short, long, default_value_t = 0_000)]
     pub timer_us: u64,
+
+    /// listener使用的CPU，cpulist格式，例如"0"或"0-1"
+    #[arg(long)]
+    pub listener_cpus: Option<String>,
+
+    /// 全局调度器使用的CPU
+    #[arg(long)]
+    pub scheduler_cpus: Option<String>,
+
+    /// 测试线程使用的CPU
+    #[arg(long)]
+    pub tester_cpus: Option<String>,
+
+    /// 每个worker使用的CPU，每个worker指定一次，会覆盖--workers
+    #[arg(long)]
+    pub worker_cpus: Vec<String>,
+
+    /// 根据CPU拓扑自动分配，忽略其他CPU参数
+    #[arg(long, default_value_t = false)]
+    pub auto_cpus: bool,
+
+    /// 看门狗处理失控任务的方式：report、demote或kill，不指定时不启动看门狗
+    #[arg(long)]
+    pub watchdog: Option<String>,
+
+    /// 超过截止时间的wasm实时任务在epoch检查点trap
+    #[arg(long, default_value_t = false)]
+    pub trap_deadline: bool,
+
+    /// 运行时间超过自己关键级别WCET的wasm实时任务在epoch检查点trap
+    #[arg(long, default_value_t = false)]
+    pub trap_budget: bool,
+
+    /// 准入时使用声明的和学习到的p99中较大的执行时间
+    #[arg(long, default_value_t = false)]
+    pub learned_admission: bool,
+
+    /// 已完成任务历史的目录，不指定时只保留在内存中
+    #[arg(long)]
+    pub history_dir: Option<PathBuf>,
+
+    /// 历史记录保留的小时数
+    #[arg(long, default_value_t = 168)]
+    pub history_ttl_hours: u64,
+
+    /// 单个历史文件的大小上限(MB)，超过后轮转
+    #[arg(long, default_value_t = 64)]
+    pub history_max_mb: u64,
+}
+
+impl ServerArgs {
+    /**
+     * 根据参数生成CpuLayout
+     * 没有指定的部分使用从start_cpu开始的连续CPU
+     */
+    pub fn cpu_layout(&self) -> Result<CpuLayout, Error> {
+        if self.auto_cpus {
+            return CpuLayout::auto(self.workers, &CpuTopology::read()?);
+        }
+        let workers = if self.worker_cpus.is_empty() {
+            self.workers
+        } else {
+            self.worker_cpus.len() as u8
+        };
+        let mut layout = CpuLayout::contiguous(workers, self.start_cpu)?;
+        if let Some(cpus) = &self.listener_cpus {
+            layout.listener = CpuList::parse(cpus)?;
+        }
+        if let Some(cpus) = &self.scheduler_cpus {
+            layout.scheduler = CpuList::parse(cpus)?;
+        }
+        if let Some(cpus) = &self.tester_cpus {
+            layout.tester = CpuList::parse(cpus)?;
+        }
+        if !self.worker_cpus.is_empty() {
+            layout.workers = self
+                .worker_cpus
+                .iter()
+                .map(|cpus| CpuList::parse(cpus))
+                .collect::<Result<_, _>>()?;
+        }
+        layout.validate()?;
+        Ok(layout)
+    }
+
+    /// 指定了--watchdog时的看门狗配置，其他参数使用默认值
+    pub fn watchdog_config(&self) -> Result<Option<WatchdogConfig>, Error> {
+        let action = match self.watchdog.as_deref() {
+            None => return Ok(None),
+            Some("report") => WatchdogAction::REPORT,
+            Some("demote") => WatchdogAction::DEMOTE,
+            Some("kill") => WatchdogAction::KILL,
+            Some(action) => return Err(Error::msg(format!("invalid watchdog action: {}", action))),
+        };
+        Ok(Some(WatchdogConfig {
+            action,
+            ..Default::default()
+        }))
+    }
+
+    /// wasm任务在epoch检查点的处理方式
+    pub fn epoch_config(&self) -> EpochConfig {
+        EpochConfig {
+            trap_deadline: self.trap_deadline,
+            trap_budget: self.trap_budget,
+        }
+    }
+
+    /// 指定了--history-dir时的历史配置
+    pub fn history_config(&self) -> Option<HistoryConfig> {
+        self.history_dir.as_ref().map(|dir| HistoryConfig {
+            dir: dir.clone(),
+            ttl: Duration::from_secs(self.history_ttl_hours * 3600),
+            max_file_size: self.history_max_mb << 20,
+        })
+    }
 }
 
 #[derive(Parser, Debug)]
@@ -34,28 +158,135 @@ struct StatusQuery {
     uname: String,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+/// /history 和 /history.csv 的查询参数
+#[derive(Serialize, Deserialize, Default)]
+pub struct HistoryParams {
+    #[serde(default)]
+    pub from: Option<i64>, //完成时间的下界，unix毫秒
+    #[serde(default)]
+    pub to: Option<i64>, //完成时间的上界，unix毫秒，不包含
+    #[serde(default)]
+    pub module: Option<String>,
+    #[serde(default)]
+    pub outcome: Option<String>, //COMPLETED、MISSED或CANCELLED
+    #[serde(default)]
+    pub worker: Option<u8>,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+impl From<HistoryParams> for HistoryQuery {
+    fn from(params: HistoryParams) -> HistoryQuery {
+        HistoryQuery {
+            from: params.from,
+            to: params.to,
+            module: params.module,
+            outcome: params.outcome,
+            worker_id: params.worker,
+            limit: params.limit,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
 pub struct CallConfigRequest {
-    pub wasm_name: String,               //指定的wasm文件名
-    pub task_unique_name: String,        //实例名称,必须唯一
-    pub export_func: String,             //调用的导出函数名称
-    pub param_type: String,              //数据类型
-    pub params: Vec<String>,             //数组
-    pub results_length: String,          //结果长度
+    pub wasm_name: String,        //指定的wasm文件名
+    pub task_unique_name: String, //实例名称,必须唯一
+    pub export_func: String,      //调用的导出函数名称
+    #[serde(default)]
+    pub param_type: String, //数据类型，所有参数相同，给出了args时忽略
+    #[serde(default)]
+    pub params: Vec<String>, //数组
+    #[serde(default)]
+    pub args: Vec<serde_json::Value>, //按导出函数签名解析的参数：数字、数字字符串或者{"i64": ...}
+    #[serde(default)]
+    pub results_length: String, //结果长度，已经不用了，返回值按签名分配
     pub expected_execution_time: String, //预期执行时长(必须小于相对截止时间，单位毫秒)
-    pub expected_deadline: String,       //相对截止时间(单位毫秒)
+    pub expected_deadline: String, //相对截止时间(单位毫秒)
+    #[serde(default)]
+    pub criticality: String, //关键级别 LO/HI，默认LO
+    #[serde(default)]
+    pub expected_execution_time_hi: String, //HI级别的预期执行时长(单位毫秒)，默认与LO级别相同
+    #[serde(default)]
+    pub auto_accept: String, //不可调度时，反报价的截止时间比预期截止时间晚不超过这个值就自动接受(单位毫秒)，默认不接受
+    #[serde(default)]
+    pub worker_id: String, //指定worker，用于接受反报价，默认轮流选择
+    #[serde(default)]
+    pub wait: bool, //不可调度时等待其他任务结束后重新准入，直到最晚开始时间，默认直接拒绝
+    #[serde(default)]
+    pub param_class: String, //参数类别，用于查找剖析结果，默认按参数类型和参数值区分
+    #[serde(default)]
+    pub fuel_budget: String, //fuel预算，用完时trap，只能用于开启了fuel计量的模块；没有预期执行时长时按校准结果估计，默认不限制
+}
+
+/// 工作流中的一个节点
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct WorkflowNodeRequest {
+    pub name: String,        //节点名称，在工作流中唯一
+    pub wasm_name: String,   //指定的wasm文件名
+    pub export_func: String, //调用的导出函数名称
+    #[serde(default)]
+    pub param_type: String, //固定参数的数据类型
+    #[serde(default)]
+    pub params: Vec<String>, //固定参数，放在前驱节点的输出之前
+    #[serde(default)]
+    pub args: Vec<serde_json::Value>, //按导出函数签名解析的固定参数，给出了args时忽略param_type和params
+    pub expected_execution_time: String, //预期执行时长(单位毫秒)
+    #[serde(default)]
+    pub criticality: String, //关键级别 LO/HI，默认LO
+    #[serde(default)]
+    pub deps: Vec<String>, //前驱节点，按顺序把它们的输出作为参数
+}
+
+/// 工作流，所有节点需要在端到端的截止时间内完成
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct WorkflowRequest {
+    pub expected_deadline: String, //端到端的相对截止时间(单位毫秒)
+    pub nodes: Vec<WorkflowNodeRequest>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct TestRequest {
-    pub wasm_name: String,         //指定的wasm文件名
-    pub export_func: String,       //调用的导出函数名称
-    pub param_type: String,        //数据类型
-    pub params: Vec<String>,       //数组
-    pub results_length: String,    //结果长度
+    pub wasm_name: String,   //指定的wasm文件名
+    pub export_func: String, //调用的导出函数名称
+    #[serde(default)]
+    pub param_type: String, //数据类型
+    #[serde(default)]
+    pub params: Vec<String>, //数组
+    #[serde(default)]
+    pub args: Vec<serde_json::Value>, //按导出函数签名解析的参数
+    #[serde(default)]
+    pub results_length: String, //结果长度，已经不用了
     pub expected_deadline: String, //预期截止时间(单位ms)
 }
 
+/// 统计WCET剖析
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct ProfileRequest {
+    pub wasm_name: String,   //指定的wasm文件名
+    pub export_func: String, //调用的导出函数名称
+    #[serde(default)]
+    pub param_type: String, //数据类型
+    #[serde(default)]
+    pub params: Vec<String>, //数组
+    #[serde(default)]
+    pub args: Vec<serde_json::Value>, //按导出函数签名解析的参数
+    #[serde(default)]
+    pub results_length: String, //结果长度，已经不用了
+    #[serde(default)]
+    pub param_class: String, //参数类别，默认按参数类型和参数值区分
+    #[serde(default)]
+    pub iterations: String, //运行次数，默认100
+    #[serde(default)]
+    pub interference: String, //干扰线程数量，默认0
+}
+
+#[derive(Serialize, Deserialize)]
+struct ProfileResponse {
+    status: String,
+    profile: Option<ProfileStats>,
+}
+
 #[derive(Serialize, Deserialize)]
 struct RegisterResponse {
     status: String,
@@ -66,9 +297,44 @@ struct RegisterResponse {
 struct CallFuncResponse {
     status: String,
     result: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    earliest_deadline: Option<u64>, //不可调度时能够准入的最早相对截止时间(单位毫秒)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    worker_id: Option<u8>, //反报价的worker
 }
 
 #[derive(Serialize, Deserialize)]
 struct CallWithName {
     wasm_name: String,
 }
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct AddWorkerRequest {
+    #[serde(default)]
+    pub cpu: Option<u16>, //绑定的CPU，默认选择一个空闲的物理核心
+    #[serde(default)]
+    pub cpus: String, //cpulist格式的CPU集合，例如"6-7"，优先于cpu
+}
+
+impl AddWorkerRequest {
+    pub fn get_cpus(&self) -> Result<Option<CpuList>, Error> {
+        if !self.cpus.trim().is_empty() {
+            Ok(Some(CpuList::parse(&self.cpus)?))
+        } else {
+            Ok(self.cpu.map(CpuList::single))
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RetireWorkerRequest {
+    pub worker_id: u8,
+    #[serde(default)]
+    pub mode: String, //migrate(默认)或finish
+}
+
+#[derive(Serialize, Deserialize)]
+struct WorkerResponse {
+    status: String,
+    worker_id: String,
+}
@@ -1,16 +1,27 @@
 use super::{
-    CallConfigRequest, CallFuncResponse, CallWithName, RegisterResponse, StatusQuery, TestRequest,
+    AddWorkerRequest, CallConfigRequest, CallFuncResponse, CallWithName, HistoryParams,
+    ProfileRequest, ProfileResponse, RegisterResponse, RetireWorkerRequest, StatusQuery,
+    TestRequest, WorkerResponse, WorkflowRequest,
 };
 use crate::{
+    cpulist::{CpuLayout, CpuList},
+    history,
+    profile::{ProfileStats, DEFAULT_ITERATIONS, MAX_INTERFERENCE, MAX_ITERATIONS},
     result::{FuncResult, ResultFuture},
-    runtime::Runtime,
+    runtime::{
+        AdmissionControl, ExecutionEstimate, RetireMode, Runtime, RuntimeBuilder, ShutdownMode,
+    },
     runwasm::{
-        call_func, call_func_sync, get_status_by_name, get_test_env, set_test_env, Environment,
-        FuncConfig, RegisterConfig, Tester,
+        call_func, call_func_sync, get_profile_job, get_status_by_name, get_test_env, run_profile,
+        set_profile_job, set_test_env, Deferred, Environment, FuncConfig, ProfileJob,
+        RegisterConfig, Tester,
     },
+    workflow::{call_workflow, Workflow},
 };
 use axum::{
     extract::{Multipart, Query},
+    http::{header, StatusCode},
+    response::IntoResponse,
     routing::{get, post},
     Json, Router,
 };
@@ -18,7 +29,7 @@ use crossbeam::queue::ArrayQueue;
 use once_cell::sync::{Lazy, OnceCell};
 use std::{
     cell::{Cell, RefCell},
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     net::SocketAddr,
     sync::{Arc, Mutex, RwLock},
     time::Instant,
@@ -78,22 +89,24 @@ static ENV_MAP: Lazy<RwLock<HashMap<String, Environment>>> =
     Lazy::new(|| RwLock::new(HashMap::new()));
 /// 函数调用的请求队列
 static REQUEST_QUEUE: Lazy<ArrayQueue<SchedRequest>> = Lazy::new(|| ArrayQueue::new(10000));
+/// 工作流的请求队列
+static WORKFLOW_QUEUE: Lazy<ArrayQueue<(Workflow, Arc<FuncResult>)>> =
+    Lazy::new(|| ArrayQueue::new(1000));
+/// 收到SIGTERM后不再接收新的调用
+static SHUTDOWN: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+/// 收到SIGTERM后等待已准入任务完成的时间
+const SHUTDOWN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+/// 调度器线程，有新请求或者关闭时唤醒
+static SCHEDULER_THREAD: OnceCell<std::thread::Thread> = OnceCell::new();
+/// 有等待准入的请求时检查任务结束的间隔
+const RETRY_INTERVAL: std::time::Duration = std::time::Duration::from_millis(1);
+/// 没有等待准入的请求时最长的休眠时间
+const IDLE_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
 /// 执行的延迟统计，不含响应时间
 pub static LATENCY: Lazy<Mutex<HashMap<i32, i32>>> = Lazy::new(|| Mutex::new(HashMap::new()));
 
-// 一些计数
-/// 准入计数
-static CNT: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(0);
-/// fib27计数，根据ddl判断
-static CNT_27: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(0);
-/// fib30计数，根据ddl判断
-static CNT_30: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(0);
-/// 总连接数
+/// 总连接数，准入计数和时延在runtime().metrics()中
 static CONNECTION: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(1);
-/// 累计热启动时延
-static mut WARM_START_TIME: std::time::Duration = std::time::Duration::from_micros(0);
-/// 累计成功调度时延
-static mut SCHED_TIME: std::time::Duration = std::time::Duration::from_micros(0);
 
 /// 调度请求
 struct SchedRequest {
@@ -103,56 +116,177 @@ struct SchedRequest {
     start_time: Instant, // 请求到达时间
 }
 
+/// 等待准入的请求，按绝对截止时间排序
+#[derive(Default)]
+struct PendingQueue {
+    seq: u64,
+    queue: BTreeMap<(Instant, u64), SchedRequest>,
+}
+
+impl PendingQueue {
+    fn push(&mut self, sched: SchedRequest) {
+        let deadline = sched
+            .func_config
+            .get_deadline_at()
+            .unwrap_or(sched.start_time);
+        self.seq += 1;
+        self.queue.insert((deadline, self.seq), sched);
+    }
+
+    fn take(&mut self) -> Vec<SchedRequest> {
+        std::mem::take(&mut self.queue).into_values().collect()
+    }
+
+    /// 拒绝过了最晚开始时间的请求
+    fn expire(&mut self, now: Instant) {
+        self.queue.retain(|_, sched| {
+            let expired = match sched.func_config.get_worst_start_time() {
+                Some(worst_start_time) => worst_start_time < now,
+                None => true,
+            };
+            if expired {
+                runtime()
+                    .metrics()
+                    .admission(AdmissionControl::UNSCHEDULABLE);
+                sched
+                    .func_result
+                    .set_result("spawn failed, cause: UNSCHEDULABLE");
+                sched.func_result.set_completed();
+            }
+            !expired
+        });
+    }
+}
+
+/**
+ * 唤醒全局调度器线程
+ */
+fn wake_scheduler() {
+    if let Some(thread) = SCHEDULER_THREAD.get() {
+        thread.unpark();
+    }
+}
+
 /**
  * 创建全局调度器线程
+ * 等待模式下不可调度的请求放入等待队列，有任务结束或取消时按截止时间顺序重新准入，
+ * 过了最晚开始时间后拒绝
+ * 没有新请求时休眠，放入请求的一方负责唤醒
  */
-pub fn spawn_scheduler(cpuset: u8) -> JoinHandle<()> {
+pub fn spawn_scheduler(cpus: CpuList) -> JoinHandle<()> {
     tokio::task::spawn_blocking(move || {
         let cg_scheduler = crate::cgroupv2::Controllerv2::new(
-            std::path::PathBuf::from("/sys/fs/cgroup/hyperwasm"),
+            runtime().get_cgroup_root().to_path_buf(),
             String::from("scheduler"),
         );
         cg_scheduler.set_threaded();
-        cg_scheduler.set_cpuset(cpuset, None);
+        if let Err(err) = cg_scheduler.set_cpus(&cpus) {
+            tracing::warn!("failed to set cpus of scheduler: {}", err);
+        }
         cg_scheduler.set_cgroup_threads(nix::unistd::gettid());
-        loop {
-            while let Some(sched) = REQUEST_QUEUE.pop() {
-                let ddl = sched.func_config.get_relative_deadline();
-                let start = std::time::Instant::now();
+        let _ = SCHEDULER_THREAD.set(std::thread::current());
+        let mut pending = PendingQueue::default();
+        let mut completions = runtime().get_completions();
+        while !SHUTDOWN.load(std::sync::atomic::Ordering::Acquire) {
+            while let Some(mut sched) = REQUEST_QUEUE.pop() {
+                sched.func_config.fix_deadline(Instant::now());
+                schedule(sched, &mut pending);
+            }
+            while let Some((workflow, func_result)) = WORKFLOW_QUEUE.pop() {
                 if let Ok(map) = ENV_MAP.read() {
-                    if let Some(env) = map.get(&sched.name) {
-                        match call_func(
-                            runtime(),
-                            env.clone(),
-                            sched.func_config,
-                            &sched.func_result,
-                        ) {
-                            Ok(_) => {
-                                let end = std::time::Instant::now();
-                                unsafe {
-                                    SCHED_TIME += end - start;
-                                    WARM_START_TIME += end - sched.start_time;
-                                }
-                                // 测试用统计
-                                CNT.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-                                if ddl == 20 {
-                                    CNT_27.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-                                } else if ddl == 100 {
-                                    CNT_30.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-                                }
-                            }
-                            Err(err) => {
-                                sched.func_result.set_result(&err.to_string());
-                                sched.func_result.set_completed();
-                            }
-                        }
+                    if let Err(err) = call_workflow(runtime(), &map, workflow, &func_result) {
+                        func_result.set_result(&err.to_string());
+                        func_result.set_completed();
                     }
+                }
+            }
+            // 先拒绝过了最晚开始时间的请求，只重新准入还来得及开始的请求
+            pending.expire(Instant::now());
+            // 有任务结束或取消，容量被释放
+            let curr = runtime().get_completions();
+            if curr != completions {
+                completions = curr;
+                for sched in pending.take() {
+                    schedule(sched, &mut pending);
+                }
+            }
+            if REQUEST_QUEUE.is_empty() && WORKFLOW_QUEUE.is_empty() {
+                let timeout = if pending.queue.is_empty() {
+                    IDLE_INTERVAL
+                } else {
+                    RETRY_INTERVAL
                 };
+                std::thread::park_timeout(timeout);
             }
         }
+        reject_queued(pending);
     })
 }
 
+/**
+ * 关闭时拒绝还没有准入的请求
+ */
+fn reject_queued(mut pending: PendingQueue) {
+    while let Some(sched) = REQUEST_QUEUE.pop() {
+        pending.push(sched);
+    }
+    let results = pending
+        .take()
+        .into_iter()
+        .map(|sched| sched.func_result)
+        .chain(std::iter::from_fn(|| WORKFLOW_QUEUE.pop().map(|(_, r)| r)));
+    for func_result in results {
+        runtime()
+            .metrics()
+            .admission(AdmissionControl::UNSCHEDULABLE);
+        func_result.set_result("spawn failed, cause: SHUTDOWN");
+        func_result.set_completed();
+    }
+}
+
+/**
+ * 准入控制并生成microprocess
+ */
+fn schedule(sched: SchedRequest, pending: &mut PendingQueue) {
+    let SchedRequest {
+        name,
+        func_config,
+        func_result,
+        start_time,
+    } = sched;
+    let start = std::time::Instant::now();
+    // 等待模式需要保留请求用于重新准入
+    let retry = func_config.is_wait().then(|| func_config.clone());
+    if let Ok(map) = ENV_MAP.read() {
+        if let Some(env) = map.get(&name) {
+            match call_func(runtime(), env.clone(), func_config, &func_result) {
+                Ok(_) => {
+                    let end = std::time::Instant::now();
+                    runtime().metrics().request(end - start, end - start_time);
+                }
+                Err(err) if err.is::<Deferred>() => {
+                    // 用准入控制使用的WCET计算最晚开始时间
+                    let mut func_config = retry.unwrap();
+                    if let Some(deferred) = err.downcast_ref::<Deferred>() {
+                        func_config.set_expected_execution_time(deferred.expected_execution_time);
+                        func_config.set_admission_wcet(deferred.admission_wcet);
+                    }
+                    pending.push(SchedRequest {
+                        name,
+                        func_config,
+                        func_result,
+                        start_time,
+                    });
+                }
+                Err(err) => {
+                    func_result.set_result(&err.to_string());
+                    func_result.set_completed();
+                }
+            }
+        }
+    };
+}
+
 pub struct Server {}
 
 impl Server {
@@ -164,36 +298,66 @@ impl Server {
      * workers: start_cpu + 2 ~ start_cpu + 1 + worker_threads
      * tester: start_cpu + 2 + worker_threads
      */
-    pub async fn start(port: u16, worker_threads: u8, start_cpu: u8, timer_exp: u64) {
+    pub async fn start(port: u16, worker_threads: u8, start_cpu: u16, timer_exp: u64) {
+        let builder = RuntimeBuilder::new()
+            .worker_threads(worker_threads.max(1))
+            .start_cpu(start_cpu)
+            .timer_exp(timer_exp);
+        Self::start_with_builder(port, builder).await;
+    }
+
+    /**
+     * 按CpuLayout启动Server，每个线程可以使用任意的CPU集合
+     */
+    pub async fn start_with_layout(port: u16, layout: CpuLayout, timer_exp: u64) {
+        let builder = RuntimeBuilder::new().layout(layout).timer_exp(timer_exp);
+        Self::start_with_builder(port, builder).await;
+    }
+
+    /**
+     * 按RuntimeBuilder的配置启动Server
+     */
+    pub async fn start_with_builder(port: u16, builder: RuntimeBuilder) {
         set_port(port);
         // 初始化runtime
-        let _ = RUNTIME.set(Runtime::new(
-            Some(worker_threads),
-            Some(start_cpu),
-            Some(timer_exp),
-        ));
+        let _ = RUNTIME.set(builder.build().expect("invalid runtime config"));
+        let layout = runtime().get_layout();
+        let (sched_cpus, tester_cpus) = (layout.scheduler.clone(), layout.tester.clone());
         // 创建全局调度器线程
-        let sched = spawn_scheduler(start_cpu + 1);
+        let sched = spawn_scheduler(sched_cpus);
         // crate::runwasm::MODEL.as_ref();
         // 测试用线程，实验室联调用
-        let handle = spawn_tester(start_cpu + 2 + worker_threads);
+        let handle = spawn_tester(tester_cpus);
         // 在这里添加路由
         let app = Router::new()
             .route("/register", post(Self::register))
             .route("/test", post(Self::test))
+            .route("/profile", post(Self::profile))
+            .route("/profiles", get(Self::get_profiles))
+            .route("/estimates", get(Self::get_estimates))
             .route("/call_with_name", post(Self::call_with_name))
             .route("/init", get(Self::init))
             .route("/call", post(Self::call_func))
+            .route("/workflow", post(Self::call_workflow))
             .route("/status", get(Self::get_status))
             .route("/uname", get(Self::get_status_by_name))
-            .route("/warm-start-latency", get(Self::get_warm_start_latency));
+            .route("/warm-start-latency", get(Self::get_warm_start_latency))
+            .route("/trace", get(Self::get_trace))
+            .route("/metrics", get(Self::get_metrics))
+            .route("/health", get(Self::get_health))
+            .route("/history", get(Self::get_history))
+            .route("/history.csv", get(Self::get_history_csv))
+            .route("/workers", get(Self::get_workers))
+            .route("/workers/add", post(Self::add_worker))
+            .route("/workers/retire", post(Self::retire_worker));
 
         let addr = SocketAddr::from(([0, 0, 0, 0], port));
         tracing::info!("listening on {}", addr);
 
-        // 启动服务器
+        // 启动服务器，收到SIGTERM后排空任务再退出
         axum::Server::bind(&addr)
             .serve(app.into_make_service())
+            .with_graceful_shutdown(Self::shutdown_signal())
             .await
             .unwrap();
         // 全局调度器线程在这里阻塞
@@ -202,6 +366,28 @@ impl Server {
         handle.await.unwrap();
     }
 
+    /**
+     * 等待SIGTERM
+     * 先拒绝新的调用，再排空已经准入的任务、删除cgroup，之后Server停止接收连接
+     */
+    async fn shutdown_signal() {
+        let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler");
+        sigterm.recv().await;
+        tracing::info!("SIGTERM received, draining");
+        SHUTDOWN.store(true, std::sync::atomic::Ordering::Release);
+        wake_scheduler();
+        let res = tokio::task::spawn_blocking(|| {
+            runtime().shutdown(ShutdownMode::DRAINADMITTED, SHUTDOWN_TIMEOUT)
+        })
+        .await;
+        if let Ok(Err(err)) = res {
+            tracing::warn!("shutdown: {}", err);
+        }
+        // 调度器线程退出后才入队的请求
+        reject_queued(PendingQueue::default());
+    }
+
     /**
      * route: /init
      * 部署服务
@@ -254,7 +440,7 @@ impl Server {
         let connection = CONNECTION.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
         //请求数量统计，每10000个请求更新一次
         if connection % 10000 == 0 {
-            let cnt = CNT.load(std::sync::atomic::Ordering::Relaxed);
+            let cnt = runtime().metrics().get_requests() as u32;
             let time = std::time::Instant::now() - get_start();
             // 吞吐量统计
             THROUGHPUT.with(|throughput| throughput.borrow_mut().push((time, connection, cnt)));
@@ -262,7 +448,13 @@ impl Server {
         let mut response = CallFuncResponse {
             status: "Error".to_owned(),
             result: "null".to_owned(),
+            earliest_deadline: None,
+            worker_id: None,
         };
+        if SHUTDOWN.load(std::sync::atomic::Ordering::Acquire) {
+            response.status = "Error_Shutting_down".to_owned();
+            return Json(response);
+        }
         let name = call_config.wasm_name.clone();
         // 这个status的flag感觉没什么用
         let mut status = false;
@@ -282,6 +474,7 @@ impl Server {
                             func_result: func_result.clone(),
                             start_time,
                         });
+                        wake_scheduler();
                         status = true;
                     } else {
                         response.status = "Error_Invalid_wasm_name".to_owned();
@@ -292,6 +485,12 @@ impl Server {
                     let res = Self::get_result(&func_result).await;
                     response.status = "Success".to_owned();
                     response.result = res;
+                    // 不可调度时返回反报价
+                    if let Some(offer) = func_result.get_counter_offer() {
+                        response.earliest_deadline =
+                            Some(offer.relative_deadline.as_micros().div_ceil(1000) as u64);
+                        response.worker_id = Some(offer.worker_id);
+                    }
                 }
             }
             Err(err) => response.status = format!("Error_{}", err),
@@ -299,6 +498,36 @@ impl Server {
         Json(response)
     }
 
+    /**
+     * route: /workflow
+     * 工作流调用，所有节点共用一个端到端的截止时间
+     * 每个节点按自己的关键级别准入，可以放在不同的worker上，所有节点都能准入时才提交
+     */
+    async fn call_workflow(Json(request): Json<WorkflowRequest>) -> Json<CallFuncResponse> {
+        let mut response = CallFuncResponse {
+            status: "Error".to_owned(),
+            result: "null".to_owned(),
+            earliest_deadline: None,
+            worker_id: None,
+        };
+        if SHUTDOWN.load(std::sync::atomic::Ordering::Acquire) {
+            response.status = "Error_Shutting_down".to_owned();
+            return Json(response);
+        }
+        match Workflow::new(request) {
+            Ok(workflow) => {
+                let func_result = Arc::new(FuncResult::new());
+                if WORKFLOW_QUEUE.push((workflow, func_result.clone())).is_ok() {
+                    wake_scheduler();
+                    response.status = "Success".to_owned();
+                    response.result = Self::get_result(&func_result).await;
+                }
+            }
+            Err(err) => response.status = format!("Error_{}", err),
+        }
+        Json(response)
+    }
+
     /**
      * 取得函数计算结果
      */
@@ -357,6 +586,76 @@ impl Server {
         }
     }
 
+    /**
+     * route: /trace
+     * 导出调度事件，Chrome Trace Event格式，保存为json后可以用Perfetto打开
+     */
+    async fn get_trace() -> impl IntoResponse {
+        (
+            [(header::CONTENT_TYPE, "application/json")],
+            runtime().get_trace(),
+        )
+    }
+
+    /**
+     * route: /metrics
+     * Prometheus文本格式的调度指标
+     */
+    async fn get_metrics() -> impl IntoResponse {
+        (
+            [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+            runtime().get_metrics(),
+        )
+    }
+
+    /**
+     * route: /health
+     * 各worker的健康状态，有失控任务的worker返回503
+     */
+    async fn get_health() -> impl IntoResponse {
+        let workers = runtime().get_health();
+        let healthy = workers.iter().all(|w| w.healthy);
+        let code = if healthy {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+        let body = serde_json::json!({
+            "status": if healthy { "ok" } else { "unhealthy" },
+            "workers": workers,
+        });
+        (code, Json(body))
+    }
+
+    /**
+     * route: /history
+     * 查询持久化的已完成任务，可以按完成时间、模块、结果和worker过滤
+     */
+    async fn get_history(Query(params): Query<HistoryParams>) -> impl IntoResponse {
+        let body = match runtime().query_history(&params.into()) {
+            Ok(records) => serde_json::to_string(&records).unwrap_or_default(),
+            Err(err) => serde_json::json!({ "status": format!("Error_{}", err) }).to_string(),
+        };
+        ([(header::CONTENT_TYPE, "application/json")], body)
+    }
+
+    /**
+     * route: /history.csv
+     * 按相同的条件导出CSV，用于离线分析
+     */
+    async fn get_history_csv(Query(params): Query<HistoryParams>) -> impl IntoResponse {
+        match runtime().query_history(&params.into()) {
+            Ok(records) => (
+                [(header::CONTENT_TYPE, "text/csv")],
+                history::to_csv(&records),
+            ),
+            Err(err) => (
+                [(header::CONTENT_TYPE, "text/plain")],
+                format!("Error_{}", err),
+            ),
+        }
+    }
+
     /**
      * route: /warm-start-latency
      * 查看平均热启动延迟、平均准入控制延迟等等
@@ -377,23 +676,83 @@ impl Server {
         // }
 
         // 获取一些计数信息
-        let cnt = CNT.load(std::sync::atomic::Ordering::Relaxed);
-        // let cnt27 = CNT_27.load(std::sync::atomic::Ordering::Relaxed);
-        // let cnt30 = CNT_30.load(std::sync::atomic::Ordering::Relaxed);
-
-        // 没有计数就算了
-        if cnt == 0 {
-            return "cnt: 0".to_owned();
-        }
-        // 计算平均延迟
-        let start_latency = unsafe { WARM_START_TIME } / cnt;
-        let sched_latency = unsafe { SCHED_TIME } / cnt;
+        let metrics = runtime().metrics();
+        let cnt = metrics.get_requests();
+        // 没有计数就算了，计算平均延迟
+        let (sched_latency, start_latency) = match metrics.get_request_latency() {
+            Some(latency) => latency,
+            None => return "cnt: 0".to_owned(),
+        };
         format!(
             "cnt: {}, start_latency: {:?},  sched_latency: {:?}",
             cnt, start_latency, sched_latency
         )
     }
 
+    /**
+     * route: /workers
+     * 查看正在运行的工作核心
+     */
+    async fn get_workers() -> String {
+        let mut s = String::new();
+        runtime().get_workers().iter().for_each(|(id, cpu)| {
+            s.push_str(&format!("worker: {}, cpus: {}\n", id, cpu));
+        });
+        s
+    }
+
+    /**
+     * route: /workers/add
+     * 运行时增加工作核心
+     */
+    async fn add_worker(Json(request): Json<AddWorkerRequest>) -> Json<WorkerResponse> {
+        let mut response = WorkerResponse {
+            status: "Error".to_owned(),
+            worker_id: "null".to_owned(),
+        };
+        let cpus = match request.get_cpus() {
+            Ok(cpus) => cpus,
+            Err(err) => {
+                response.status = format!("Error_{}", err);
+                return Json(response);
+            }
+        };
+        match runtime().add_worker(cpus) {
+            Ok(worker_id) => {
+                response.status = "Success".to_owned();
+                response.worker_id = worker_id.to_string();
+            }
+            Err(err) => response.status = format!("Error_{}", err),
+        }
+        Json(response)
+    }
+
+    /**
+     * route: /workers/retire
+     * 运行时退役工作核心，等待worker退出后返回
+     */
+    async fn retire_worker(Json(request): Json<RetireWorkerRequest>) -> Json<WorkerResponse> {
+        let mut response = WorkerResponse {
+            status: "Error".to_owned(),
+            worker_id: request.worker_id.to_string(),
+        };
+        let mode = match request.mode.to_ascii_lowercase().as_str() {
+            "" | "migrate" => RetireMode::MIGRATE,
+            "finish" => RetireMode::FINISH,
+            _ => {
+                response.status = "Error_Invalid_mode".to_owned();
+                return Json(response);
+            }
+        };
+        let worker_id = request.worker_id;
+        match tokio::task::spawn_blocking(move || runtime().retire_worker(worker_id, mode)).await {
+            Ok(Ok(())) => response.status = "Success".to_owned(),
+            Ok(Err(err)) => response.status = format!("Error_{}", err),
+            Err(err) => response.status = format!("Error_{}", err),
+        }
+        Json(response)
+    }
+
     /**
      * route: /register
      * 不用这个，但是留着
@@ -441,7 +800,13 @@ impl Server {
         let mut response = CallFuncResponse {
             status: "Error".to_owned(),
             result: "null".to_owned(),
+            earliest_deadline: None,
+            worker_id: None,
         };
+        if SHUTDOWN.load(std::sync::atomic::Ordering::Acquire) {
+            response.status = "Error_Shutting_down".to_owned();
+            return Json(response);
+        }
         let mut status = false;
         let func_result = Arc::new(FuncResult::new());
         if let Ok(map) = ENV_MAP.read() {
@@ -475,6 +840,8 @@ impl Server {
         let mut response = CallFuncResponse {
             status: "Error".to_owned(),
             result: "null".to_owned(),
+            earliest_deadline: None,
+            worker_id: None,
         };
         let name = test_config.wasm_name.to_owned();
         let mut status = false;
@@ -514,6 +881,98 @@ impl Server {
         Json(response)
     }
 
+    /**
+     * route: /profile
+     * 在测试线程上运行多次，统计执行时间并估计WCET界
+     * 结果按(模块, 导出函数, 参数类别)保存，调用没有给出预期执行时间时使用
+     */
+    async fn profile(Json(request): Json<ProfileRequest>) -> Json<ProfileResponse> {
+        let mut response = ProfileResponse {
+            status: "Error".to_owned(),
+            profile: None,
+        };
+        let iterations = match request.iterations.as_str() {
+            "" => DEFAULT_ITERATIONS,
+            s => match s.parse::<usize>() {
+                Ok(n) if n > 0 && n <= MAX_ITERATIONS => n,
+                _ => {
+                    response.status = "Error_Invalid_iterations".to_owned();
+                    return Json(response);
+                }
+            },
+        };
+        let interference = match request.interference.as_str() {
+            "" => 0,
+            s => match s.parse::<u32>() {
+                Ok(n) if n <= MAX_INTERFERENCE => n,
+                _ => {
+                    response.status = "Error_Invalid_interference".to_owned();
+                    return Json(response);
+                }
+            },
+        };
+        let name = request.wasm_name.clone();
+        let param_class = request.param_class.clone();
+        let mut conf = match FuncConfig::from(TestRequest {
+            wasm_name: request.wasm_name,
+            export_func: request.export_func,
+            param_type: request.param_type,
+            params: request.params,
+            args: request.args,
+            results_length: request.results_length,
+            expected_deadline: "0".to_owned(),
+        }) {
+            Ok(conf) => conf,
+            Err(err) => {
+                response.status = format!("Error_{}", err);
+                return Json(response);
+            }
+        };
+        if !param_class.is_empty() {
+            conf.set_param_class(&param_class);
+        }
+        let env = match ENV_MAP.read().ok().and_then(|map| map.get(&name).cloned()) {
+            Some(env) => env,
+            None => {
+                response.status = "Error_Invalid_wasm_name".to_owned();
+                return Json(response);
+            }
+        };
+        let func_result = Arc::new(FuncResult::new());
+        set_profile_job(ProfileJob {
+            env,
+            conf,
+            iterations,
+            interference,
+            result: func_result.clone(),
+        });
+        let res = Self::get_result(&func_result).await;
+        match serde_json::from_str::<ProfileStats>(&res) {
+            Ok(stats) => {
+                response.status = "Success".to_owned();
+                response.profile = Some(stats);
+            }
+            Err(_) => response.status = format!("Error_{}", res),
+        }
+        Json(response)
+    }
+
+    /**
+     * route: /profiles
+     * 所有剖析结果
+     */
+    async fn get_profiles() -> Json<Vec<ProfileStats>> {
+        Json(runtime().get_profiles())
+    }
+
+    /**
+     * route: /estimates
+     * 按已完成任务在线学习到的执行时间
+     */
+    async fn get_estimates() -> Json<Vec<ExecutionEstimate>> {
+        Json(runtime().get_execution_estimates())
+    }
+
     /**
      * route: /call
      * 函数调用
@@ -525,7 +984,7 @@ impl Server {
         let connection = CONNECTION.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
         if connection % 10000 == 0 {
             //请求数量统计，每10000个请求更新一次
-            let cnt = CNT.load(std::sync::atomic::Ordering::Relaxed);
+            let cnt = runtime().metrics().get_requests() as u32;
             let time = std::time::Instant::now() - get_start();
             THROUGHPUT.with(|throughput| throughput.borrow_mut().push((time, connection, cnt)));
         }
@@ -533,9 +992,10 @@ impl Server {
         let mut response = CallFuncResponse {
             status: "Error".to_owned(),
             result: "null".to_owned(),
+            earliest_deadline: None,
+            worker_id: None,
         };
         let name = call_config.wasm_name.to_owned();
-        let ddl = call_config.expected_deadline.clone();
         let mut status = false;
 
         let start = std::time::Instant::now();
@@ -557,16 +1017,7 @@ impl Server {
                                 let end = std::time::Instant::now();
                                 warm_start = end - start;
                                 // tracing::info!("{:?}", warm_start);
-                                unsafe {
-                                    WARM_START_TIME += warm_start;
-                                }
-                                // 测试用统计
-                                CNT.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-                                if ddl == "20" {
-                                    CNT_27.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-                                } else if ddl == "100" {
-                                    CNT_30.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-                                }
+                                runtime().metrics().request(warm_start, warm_start);
                                 // tracing::info!("warm-start: {:?}", end - start);
                                 status = true;
                             }
@@ -585,6 +1036,12 @@ impl Server {
                     let res = Self::get_result(&func_result).await;
                     response.status = "Success".to_owned();
                     response.result = res;
+                    // 不可调度时返回反报价
+                    if let Some(offer) = func_result.get_counter_offer() {
+                        response.earliest_deadline =
+                            Some(offer.relative_deadline.as_micros().div_ceil(1000) as u64);
+                        response.worker_id = Some(offer.worker_id);
+                    }
                     response.result = format!("{:?}", warm_start);
                 }
             }
@@ -600,16 +1057,18 @@ impl Server {
  * 创建测试用线程
  * 实验室联调用
  */
-pub fn spawn_tester(cpuset: u8) -> JoinHandle<()> {
+pub fn spawn_tester(cpus: CpuList) -> JoinHandle<()> {
     tokio::task::spawn_blocking(move || {
         let cg_tester = crate::cgroupv2::Controllerv2::new(
-            std::path::PathBuf::from("/sys/fs/cgroup/hyperwasm"),
+            runtime().get_cgroup_root().to_path_buf(),
             String::from("tester"),
         );
         cg_tester.set_threaded();
-        cg_tester.set_cpuset(cpuset, None);
+        if let Err(err) = cg_tester.set_cpus(&cpus) {
+            tracing::warn!("failed to set cpus of tester: {}", err);
+        }
         cg_tester.set_cgroup_threads(nix::unistd::gettid());
-        loop {
+        while !SHUTDOWN.load(std::sync::atomic::Ordering::Acquire) {
             // std::thread::sleep(std::time::Duration::from_millis(1));
             if let Some(tester) = get_test_env() {
                 match call_func_sync(tester.env) {
@@ -624,6 +1083,14 @@ pub fn spawn_tester(cpuset: u8) -> JoinHandle<()> {
                     }
                 };
             }
+            if let Some(job) = get_profile_job() {
+                let res = match run_profile(runtime(), &job) {
+                    Ok(stats) => serde_json::to_string(&stats).unwrap_or_default(),
+                    Err(err) => err.to_string(),
+                };
+                job.result.set_result(&res);
+                job.result.set_completed();
+            }
         }
     })
 }
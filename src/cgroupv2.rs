@@ -1,3 +1,4 @@
+use crate::cpulist::CpuList;
 use nix::{sys::stat, unistd};
 use std::{
     fs::{self, File},
@@ -5,6 +6,7 @@ use std::{
     path::PathBuf,
 };
 
+#[derive(Clone, Debug)]
 pub enum ControllerType {
     CPUSET,
     CPU,
@@ -178,6 +180,42 @@ impl Controllerv2 {
         Ok(weight)
     }
 
+    /// 把进程的所有线程移到base
+    pub fn move_procs_to_base(&self, pid: unistd::Pid) {
+        let mut path = self.base.clone();
+        path.push("cgroup.procs");
+        let _ = fs::write(path, pid.to_string());
+    }
+
+    /// 把这个cgroup和子cgroup中的所有线程移到dest
+    pub fn move_threads_to(&self, dest: &Controllerv2) {
+        let mut paths = vec![self.get_path()];
+        if let Ok(entries) = fs::read_dir(self.get_path()) {
+            paths.extend(entries.flatten().map(|e| e.path()).filter(|p| p.is_dir()));
+        }
+        let mut dest_path = dest.get_path();
+        dest_path.push("cgroup.threads");
+        for mut path in paths {
+            path.push("cgroup.threads");
+            if let Ok(threads) = fs::read_to_string(path) {
+                for tid in threads.lines() {
+                    let _ = fs::write(&dest_path, tid);
+                }
+            }
+        }
+    }
+
+    /// 删除所有子cgroup
+    pub fn delete_children(&self) {
+        if let Ok(entries) = fs::read_dir(self.get_path()) {
+            for entry in entries.flatten() {
+                if entry.path().is_dir() {
+                    let _ = fs::remove_dir(entry.path());
+                }
+            }
+        }
+    }
+
     pub fn set_cgroup_procs(&self, pid: unistd::Pid) {
         let mut path = self.path.clone();
         path.push("cgroup.procs");
@@ -223,17 +261,25 @@ impl Controllerv2 {
         Ok(cgroup_type)
     }
 
-    /// FIXME:临时写的，只支持连续的CPU
-    pub fn set_cpuset(&self, cpuset1: u8, cpuset2: Option<u8>) {
+    /// 连续的CPU，cpuset2为None时只有cpuset1
+    pub fn set_cpuset(&self, cpuset1: u16, cpuset2: Option<u16>) -> Result<(), io::Error> {
+        self.set_cpus(&CpuList::range(cpuset1, cpuset2.unwrap_or(cpuset1)))
+    }
+
+    /// cpuset.cpus，任意的CPU集合
+    pub fn set_cpus(&self, cpus: &CpuList) -> Result<(), io::Error> {
         let mut path = self.path.clone();
         path.push("cpuset.cpus");
-        let mut contents = String::new();
-        contents.push_str(cpuset1.to_string().as_str());
-        if let Some(cpu) = cpuset2 {
-            contents.push('-');
-            contents.push_str(cpu.to_string().as_str());
-        }
-        if let Ok(_) = fs::write(path, contents) {};
+        fs::write(&path, cpus.to_string())
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", path.display(), err)))
+    }
+
+    pub fn cpus(&self) -> Result<CpuList, io::Error> {
+        let mut cpus = String::new();
+        let mut path = self.path.clone();
+        path.push("cpuset.cpus");
+        File::open(path)?.read_to_string(&mut cpus)?;
+        CpuList::parse(&cpus).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
     }
 }
 
@@ -0,0 +1,65 @@
+use std::{
+    cell::Cell,
+    time::{Duration, Instant},
+};
+
+thread_local! {
+    static VIRTUAL_NOW: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/**
+ * 调度使用的当前时间
+ * 当前线程设置了虚拟时钟时返回虚拟时间，否则返回Instant::now()
+ * 只访问线程局部变量，可以在信号处理函数中调用
+ */
+pub fn now() -> Instant {
+    VIRTUAL_NOW
+        .with(|now| now.get())
+        .unwrap_or_else(Instant::now)
+}
+
+/// 虚拟时钟，只对创建它的线程生效，drop后恢复真实时间
+pub struct VirtualClock {
+    base: Instant,
+    prev: Option<Instant>,
+}
+
+impl VirtualClock {
+    /// 从当前的真实时间开始
+    pub fn start() -> VirtualClock {
+        let base = Instant::now();
+        let prev = VIRTUAL_NOW.with(|now| now.replace(Some(base)));
+        VirtualClock { base, prev }
+    }
+
+    /// 虚拟时间，不会倒退
+    pub fn advance_to(&self, elapsed: Duration) {
+        let to = self.base + elapsed;
+        VIRTUAL_NOW.with(|now| {
+            if now.get() < Some(to) {
+                now.set(Some(to));
+            }
+        });
+    }
+
+    /// 从开始到现在的虚拟时间
+    pub fn elapsed(&self) -> Duration {
+        now() - self.base
+    }
+
+    /// 虚拟时间对应的Instant
+    pub fn at(&self, elapsed: Duration) -> Instant {
+        self.base + elapsed
+    }
+
+    /// Instant对应的虚拟时间
+    pub fn since_start(&self, instant: Instant) -> Duration {
+        instant.saturating_duration_since(self.base)
+    }
+}
+
+impl Drop for VirtualClock {
+    fn drop(&mut self) {
+        VIRTUAL_NOW.with(|now| now.set(self.prev));
+    }
+}
@@ -0,0 +1,438 @@
+use anyhow::Error;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt, fs,
+    path::Path,
+};
+
+/// 内核cpulist格式的CPU集合，例如 "2-5,10,12-13"
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CpuList {
+    cpus: BTreeSet<u16>,
+}
+
+impl CpuList {
+    pub fn new() -> CpuList {
+        CpuList::default()
+    }
+
+    /// 单个CPU
+    pub fn single(cpu: u16) -> CpuList {
+        CpuList {
+            cpus: BTreeSet::from([cpu]),
+        }
+    }
+
+    /// 连续的CPU，包含start和end
+    pub fn range(start: u16, end: u16) -> CpuList {
+        CpuList {
+            cpus: (start..=end).collect(),
+        }
+    }
+
+    /**
+     * 解析cpulist格式
+     * 逗号分隔，每一项是单个CPU或者用'-'连接的范围，空字符串表示空集合
+     */
+    pub fn parse(str: &str) -> Result<CpuList, Error> {
+        let mut cpus = BTreeSet::new();
+        for item in str.trim().split(',').filter(|item| !item.trim().is_empty()) {
+            let item = item.trim();
+            let parse_cpu = |cpu: &str| {
+                cpu.trim()
+                    .parse::<u16>()
+                    .map_err(|_| Error::msg(format!("Invalid_cpulist: {}", str)))
+            };
+            if let Some((start, end)) = item.split_once('-') {
+                let (start, end) = (parse_cpu(start)?, parse_cpu(end)?);
+                if start > end {
+                    return Err(Error::msg(format!("Invalid_cpulist: {}", str)));
+                }
+                cpus.extend(start..=end);
+            } else {
+                cpus.insert(parse_cpu(item)?);
+            }
+        }
+        Ok(CpuList { cpus })
+    }
+
+    pub fn insert(&mut self, cpu: u16) {
+        self.cpus.insert(cpu);
+    }
+
+    pub fn contains(&self, cpu: u16) -> bool {
+        self.cpus.contains(&cpu)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cpus.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.cpus.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u16> + '_ {
+        self.cpus.iter().copied()
+    }
+
+    pub fn first(&self) -> Option<u16> {
+        self.cpus.first().copied()
+    }
+
+    /// 并集
+    pub fn union(&self, other: &CpuList) -> CpuList {
+        CpuList {
+            cpus: self.cpus.union(&other.cpus).copied().collect(),
+        }
+    }
+
+    /// 是否有相同的CPU
+    pub fn intersects(&self, other: &CpuList) -> bool {
+        self.cpus.intersection(&other.cpus).next().is_some()
+    }
+}
+
+impl fmt::Display for CpuList {
+    /// 输出cpulist格式，连续的CPU合并为范围
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut ranges: Vec<(u16, u16)> = Vec::new();
+        for cpu in self.iter() {
+            match ranges.last_mut() {
+                Some((_, end)) if end.checked_add(1) == Some(cpu) => *end = cpu,
+                _ => ranges.push((cpu, cpu)),
+            }
+        }
+        let items: Vec<String> = ranges
+            .iter()
+            .map(|(start, end)| {
+                if start == end {
+                    start.to_string()
+                } else {
+                    format!("{}-{}", start, end)
+                }
+            })
+            .collect();
+        write!(f, "{}", items.join(","))
+    }
+}
+
+/// 一个逻辑CPU的拓扑信息
+#[derive(Clone, Copy, Debug)]
+pub struct CpuInfo {
+    pub cpu: u16,
+    pub core_id: u32,
+    pub package_id: u32,
+    pub node: u32,
+    pub llc: u32, // 末级缓存的id，没有缓存信息时按package_id
+}
+
+/// 从/sys/devices/system/cpu读取的CPU拓扑
+#[derive(Clone, Debug)]
+pub struct CpuTopology {
+    cpus: Vec<CpuInfo>,
+}
+
+impl CpuTopology {
+    pub fn read() -> Result<CpuTopology, Error> {
+        Self::read_from(Path::new("/sys/devices/system"))
+    }
+
+    /// sys: 通常是/sys/devices/system
+    pub fn read_from(sys: &Path) -> Result<CpuTopology, Error> {
+        let online = CpuList::parse(&fs::read_to_string(sys.join("cpu/online"))?)?;
+        // 没有NUMA信息时都算作node0
+        let mut nodes = BTreeMap::new();
+        if let Ok(entries) = fs::read_dir(sys.join("node")) {
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                if let Some(node) = name.strip_prefix("node").and_then(|n| n.parse().ok()) {
+                    if let Ok(cpulist) = fs::read_to_string(entry.path().join("cpulist")) {
+                        for cpu in CpuList::parse(&cpulist)?.iter() {
+                            nodes.insert(cpu, node);
+                        }
+                    }
+                }
+            }
+        }
+        let read = |path: String| -> Option<u32> {
+            fs::read_to_string(sys.join(path))
+                .ok()
+                .and_then(|id| id.trim().parse().ok())
+        };
+        let read_id = |cpu: u16, file: &str| -> u32 {
+            read(format!("cpu/cpu{}/topology/{}", cpu, file)).unwrap_or(cpu as u32)
+        };
+        let cpus = online
+            .iter()
+            .map(|cpu| {
+                let package_id = read_id(cpu, "physical_package_id");
+                CpuInfo {
+                    cpu,
+                    core_id: read_id(cpu, "core_id"),
+                    package_id,
+                    node: nodes.get(&cpu).copied().unwrap_or(0),
+                    llc: read(format!("cpu/cpu{}/cache/index3/id", cpu)).unwrap_or(package_id),
+                }
+            })
+            .collect();
+        Ok(CpuTopology { cpus })
+    }
+
+    pub fn get_cpus(&self) -> &[CpuInfo] {
+        &self.cpus
+    }
+
+    /// 与cpus中任意一个CPU共享末级缓存的其他CPU
+    pub fn llc_siblings(&self, cpus: &CpuList) -> CpuList {
+        let llcs: BTreeSet<(u32, u32)> = self
+            .cpus
+            .iter()
+            .filter(|info| cpus.contains(info.cpu))
+            .map(|info| (info.package_id, info.llc))
+            .collect();
+        let mut siblings = CpuList::new();
+        for info in &self.cpus {
+            if !cpus.contains(info.cpu) && llcs.contains(&(info.package_id, info.llc)) {
+                siblings.insert(info.cpu);
+            }
+        }
+        siblings
+    }
+
+    /**
+     * 每个NUMA节点上每个物理核心的第一个逻辑CPU
+     * 只使用这些CPU可以避免SMT兄弟线程互相干扰
+     */
+    pub fn physical_cores(&self) -> BTreeMap<u32, Vec<u16>> {
+        let mut seen = BTreeSet::new();
+        let mut cores: BTreeMap<u32, Vec<u16>> = BTreeMap::new();
+        for info in &self.cpus {
+            if seen.insert((info.package_id, info.core_id)) {
+                cores.entry(info.node).or_default().push(info.cpu);
+            }
+        }
+        cores
+    }
+}
+
+/// HyperWasm各线程使用的CPU
+#[derive(Clone, Debug)]
+pub struct CpuLayout {
+    pub listener: CpuList,
+    pub scheduler: CpuList,
+    pub tester: CpuList,
+    pub workers: Vec<CpuList>,
+}
+
+impl CpuLayout {
+    /**
+     * 从start_cpu开始的连续CPU
+     * litener: start_cpu
+     * scheduler: start_cpu + 1
+     * workers: start_cpu + 2 ~ start_cpu + 1 + worker_threads
+     * tester: start_cpu + 2 + worker_threads
+     */
+    pub fn contiguous(worker_threads: u8, start_cpu: u16) -> Result<CpuLayout, Error> {
+        let cpu = |offset: u16| {
+            start_cpu
+                .checked_add(offset)
+                .map(CpuList::single)
+                .ok_or_else(|| {
+                    Error::msg(format!("cpu {} + {} is out of range", start_cpu, offset))
+                })
+        };
+        Ok(CpuLayout {
+            listener: cpu(0)?,
+            scheduler: cpu(1)?,
+            tester: cpu(2 + worker_threads as u16)?,
+            workers: (0..worker_threads as u16)
+                .map(|i| cpu(2 + i))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    /**
+     * 从start_cpu开始的连续CPU，scheduler和tester使用相对start_cpu的偏移
+     * litener: start_cpu
+     * scheduler: start_cpu + scheduler_offset
+     * tester: start_cpu + tester_offset
+     * workers: 从start_cpu + 1开始的其余CPU
+     */
+    pub fn with_offsets(
+        worker_threads: u8,
+        start_cpu: u16,
+        scheduler_offset: u16,
+        tester_offset: u16,
+    ) -> Result<CpuLayout, Error> {
+        if scheduler_offset == 0 || tester_offset == 0 {
+            return Err(Error::msg("cpu offset 0 is used by the listener"));
+        }
+        if scheduler_offset == tester_offset {
+            return Err(Error::msg("scheduler and tester cpu offsets must differ"));
+        }
+        let cpu = |offset: u16| {
+            start_cpu
+                .checked_add(offset)
+                .map(CpuList::single)
+                .ok_or_else(|| {
+                    Error::msg(format!("cpu {} + {} is out of range", start_cpu, offset))
+                })
+        };
+        let mut workers = Vec::with_capacity(worker_threads as usize);
+        let mut offset: u16 = 1;
+        while workers.len() < worker_threads as usize {
+            if offset != scheduler_offset && offset != tester_offset {
+                workers.push(cpu(offset)?);
+            }
+            offset = offset
+                .checked_add(1)
+                .ok_or_else(|| Error::msg("too many worker cpus"))?;
+        }
+        Ok(CpuLayout {
+            listener: CpuList::single(start_cpu),
+            scheduler: cpu(scheduler_offset)?,
+            tester: cpu(tester_offset)?,
+            workers,
+        })
+    }
+
+    /**
+     * 根据CPU拓扑自动分配
+     * 所有线程放在同一个NUMA节点上，每个线程独占一个物理核心，不使用SMT兄弟线程，
+     * CPU0所在的核心留给系统
+     */
+    pub fn auto(worker_threads: u8, topology: &CpuTopology) -> Result<CpuLayout, Error> {
+        let need = worker_threads as usize + 3;
+        let first = topology.get_cpus().first().map(|info| info.cpu);
+        let cores = topology
+            .physical_cores()
+            .into_values()
+            .map(|cores| {
+                let skipped: Vec<u16> = cores
+                    .iter()
+                    .copied()
+                    .filter(|c| Some(*c) != first)
+                    .collect();
+                if skipped.len() >= need {
+                    skipped
+                } else {
+                    cores
+                }
+            })
+            .filter(|cores| cores.len() >= need)
+            .max_by_key(|cores| cores.len());
+        let cores = match cores {
+            Some(cores) => cores,
+            None => {
+                return Err(Error::msg(format!(
+                    "not enough physical cores on one NUMA node, need {}",
+                    need
+                )))
+            }
+        };
+        Ok(CpuLayout {
+            listener: CpuList::single(cores[0]),
+            scheduler: CpuList::single(cores[1]),
+            tester: CpuList::single(cores[2 + worker_threads as usize]),
+            workers: (0..worker_threads as usize)
+                .map(|i| CpuList::single(cores[2 + i]))
+                .collect(),
+        })
+    }
+
+    /// 检查每个线程都有CPU，并且不同线程的CPU不重叠
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.workers.is_empty() {
+            return Err(Error::msg("no worker cpus"));
+        }
+        if self.listener.is_empty()
+            || self.scheduler.is_empty()
+            || self.tester.is_empty()
+            || self.workers.iter().any(|w| w.is_empty())
+        {
+            return Err(Error::msg("empty cpulist"));
+        }
+        let mut threads = vec![
+            ("listener".to_owned(), &self.listener),
+            ("scheduler".to_owned(), &self.scheduler),
+            ("tester".to_owned(), &self.tester),
+        ];
+        for (i, worker) in self.workers.iter().enumerate() {
+            threads.push((format!("worker{}", i), worker));
+        }
+        for (i, (name, cpus)) in threads.iter().enumerate() {
+            for (other, other_cpus) in &threads[i + 1..] {
+                if cpus.intersects(other_cpus) {
+                    return Err(Error::msg(format!(
+                        "{} cpus {} overlap {} cpus {}",
+                        name, cpus, other, other_cpus
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// 所有用到的CPU，用于hyperwasm的cgroup
+    pub fn all(&self) -> CpuList {
+        self.workers.iter().fold(
+            self.listener.union(&self.scheduler).union(&self.tester),
+            |all, worker| all.union(worker),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_display_round_trip() {
+        for (input, output) in [
+            ("2-5,10,12-13", "2-5,10,12-13"),
+            (" 1 , 3 - 4 ,\n", "1,3-4"),
+            ("7,6,5,9", "5-7,9"),
+            ("0-1,1-2", "0-2"),
+            ("300-301,65535", "300-301,65535"),
+            ("", ""),
+            (" , ", ""),
+        ] {
+            let cpus = CpuList::parse(input).unwrap();
+            assert_eq!(cpus.to_string(), output, "{:?}", input);
+            assert_eq!(CpuList::parse(&cpus.to_string()).unwrap(), cpus);
+        }
+        assert_eq!(CpuList::range(3, 5), CpuList::parse("3-5").unwrap());
+    }
+
+    #[test]
+    fn parse_rejects_invalid_input() {
+        for input in ["5-3", "a", "1-", "-1", "1-2-3", "65536", "1;2"] {
+            assert!(CpuList::parse(input).is_err(), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn contiguous_checks_overflow() {
+        let layout = CpuLayout::contiguous(2, 254).unwrap();
+        assert_eq!(layout.tester, CpuList::single(258));
+        assert_eq!(
+            layout.workers,
+            vec![CpuList::single(256), CpuList::single(257)]
+        );
+        assert!(layout.validate().is_ok());
+        assert!(CpuLayout::contiguous(2, u16::MAX - 3).is_err());
+    }
+
+    #[test]
+    fn validate_rejects_overlap() {
+        let mut layout = CpuLayout::contiguous(2, 0).unwrap();
+        layout.workers[1] = CpuList::parse("3-4").unwrap();
+        assert!(layout.validate().is_err());
+        let mut layout = CpuLayout::contiguous(2, 0).unwrap();
+        layout.listener = CpuList::parse("0-1").unwrap();
+        assert!(layout.validate().is_err());
+        layout.listener = CpuList::single(0);
+        assert!(layout.validate().is_ok());
+    }
+}
@@ -0,0 +1,341 @@
+use crate::{
+    scheduler::get_start,
+    task::{CoStatus, Criticality, SchedulerStatus},
+};
+use anyhow::Error;
+use crossbeam::queue::SegQueue;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// 默认保留7天
+pub const HISTORY_TTL: Duration = Duration::from_secs(7 * 24 * 3600);
+/// 单个文件的默认大小上限，超过后轮转到新文件
+pub const HISTORY_FILE_SIZE: u64 = 64 << 20;
+/// 后台线程写文件的间隔
+const FLUSH_INTERVAL: Duration = Duration::from_millis(500);
+/// 后台线程检查过期文件的间隔
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// 已完成任务历史的配置
+#[derive(Clone, Debug)]
+pub struct HistoryConfig {
+    pub dir: PathBuf,       // JSON Lines文件所在的目录
+    pub ttl: Duration,      // 超过这个时间的记录会被删除
+    pub max_file_size: u64, // 单个文件的大小上限(字节)
+}
+
+impl HistoryConfig {
+    pub fn new(dir: impl Into<PathBuf>) -> HistoryConfig {
+        HistoryConfig {
+            dir: dir.into(),
+            ttl: HISTORY_TTL,
+            max_file_size: HISTORY_FILE_SIZE,
+        }
+    }
+}
+
+/// 一条已完成任务的记录，时间点是unix毫秒，时长是微秒
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct HistoryRecord {
+    pub co_id: u64,
+    pub worker_id: u8,
+    pub module: String,  // 任务的分组，通过Server提交时是wasm模块名
+    pub outcome: String, // COMPLETED, MISSED(完成但超过截止时间), TRAPPED(wasm调用trap) 或 CANCELLED
+    pub criticality: String,
+    pub spawn_time: i64,
+    pub finish_time: i64,
+    pub response_time: u64,
+    pub running_time: u64,
+    pub expected_execution_time: Option<u64>,
+    pub relative_deadline: Option<u64>,
+}
+
+/// Instant对应的unix毫秒，以Runtime启动时记录的时间为基准
+fn unix_millis(instant: Instant) -> i64 {
+    let (start, wall) = get_start();
+    let millis = wall.timestamp_millis();
+    match instant.checked_duration_since(start) {
+        Some(d) => millis + d.as_millis() as i64,
+        None => millis - start.duration_since(instant).as_millis() as i64,
+    }
+}
+
+impl HistoryRecord {
+    pub fn new(co_id: u64, stat: &SchedulerStatus, worker_id: u8, now: Instant) -> HistoryRecord {
+        let spawn_time = stat.get_spawn_time();
+        let response_time = now.saturating_duration_since(spawn_time);
+        let relative_deadline = stat.get_relative_deadline();
+        let outcome = match stat.co_status {
+            CoStatus::CANCELLED => "CANCELLED",
+            _ if stat.trapped => "TRAPPED",
+            _ if relative_deadline.is_some_and(|ddl| response_time > ddl) => "MISSED",
+            _ => "COMPLETED",
+        };
+        HistoryRecord {
+            co_id,
+            worker_id,
+            module: stat.group.clone(),
+            outcome: outcome.to_owned(),
+            criticality: format!("{:?}", stat.criticality),
+            spawn_time: unix_millis(spawn_time),
+            finish_time: unix_millis(now),
+            response_time: response_time.as_micros() as u64,
+            running_time: stat.running_time.as_micros() as u64,
+            expected_execution_time: stat
+                .get_wcet(Criticality::LO)
+                .map(|eet| eet.as_micros() as u64),
+            relative_deadline: relative_deadline.map(|ddl| ddl.as_micros() as u64),
+        }
+    }
+
+    const CSV_HEADER: &'static str = "co_id,worker_id,module,outcome,criticality,spawn_time,finish_time,response_time,running_time,expected_execution_time,relative_deadline";
+
+    fn to_csv(&self) -> String {
+        let opt = |v: Option<u64>| v.map(|v| v.to_string()).unwrap_or_default();
+        format!(
+            "{},{},{},{},{},{},{},{},{},{},{}",
+            self.co_id,
+            self.worker_id,
+            csv_field(&self.module),
+            self.outcome,
+            self.criticality,
+            self.spawn_time,
+            self.finish_time,
+            self.response_time,
+            self.running_time,
+            opt(self.expected_execution_time),
+            opt(self.relative_deadline),
+        )
+    }
+}
+
+/// 含有逗号、引号或换行的字段用引号括起来
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+/// 导出CSV，第一行是表头
+pub fn to_csv(records: &[HistoryRecord]) -> String {
+    let mut s = String::from(HistoryRecord::CSV_HEADER);
+    s.push('\n');
+    for record in records {
+        s.push_str(&record.to_csv());
+        s.push('\n');
+    }
+    s
+}
+
+/// 历史查询条件，没有设置的条件不过滤
+#[derive(Clone, Debug, Default)]
+pub struct HistoryQuery {
+    pub from: Option<i64>, // 完成时间的下界(unix毫秒，包含)
+    pub to: Option<i64>,   // 完成时间的上界(unix毫秒，不包含)
+    pub module: Option<String>,
+    pub outcome: Option<String>,
+    pub worker_id: Option<u8>,
+    pub limit: Option<usize>, // 最多返回的记录数，从最早的开始
+}
+
+impl HistoryQuery {
+    fn matches(&self, record: &HistoryRecord) -> bool {
+        self.from.iter().all(|&from| record.finish_time >= from)
+            && self.to.iter().all(|&to| record.finish_time < to)
+            && self.module.iter().all(|m| &record.module == m)
+            && self
+                .outcome
+                .iter()
+                .all(|o| record.outcome.eq_ignore_ascii_case(o))
+            && self.worker_id.iter().all(|&w| record.worker_id == w)
+    }
+}
+
+/// 当前写入的文件
+#[derive(Debug)]
+struct Segment {
+    path: PathBuf,
+    file: File,
+    size: u64,
+}
+
+/**
+ * 已完成任务的持久化历史
+ * worker只把记录放进无锁队列，后台线程批量追加到JSON Lines文件，
+ * 文件超过大小上限后轮转，最后修改时间超过TTL的文件被删除
+ */
+#[derive(Debug)]
+pub struct History {
+    config: HistoryConfig,
+    pending: SegQueue<HistoryRecord>,
+    segment: Mutex<Option<Segment>>,
+}
+
+impl History {
+    /// 创建目录并启动后台写入线程，History被drop后线程退出
+    pub fn open(config: HistoryConfig) -> Result<Arc<History>, Error> {
+        if config.ttl.is_zero() {
+            return Err(Error::msg("history ttl must be positive"));
+        }
+        if config.max_file_size == 0 {
+            return Err(Error::msg("history max_file_size must be positive"));
+        }
+        fs::create_dir_all(&config.dir)?;
+        let history = Arc::new(History {
+            config,
+            pending: SegQueue::new(),
+            segment: Mutex::new(None),
+        });
+        history.prune();
+        let weak = Arc::downgrade(&history);
+        std::thread::Builder::new()
+            .name("history".to_owned())
+            .spawn(move || Self::run(weak))?;
+        Ok(history)
+    }
+
+    fn run(history: Weak<History>) {
+        let mut last_prune = Instant::now();
+        loop {
+            std::thread::sleep(FLUSH_INTERVAL);
+            let history = match history.upgrade() {
+                Some(history) => history,
+                None => return,
+            };
+            if let Err(err) = history.flush() {
+                tracing::warn!("history flush failed: {}", err);
+            }
+            if last_prune.elapsed() >= PRUNE_INTERVAL {
+                history.prune();
+                last_prune = Instant::now();
+            }
+        }
+    }
+
+    pub fn get_config(&self) -> &HistoryConfig {
+        &self.config
+    }
+
+    /// 记录一个已完成的任务，不做IO
+    pub fn record(&self, record: HistoryRecord) {
+        self.pending.push(record);
+    }
+
+    /// 把队列中的记录写入文件
+    pub fn flush(&self) -> Result<(), Error> {
+        let mut segment = self.segment.lock().unwrap();
+        while let Some(record) = self.pending.pop() {
+            let mut line = serde_json::to_vec(&record)?;
+            line.push(b'\n');
+            let len = line.len() as u64;
+            // 单条记录超过上限时也写入，之后再轮转
+            let full = match segment.as_ref() {
+                Some(s) => s.size > 0 && s.size + len > self.config.max_file_size,
+                None => true,
+            };
+            if full {
+                *segment = Some(self.new_segment()?);
+            }
+            let s = segment.as_mut().unwrap();
+            s.file.write_all(&line)?;
+            s.size += len;
+        }
+        Ok(())
+    }
+
+    /// 新文件按创建时间命名，文件名的字典序就是时间顺序
+    fn new_segment(&self) -> Result<Segment, Error> {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+        let path = self.config.dir.join(format!("history-{:024}.jsonl", nanos));
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(Segment {
+            path,
+            file,
+            size: 0,
+        })
+    }
+
+    /// 按时间顺序排列的历史文件
+    fn segments(&self) -> Result<Vec<PathBuf>, Error> {
+        let mut paths: Vec<PathBuf> = fs::read_dir(&self.config.dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| is_segment(path))
+            .collect();
+        paths.sort();
+        Ok(paths)
+    }
+
+    /**
+     * 删除最后修改时间超过TTL的文件
+     * 正在写入的文件过期时不再使用，下一次写入时新建文件
+     */
+    pub fn prune(&self) {
+        let mut segment = self.segment.lock().unwrap();
+        let segments = match self.segments() {
+            Ok(segments) => segments,
+            Err(_) => return,
+        };
+        for path in segments {
+            let expired = fs::metadata(&path)
+                .and_then(|meta| meta.modified())
+                .ok()
+                .and_then(|modified| modified.elapsed().ok())
+                .is_some_and(|age| age > self.config.ttl);
+            if expired {
+                if segment.as_ref().is_some_and(|s| s.path == path) {
+                    *segment = None;
+                }
+                let _ = fs::remove_file(&path);
+            }
+        }
+    }
+
+    /**
+     * 查询历史记录，按完成时间的先后返回
+     * 先写入还在队列中的记录，超过TTL但还没被删除的记录不返回
+     * 按写入顺序逐行读取文件，找到limit条记录后停止
+     */
+    pub fn query(&self, query: &HistoryQuery) -> Result<Vec<HistoryRecord>, Error> {
+        self.flush()?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let oldest = now.saturating_sub(self.config.ttl).as_millis() as i64;
+        let limit = query.limit.unwrap_or(usize::MAX);
+        let mut records = Vec::new();
+        'segments: for path in self.segments()? {
+            let file = match File::open(&path) {
+                Ok(file) => file,
+                Err(_) => continue,
+            };
+            for line in BufReader::new(file).lines() {
+                let line = line?;
+                // 进程异常退出时最后一行可能不完整
+                let record: HistoryRecord = match serde_json::from_str(&line) {
+                    Ok(record) => record,
+                    Err(_) => continue,
+                };
+                if record.finish_time >= oldest && query.matches(&record) {
+                    if records.len() >= limit {
+                        break 'segments;
+                    }
+                    records.push(record);
+                }
+            }
+        }
+        records.sort_by_key(|record| record.finish_time);
+        Ok(records)
+    }
+}
+
+fn is_segment(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with("history-") && name.ends_with(".jsonl"))
+}
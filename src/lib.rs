@@ -1,8 +1,16 @@
+pub mod analysis;
 pub mod axum;
 pub mod cgroupv2;
+pub mod clock;
+pub mod cpulist;
+pub mod history;
+pub mod metrics;
+pub mod profile;
 pub mod result;
 pub mod runtime;
 pub mod runwasm;
 mod scheduler;
+pub mod sim;
 pub mod task;
+pub mod workflow;
 use task::stack::StackSize;
@@ -0,0 +1,306 @@
+use crate::{runtime::AdmissionControl, scheduler::MAX_WORKERS};
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+/// 准入控制的所有结果，按AdmissionControl的值排列
+const ADMISSIONS: [AdmissionControl; 4] = [
+    AdmissionControl::NOTREALTIME,
+    AdmissionControl::PREEMPTIVE,
+    AdmissionControl::SCHEDULABLE,
+    AdmissionControl::UNSCHEDULABLE,
+];
+
+/// 纳秒
+fn nanos(d: Duration) -> u64 {
+    d.as_nanos().min(u64::MAX as u128) as u64
+}
+
+/// 一个worker的计数，只使用原子操作，可以在信号处理函数中更新
+#[derive(Default)]
+struct WorkerMetrics {
+    started: AtomicU64,   // 登记时间，距Metrics创建的纳秒数加1，0表示没有登记过
+    busy: AtomicU64,      // 运行任务的时间(纳秒)
+    busy_base: AtomicU64, // 登记时的busy，用于计算登记以来的利用率
+    context_switches: AtomicU64,
+    preemptions: AtomicU64,
+    completed: AtomicU64,
+    cancelled: AtomicU64,
+    deadline_misses: AtomicU64,
+    overruns: AtomicU64, // 运行时间超过LO级别的WCET
+}
+
+/// 取出worker的一个计数器
+type Counter = fn(&WorkerMetrics) -> &AtomicU64;
+
+/**
+ * Runtime的指标
+ * 计数器由worker、准入控制和Server累加，队列长度等瞬时值在导出时由调度器读取
+ */
+pub struct Metrics {
+    created: Instant,
+    workers: Vec<WorkerMetrics>,
+    admissions: [AtomicU64; ADMISSIONS.len()],
+    requests: AtomicU64,   // Server准入成功的请求数量
+    warm_start: AtomicU64, // 累计热启动时延(纳秒)
+    schedule: AtomicU64,   // 累计调度时延(纳秒)
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics {
+            created: Instant::now(),
+            workers: (0..MAX_WORKERS).map(|_| WorkerMetrics::default()).collect(),
+            admissions: Default::default(),
+            requests: AtomicU64::new(0),
+            warm_start: AtomicU64::new(0),
+            schedule: AtomicU64::new(0),
+        }
+    }
+
+    fn worker(&self, worker_id: u8) -> Option<&WorkerMetrics> {
+        self.workers.get(worker_id as usize)
+    }
+
+    /// worker登记后开始计算利用率，退役后重新加入时重新计算
+    pub fn worker_started(&self, worker_id: u8) {
+        if let Some(w) = self.worker(worker_id) {
+            let now = nanos(self.created.elapsed()) + 1;
+            w.busy_base
+                .store(w.busy.load(Ordering::Relaxed), Ordering::Relaxed);
+            w.started.store(now, Ordering::Relaxed);
+        }
+    }
+
+    /// 切换到一个任务运行了busy时间
+    pub fn context_switch(&self, worker_id: u8, busy: Duration) {
+        if let Some(w) = self.worker(worker_id) {
+            w.context_switches.fetch_add(1, Ordering::Relaxed);
+            w.busy.fetch_add(nanos(busy), Ordering::Relaxed);
+        }
+    }
+
+    pub fn preemption(&self, worker_id: u8) {
+        if let Some(w) = self.worker(worker_id) {
+            w.preemptions.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn admission(&self, ac: AdmissionControl) {
+        self.admissions[ac as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 任务结束或被取消
+    pub fn finished(&self, worker_id: u8, cancelled: bool, missed: bool, overrun: bool) {
+        if let Some(w) = self.worker(worker_id) {
+            if cancelled {
+                w.cancelled.fetch_add(1, Ordering::Relaxed);
+            } else {
+                w.completed.fetch_add(1, Ordering::Relaxed);
+            }
+            if missed {
+                w.deadline_misses.fetch_add(1, Ordering::Relaxed);
+            }
+            if overrun {
+                w.overruns.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Server准入一个请求，schedule是调度时延，warm_start是从收到请求开始的热启动时延
+    pub fn request(&self, schedule: Duration, warm_start: Duration) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.schedule.fetch_add(nanos(schedule), Ordering::Relaxed);
+        self.warm_start
+            .fetch_add(nanos(warm_start), Ordering::Relaxed);
+    }
+
+    /// Server准入的请求数量
+    pub fn get_requests(&self) -> u64 {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    /// 平均调度时延和平均热启动时延，没有请求时返回None
+    pub fn get_request_latency(&self) -> Option<(Duration, Duration)> {
+        let cnt = self.get_requests();
+        if cnt == 0 {
+            return None;
+        }
+        Some((
+            Duration::from_nanos(self.schedule.load(Ordering::Relaxed) / cnt),
+            Duration::from_nanos(self.warm_start.load(Ordering::Relaxed) / cnt),
+        ))
+    }
+
+    /// 准入结果的计数
+    pub fn get_admissions(&self, ac: AdmissionControl) -> u64 {
+        self.admissions[ac as usize].load(Ordering::Relaxed)
+    }
+
+    /// 登记过的worker
+    fn started(&self) -> impl Iterator<Item = (u8, &WorkerMetrics)> {
+        self.workers
+            .iter()
+            .enumerate()
+            .filter(|(_, w)| w.started.load(Ordering::Relaxed) != 0)
+            .map(|(i, w)| (i as u8, w))
+    }
+
+    /**
+     * 按Prometheus文本格式导出计数器
+     * active是正在运行的worker，只有它们导出利用率
+     */
+    pub fn encode(&self, out: &mut Encoder, active: &[u8]) {
+        out.family(
+            "admissions_total",
+            "counter",
+            "Admission control results by outcome",
+        );
+        for ac in ADMISSIONS {
+            out.sample(
+                "admissions_total",
+                &[("outcome", &format!("{:?}", ac))],
+                self.get_admissions(ac) as f64,
+            );
+        }
+
+        let counters: [(&str, &str, Counter); 6] = [
+            (
+                "context_switches_total",
+                "Tasks resumed on the worker",
+                |w| &w.context_switches,
+            ),
+            ("preemptions_total", "Tasks preempted on the worker", |w| {
+                &w.preemptions
+            }),
+            ("tasks_completed_total", "Tasks completed", |w| &w.completed),
+            ("tasks_cancelled_total", "Tasks cancelled", |w| &w.cancelled),
+            (
+                "deadline_misses_total",
+                "Tasks finished after their deadline",
+                |w| &w.deadline_misses,
+            ),
+            (
+                "overruns_total",
+                "Tasks that ran longer than their LO execution time",
+                |w| &w.overruns,
+            ),
+        ];
+        for (name, help, counter) in counters {
+            out.family(name, "counter", help);
+            for (id, w) in self.started() {
+                let value = counter(w).load(Ordering::Relaxed);
+                out.sample(name, &[("worker", &id.to_string())], value as f64);
+            }
+        }
+
+        out.family(
+            "worker_busy_seconds_total",
+            "counter",
+            "Time the worker spent running tasks",
+        );
+        for (id, w) in self.started() {
+            let busy = w.busy.load(Ordering::Relaxed) as f64 / 1e9;
+            out.sample(
+                "worker_busy_seconds_total",
+                &[("worker", &id.to_string())],
+                busy,
+            );
+        }
+        out.family(
+            "worker_utilization",
+            "gauge",
+            "Busy time divided by the time since the worker started",
+        );
+        let now = nanos(self.created.elapsed()) + 1;
+        for (id, w) in self.started().filter(|(id, _)| active.contains(id)) {
+            let elapsed = now.saturating_sub(w.started.load(Ordering::Relaxed));
+            let busy = w
+                .busy
+                .load(Ordering::Relaxed)
+                .saturating_sub(w.busy_base.load(Ordering::Relaxed));
+            let utilization = if elapsed == 0 {
+                0.0
+            } else {
+                (busy as f64 / elapsed as f64).min(1.0)
+            };
+            out.sample(
+                "worker_utilization",
+                &[("worker", &id.to_string())],
+                utilization,
+            );
+        }
+
+        out.family(
+            "requests_total",
+            "counter",
+            "Requests admitted by the server",
+        );
+        out.sample("requests_total", &[], self.get_requests() as f64);
+        out.family(
+            "request_schedule_seconds_total",
+            "counter",
+            "Accumulated scheduling latency of admitted requests",
+        );
+        let schedule = self.schedule.load(Ordering::Relaxed) as f64 / 1e9;
+        out.sample("request_schedule_seconds_total", &[], schedule);
+        out.family(
+            "request_warm_start_seconds_total",
+            "counter",
+            "Accumulated warm start latency of admitted requests",
+        );
+        let warm_start = self.warm_start.load(Ordering::Relaxed) as f64 / 1e9;
+        out.sample("request_warm_start_seconds_total", &[], warm_start);
+    }
+}
+
+/**
+ * Prometheus文本格式
+ * 所有指标名加上hyperwasm_前缀，每个样本都带runtime标签
+ */
+pub struct Encoder {
+    runtime: String,
+    out: String,
+}
+
+impl Encoder {
+    pub fn new(runtime: &str) -> Encoder {
+        Encoder {
+            runtime: escape(runtime),
+            out: String::new(),
+        }
+    }
+
+    /// 指标的HELP和TYPE
+    pub fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.out, "# HELP hyperwasm_{} {}", name, help);
+        let _ = writeln!(self.out, "# TYPE hyperwasm_{} {}", name, kind);
+    }
+
+    pub fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        let _ = write!(self.out, "hyperwasm_{}{{runtime=\"{}\"", name, self.runtime);
+        for (key, value) in labels {
+            let _ = write!(self.out, ",{}=\"{}\"", key, escape(value));
+        }
+        let _ = writeln!(self.out, "}} {}", value);
+    }
+
+    pub fn finish(self) -> String {
+        self.out
+    }
+}
+
+/// 标签值中的反斜杠、引号和换行需要转义
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,204 @@
+use crate::cgroupv2::Controllerv2;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// 默认的剖析次数
+pub const DEFAULT_ITERATIONS: usize = 100;
+/// 一次剖析的次数上限
+pub const MAX_ITERATIONS: usize = 100000;
+/// 干扰线程数量的上限
+pub const MAX_INTERFERENCE: u32 = 16;
+/// WCET界的单次超出概率
+pub const EXCEEDANCE: f64 = 1e-9;
+/// 块最大值法每块的样本数
+const BLOCK: usize = 10;
+/// 块太少时拟合不可靠，直接使用观测到的最大值
+const MIN_BLOCKS: usize = 5;
+/// 干扰线程反复遍历的缓冲区大小，超过末级缓存
+const INTERFERENCE_BUFFER: usize = 32 << 20;
+/// Euler-Mascheroni常数
+const EULER: f64 = 0.577_215_664_901_532_9;
+
+/// 剖析结果按模块、导出函数和参数类别区分
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ProfileKey {
+    pub module: String,
+    pub export: String,
+    pub param_class: String,
+}
+
+/// 默认的参数类别，参数类型和参数值都相同的调用属于同一类
+pub fn param_class(param_type: &str, params: &[String]) -> String {
+    format!("{}:{}", param_type.to_ascii_lowercase(), params.join(","))
+}
+
+/// 一次剖析的统计结果，时间单位微秒
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProfileStats {
+    #[serde(flatten)]
+    pub key: ProfileKey,
+    pub iterations: usize,
+    pub interference: u32, // 干扰线程数量
+    pub min_us: u64,
+    pub mean_us: u64,
+    pub p99_us: u64,
+    pub max_us: u64,
+    pub wcet_us: u64,     // 极值理论估计的WCET界，不小于max_us
+    pub profiled_at: i64, // unix毫秒
+}
+
+impl ProfileStats {
+    /// 按运行顺序的样本计算统计结果，没有样本时返回None
+    pub fn from_samples(
+        key: ProfileKey,
+        samples: &[Duration],
+        interference: u32,
+    ) -> Option<ProfileStats> {
+        let us: Vec<f64> = samples
+            .iter()
+            .map(|d| d.as_nanos() as f64 / 1000.0)
+            .collect();
+        let mut sorted = us.clone();
+        sorted.sort_by(f64::total_cmp);
+        let (min, max) = (*sorted.first()?, *sorted.last()?);
+        let mean = us.iter().sum::<f64>() / us.len() as f64;
+        // 最近秩法
+        let rank = (sorted.len() as f64 * 0.99).ceil() as usize;
+        let p99 = sorted[rank.clamp(1, sorted.len()) - 1];
+        let wcet = gumbel_bound(&us).unwrap_or(max).max(max);
+        Some(ProfileStats {
+            key,
+            iterations: us.len(),
+            interference,
+            min_us: min.floor() as u64,
+            mean_us: mean.round() as u64,
+            p99_us: p99.ceil() as u64,
+            max_us: max.ceil() as u64,
+            wcet_us: wcet.ceil() as u64,
+            profiled_at: chrono::Local::now().timestamp_millis(),
+        })
+    }
+
+    /// 作为预期执行时间使用的WCET界
+    pub fn get_wcet(&self) -> Duration {
+        Duration::from_micros(self.wcet_us)
+    }
+}
+
+/**
+ * 块最大值法
+ * 每BLOCK个样本取最大值，用矩估计拟合Gumbel分布，
+ * 返回单次运行超出概率为EXCEEDANCE的分位数，块太少或者没有波动时返回None
+ */
+fn gumbel_bound(samples: &[f64]) -> Option<f64> {
+    let maxima: Vec<f64> = samples
+        .chunks_exact(BLOCK)
+        .map(|block| block.iter().copied().fold(f64::MIN, f64::max))
+        .collect();
+    if maxima.len() < MIN_BLOCKS {
+        return None;
+    }
+    let n = maxima.len() as f64;
+    let mean = maxima.iter().sum::<f64>() / n;
+    let var = maxima.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    if var <= 0.0 {
+        return None;
+    }
+    let beta = var.sqrt() * 6f64.sqrt() / std::f64::consts::PI;
+    let mu = mean - EULER * beta;
+    // 一块中至少一次超出的概率
+    let p = -(BLOCK as f64 * (-EXCEEDANCE).ln_1p()).exp_m1();
+    Some(mu - beta * (-(-p).ln_1p()).ln())
+}
+
+/// 一个Runtime的剖析结果
+#[derive(Default)]
+pub struct Profiles {
+    profiles: RwLock<HashMap<ProfileKey, ProfileStats>>,
+}
+
+impl Profiles {
+    pub fn new() -> Profiles {
+        Self::default()
+    }
+
+    /// 保存剖析结果，覆盖同一个键之前的结果
+    pub fn record(&self, stats: ProfileStats) {
+        if let Ok(mut profiles) = self.profiles.write() {
+            profiles.insert(stats.key.clone(), stats);
+        }
+    }
+
+    pub fn get(&self, key: &ProfileKey) -> Option<ProfileStats> {
+        self.profiles.read().ok()?.get(key).cloned()
+    }
+
+    /// 所有剖析结果，按键排序
+    pub fn list(&self) -> Vec<ProfileStats> {
+        let mut profiles: Vec<ProfileStats> = self
+            .profiles
+            .read()
+            .map(|profiles| profiles.values().cloned().collect())
+            .unwrap_or_default();
+        profiles.sort_by(|a, b| a.key.cmp(&b.key));
+        profiles
+    }
+}
+
+/**
+ * 合成干扰
+ * 每个线程反复遍历超过末级缓存的缓冲区，污染缓存和内存带宽，drop时停止
+ */
+pub struct Interference {
+    stop: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl Interference {
+    /**
+     * cgroup不为None时干扰线程加入这个cgroup，运行在与测试线程共享末级缓存的其他CPU上，
+     * 否则继承调用线程的cgroup
+     */
+    pub fn start(threads: u32, cgroup: Option<Controllerv2>) -> Interference {
+        let stop = Arc::new(AtomicBool::new(false));
+        let threads = (0..threads)
+            .filter_map(|i| {
+                let stop = stop.clone();
+                let cgroup = cgroup.clone();
+                thread::Builder::new()
+                    .name(format!("interference{}", i))
+                    .spawn(move || {
+                        if let Some(cgroup) = cgroup {
+                            cgroup.set_cgroup_threads(nix::unistd::gettid());
+                        }
+                        let mut buf = vec![0u8; INTERFERENCE_BUFFER];
+                        while !stop.load(Ordering::Relaxed) {
+                            for i in (0..buf.len()).step_by(64) {
+                                buf[i] = buf[i].wrapping_add(1);
+                            }
+                            std::hint::black_box(&buf);
+                        }
+                    })
+                    .ok()
+            })
+            .collect();
+        Interference { stop, threads }
+    }
+}
+
+impl Drop for Interference {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        for t in self.threads.drain(..) {
+            let _ = t.join();
+        }
+    }
+}
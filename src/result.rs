@@ -1,3 +1,4 @@
+use crate::runtime::CounterOffer;
 use std::{
     future::Future,
     pin::Pin,
@@ -12,6 +13,7 @@ pub struct FuncResult {
     completed: Mutex<bool>,
     result: Mutex<Option<String>>,
     waker: Mutex<Option<Waker>>,
+    counter_offer: Mutex<Option<CounterOffer>>, // 不可调度时的反报价
 }
 
 unsafe impl Send for FuncResult {}
@@ -23,6 +25,7 @@ impl FuncResult {
             completed: false.into(),
             result: Mutex::new(None),
             waker: Mutex::new(None),
+            counter_offer: Mutex::new(None),
         }
     }
 
@@ -42,6 +45,16 @@ impl FuncResult {
             result.replace(str.to_owned());
         }
     }
+
+    pub fn set_counter_offer(&self, offer: Option<CounterOffer>) {
+        if let Ok(mut counter_offer) = self.counter_offer.lock() {
+            *counter_offer = offer;
+        }
+    }
+
+    pub fn get_counter_offer(&self) -> Option<CounterOffer> {
+        self.counter_offer.lock().ok().and_then(|offer| *offer)
+    }
 }
 
 impl Future for ResultFuture {
@@ -1,24 +1,46 @@
+pub use crate::scheduler::epoch::EpochConfig;
+pub use crate::scheduler::estimator::{ExecutionEstimate, ExecutionEstimator, FuncKey};
+pub use crate::scheduler::inbox::InboxLatency;
+pub use crate::scheduler::watchdog::{WatchdogAction, WatchdogConfig, WorkerHealth};
 use crate::{
-    scheduler::Scheduler,
-    task::{Coroutine, SchedulerStatus},
+    analysis,
+    cgroupv2::Controllerv2,
+    clock,
+    cpulist::{CpuLayout, CpuList},
+    history::{History, HistoryConfig, HistoryQuery, HistoryRecord},
+    metrics::Metrics,
+    profile::{ProfileKey, ProfileStats, Profiles},
+    scheduler::{
+        claim_name,
+        trace::{TraceKind, SCHEDULER_RING},
+        Scheduler, SchedulerConfig, CG_ROOT, COMPLETED_CAPACITY, MAX_WORKERS, PREEMPTY, SIG,
+        WORKER_CAPACITY,
+    },
+    task::{Coroutine, Criticality, SchedulerStatus},
     StackSize,
 };
 use anyhow::Error;
-use once_cell::sync::Lazy;
+use nix::sys::signal::Signal;
 use std::{
-    collections::{BTreeMap, BinaryHeap, HashMap},
+    collections::{BTreeMap, HashMap},
     panic::{self, AssertUnwindSafe},
-    sync::{Arc, Mutex},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
     thread::JoinHandle,
     time::{Duration, Instant},
 };
 
-static _AVA_TIME: Lazy<Mutex<HashMap<u64, f64>>> = Lazy::new(|| Mutex::new(HashMap::new()));
-
 /// Runtime就是Runtime
 pub struct Runtime {
     scheduler: Arc<Scheduler>,
-    threads: Vec<JoinHandle<()>>,
+    threads: Mutex<BTreeMap<u8, JoinHandle<()>>>,
+    timer_exp: u64,
+    next_worker: AtomicU64, // 轮流选择目标工作核心
+    admission: Mutex<()>,   // 准入控制和预留容量之间不能有其他准入
+    profiles: Profiles,     // 剖析得到的WCET界，只在这个Runtime中使用
 }
 
 impl Default for Runtime {
@@ -28,49 +50,602 @@ impl Default for Runtime {
     fn default() -> Self {
         let scheduler = Scheduler::new(1, 0);
         let threads = Scheduler::start(&scheduler, 0);
-        Self { scheduler, threads }
+        Runtime::with_scheduler(scheduler, threads, 0)
     }
 }
 
 impl Runtime {
     /**
      * 需要指定线程数量和定时器周期（微秒）
+     * 其他配置见RuntimeBuilder
      */
     pub fn new(
         worker_threads: Option<u8>,
-        start_cpu: Option<u8>,
+        start_cpu: Option<u16>,
         timer_exp: Option<u64>,
     ) -> Runtime {
         let scheduler = Scheduler::new(
             worker_threads.unwrap_or_default(),
             start_cpu.unwrap_or_default(),
         );
-        let threads = Scheduler::start(&scheduler, timer_exp.unwrap_or_default());
-        Runtime { scheduler, threads }
+        let timer_exp = timer_exp.unwrap_or_default();
+        let threads = Scheduler::start(&scheduler, timer_exp);
+        Runtime::with_scheduler(scheduler, threads, timer_exp)
+    }
+
+    /**
+     * 模拟运行使用的Runtime
+     * 没有worker线程、cgroup和信号，任务状态由crate::sim按虚拟时钟更新
+     */
+    pub fn simulated(worker_threads: u8) -> Runtime {
+        let scheduler = Scheduler::new(worker_threads, 0);
+        scheduler.start_simulated();
+        Runtime::with_scheduler(scheduler, BTreeMap::new(), 0)
+    }
+
+    /**
+     * 按CpuLayout创建，每个worker可以使用任意的CPU集合
+     */
+    pub fn with_layout(layout: CpuLayout, timer_exp: Option<u64>) -> Result<Runtime, Error> {
+        layout.validate()?;
+        let scheduler = Scheduler::with_layout(layout);
+        let timer_exp = timer_exp.unwrap_or_default();
+        let threads = Scheduler::start(&scheduler, timer_exp);
+        Ok(Runtime::with_scheduler(scheduler, threads, timer_exp))
+    }
+
+    fn with_scheduler(
+        scheduler: Arc<Scheduler>,
+        threads: BTreeMap<u8, JoinHandle<()>>,
+        timer_exp: u64,
+    ) -> Runtime {
+        Runtime {
+            scheduler,
+            threads: Mutex::new(threads),
+            timer_exp,
+            next_worker: AtomicU64::new(0),
+            admission: Mutex::new(()),
+            profiles: Profiles::new(),
+        }
+    }
+
+    /**
+     * 运行时增加一个工作核心
+     * cpu为None时选择一个没有被使用的物理核心
+     * 返回新worker的id
+     */
+    pub fn add_worker(&self, cpu: Option<CpuList>) -> Result<u8, Error> {
+        if cpu.as_ref().is_some_and(|cpu| cpu.is_empty()) {
+            return Err(Error::msg("empty cpulist"));
+        }
+        let (worker_id, cpu) = self.scheduler.alloc_worker(cpu)?;
+        self.scheduler.extend_cpuset(&cpu);
+        tracing::info!("add worker {} on cpus {}", worker_id, cpu);
+        let t = self.scheduler.spawn_worker(worker_id, cpu, self.timer_exp);
+        if let Ok(threads) = self.threads.lock().as_mut() {
+            threads.insert(worker_id, t);
+        }
+        Ok(worker_id)
+    }
+
+    /**
+     * 运行时退役一个工作核心，阻塞直到worker退出
+     * 退役中的worker不再接收新任务，MIGRATE模式下还没开始运行的任务会重新准入到其他worker，
+     * 重新准入失败的任务会被取消
+     */
+    pub fn retire_worker(&self, worker_id: u8, mode: RetireMode) -> Result<(), Error> {
+        let available = self.scheduler.get_available_workers();
+        if !available.contains(&worker_id) {
+            return Err(Error::msg("Invalid_worker_id"));
+        }
+        if available.len() == 1 {
+            return Err(Error::msg("cannot retire the last worker"));
+        }
+        let handle = match self.threads.lock().as_mut() {
+            Ok(threads) => threads.remove(&worker_id),
+            Err(_) => None,
+        };
+        let handle = match handle {
+            Some(handle) => handle,
+            None => return Err(Error::msg("Invalid_worker_id")),
+        };
+        self.scheduler.set_retiring(worker_id, mode);
+        while !handle.is_finished() {
+            self.readmit_migrated(worker_id);
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        let _ = handle.join();
+        // 退出后可能还有迟到的任务
+        while let Some(co) = self.scheduler.pop_realtime(worker_id) {
+            self.scheduler.migrate(co);
+        }
+        if let Some(co) = self.scheduler.get_slots(worker_id) {
+            self.scheduler.migrate(co);
+        }
+        self.readmit_migrated(worker_id);
+        self.scheduler.delete_worker_cg(worker_id);
+        tracing::info!("worker {} retired", worker_id);
+        Ok(())
+    }
+
+    /**
+     * 关闭Runtime
+     * 不再准入新任务，通知所有worker按mode退出，最多等待timeout，
+     * 然后取消剩下的任务并删除hyperwasm的cgroup
+     * 超时时改为IMMEDIATE模式，返回没有退出的worker
+     */
+    pub fn shutdown(&self, mode: ShutdownMode, timeout: Duration) -> Result<(), Error> {
+        let threads = match self.threads.lock().as_mut() {
+            Ok(threads) => std::mem::take(&mut **threads),
+            Err(_) => BTreeMap::new(),
+        };
+        {
+            // 等待正在进行的准入完成
+            let _guard = self.admission.lock().unwrap();
+            self.scheduler.set_shutdown(mode);
+        }
+        tracing::info!("shutdown {:?}", mode);
+        let has_threads = !threads.is_empty();
+        let deadline = Instant::now() + timeout;
+        let mut running = Vec::new();
+        for (worker_id, handle) in threads {
+            while !handle.is_finished() && Instant::now() < deadline {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            if handle.is_finished() {
+                let _ = handle.join();
+            } else {
+                running.push(worker_id);
+            }
+        }
+        if !running.is_empty() {
+            tracing::warn!("workers {:?} did not exit in {:?}", running, timeout);
+            self.scheduler.set_shutdown(ShutdownMode::IMMEDIATE);
+        }
+        self.scheduler.cancel_queued();
+        if let Some(history) = self.scheduler.get_history() {
+            if let Err(err) = history.flush() {
+                tracing::warn!("history flush failed: {}", err);
+            }
+        }
+        if has_threads {
+            self.scheduler.delete_cg();
+        }
+        if running.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::msg(format!("workers {:?} did not exit", running)))
+        }
+    }
+
+    /**
+     * 重新准入退役worker交还的任务
+     */
+    fn readmit_migrated(&self, from: u8) {
+        while let Some(mut co) = self.scheduler.pop_migrated() {
+            let id = co.get_co_id();
+            self.scheduler.delete_status(id, from);
+            if !co.is_realtime() {
+                let _ = self.scheduler.push(co, false, from);
+                continue;
+            }
+            let stat = co.get_schedulestatus();
+            let _guard = self.admission.lock().unwrap();
+            let res = match self.select_worker(&stat) {
+                Some(worker_id) => self.is_schedulable(&stat, worker_id),
+                None => SchedulabilityResult {
+                    ac: AdmissionControl::UNSCHEDULABLE,
+                    worker_id: None,
+                    costatus: None,
+                    group: None,
+                    func: None,
+                    counter_offer: None,
+                    reserved: None,
+                },
+            };
+            self.trace_admission(id, &res);
+            match res.worker_id {
+                Some(worker_id) if res.ac != AdmissionControl::UNSCHEDULABLE => {
+                    let _ = self.dispatch(co, res.ac, worker_id);
+                }
+                _ => {
+                    tracing::warn!("migrated task {} is unschedulable", id);
+                    co.cancel();
+                    self.scheduler
+                        .update_completed_status(id, co.get_schedulestatus(), from);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn get_scheduler(&self) -> &Arc<Scheduler> {
+        &self.scheduler
+    }
+
+    /**
+     * 获取正在运行的工作核心及其CPU
+     */
+    pub fn get_workers(&self) -> BTreeMap<u8, CpuList> {
+        self.scheduler.get_workers()
     }
 
     /**
      * 准入控制的结果
+     * 与reserve相同，结果没有交给micro_process就drop或者micro_process失败时释放预留的容量
      */
+    #[deprecated(note = "use Runtime::reserve and Reservation::commit")]
     pub fn admission_control_result(
         &self,
         expected_execution_time: Option<Duration>,
         relative_deadline: Option<Duration>,
     ) -> SchedulabilityResult {
+        self.legacy_result(self.reserve(
+            expected_execution_time,
+            relative_deadline,
+            Criticality::LO,
+            None,
+        ))
+    }
+
+    /**
+     * 带关键级别的准入控制
+     * expected_execution_time是LO级别的WCET，wcet_hi是HI级别的WCET
+     * 与reserve相同，结果没有交给micro_process就drop或者micro_process失败时释放预留的容量
+     */
+    #[deprecated(note = "use Runtime::reserve and Reservation::commit")]
+    pub fn admission_control_result_with_criticality(
+        &self,
+        expected_execution_time: Option<Duration>,
+        relative_deadline: Option<Duration>,
+        criticality: Criticality,
+        wcet_hi: Option<Duration>,
+    ) -> SchedulabilityResult {
+        self.legacy_result(self.reserve(
+            expected_execution_time,
+            relative_deadline,
+            criticality,
+            wcet_hi,
+        ))
+    }
+
+    /// 旧接口的结果，预留的容量随结果一起释放
+    fn legacy_result(&self, reservation: Reservation) -> SchedulabilityResult {
+        let mut res = reservation.into_result();
+        res.reserved = Some(Reserved {
+            scheduler: self.scheduler.clone(),
+            task: Reservation::reserved(&res),
+        });
+        res
+    }
+
+    /**
+     * 准入控制，worker_id为None时轮流选择目标工作核心
+     */
+    fn admission(
+        &self,
+        expected_execution_time: Option<Duration>,
+        relative_deadline: Option<Duration>,
+        criticality: Criticality,
+        wcet_hi: Option<Duration>,
+        worker_id: Option<u8>,
+    ) -> SchedulabilityResult {
+        // 关闭后不再准入任何任务
+        if self.scheduler.is_shutdown() {
+            return SchedulabilityResult {
+                ac: AdmissionControl::UNSCHEDULABLE,
+                worker_id: None,
+                costatus: None,
+                group: None,
+                func: None,
+                counter_offer: None,
+                reserved: None,
+            };
+        }
         // 如果不是实时任务那就随便调度吧
         if relative_deadline.is_none() || expected_execution_time.is_none() {
             return SchedulabilityResult {
                 ac: AdmissionControl::NOTREALTIME,
                 worker_id: None,
                 costatus: None,
+                group: None,
+                func: None,
+                counter_offer: None,
+                reserved: None,
             };
         }
         // 新建这个任务的状态并初始化id
         let mut co_stat = SchedulerStatus::new(expected_execution_time, relative_deadline);
+        co_stat.set_criticality(criticality, wcet_hi);
         let id = crate::task::get_id();
         co_stat.init(id);
+        let res = self.admit(&mut co_stat, worker_id);
+        self.trace_admission(id, &res);
+        res
+    }
+
+    /**
+     * 两阶段准入：准入控制并预留容量
+     * 准入和预留之间不会有其他准入，预留的任务状态会参与之后的准入控制，
+     * 调用者用Reservation::commit生成microprocess，或者drop释放容量
+     */
+    pub fn reserve(
+        &self,
+        expected_execution_time: Option<Duration>,
+        relative_deadline: Option<Duration>,
+        criticality: Criticality,
+        wcet_hi: Option<Duration>,
+    ) -> Reservation<'_> {
+        self.reserve_with(
+            expected_execution_time,
+            relative_deadline,
+            criticality,
+            wcet_hi,
+            None,
+        )
+    }
+
+    /**
+     * 在指定的worker上预留容量，用于接受反报价
+     */
+    pub fn reserve_on(
+        &self,
+        worker_id: u8,
+        expected_execution_time: Option<Duration>,
+        relative_deadline: Option<Duration>,
+        criticality: Criticality,
+        wcet_hi: Option<Duration>,
+    ) -> Reservation<'_> {
+        self.reserve_with(
+            expected_execution_time,
+            relative_deadline,
+            criticality,
+            wcet_hi,
+            Some(worker_id),
+        )
+    }
+
+    fn reserve_with(
+        &self,
+        expected_execution_time: Option<Duration>,
+        relative_deadline: Option<Duration>,
+        criticality: Criticality,
+        wcet_hi: Option<Duration>,
+        worker_id: Option<u8>,
+    ) -> Reservation<'_> {
+        let mut reservation = self.try_reserve(
+            expected_execution_time,
+            relative_deadline,
+            criticality,
+            wcet_hi,
+            worker_id,
+        );
+        self.scheduler.get_metrics().admission(reservation.get_ac());
+        if reservation.get_ac() == AdmissionControl::UNSCHEDULABLE {
+            let offer = self.counter_offer(
+                expected_execution_time,
+                relative_deadline,
+                criticality,
+                wcet_hi,
+            );
+            reservation = reservation.with_counter_offer(offer);
+        }
+        reservation
+    }
+
+    /**
+     * 预留容量，但不记录准入计数
+     * 用于一次请求中的多次尝试(自动接受反报价、等待重新准入)，调用者记录最终结果
+     */
+    pub(crate) fn try_reserve(
+        &self,
+        expected_execution_time: Option<Duration>,
+        relative_deadline: Option<Duration>,
+        criticality: Criticality,
+        wcet_hi: Option<Duration>,
+        worker_id: Option<u8>,
+    ) -> Reservation<'_> {
+        let _guard = self.admission.lock().unwrap();
+        self.reserve_locked(
+            expected_execution_time,
+            relative_deadline,
+            criticality,
+            wcet_hi,
+            worker_id,
+        )
+    }
+
+    /**
+     * 原子地为一组实时任务预留容量，tasks是(预期执行时间, 相对截止时间, 关键级别)
+     * 每个任务按顺序放在第一个能准入它的worker上，同一组的任务可以在不同的worker上，
+     * 都能准入时返回所有的预留，否则不预留任何容量
+     */
+    pub fn reserve_group(
+        &self,
+        tasks: &[(Duration, Duration, Criticality)],
+    ) -> Option<Vec<Reservation<'_>>> {
+        let _guard = self.admission.lock().unwrap();
+        let workers = self.scheduler.get_available_workers();
+        let first = self.next_worker.fetch_add(1, Ordering::Relaxed) as usize;
+        let mut reservations = Vec::with_capacity(tasks.len());
+        for &(expected_execution_time, relative_deadline, criticality) in tasks {
+            // 已经预留的容量参与后面任务的准入控制
+            let reservation = (0..workers.len())
+                .map(|k| {
+                    self.reserve_locked(
+                        Some(expected_execution_time),
+                        Some(relative_deadline),
+                        criticality,
+                        None,
+                        Some(workers[(first + k) % workers.len()]),
+                    )
+                })
+                .find(|reservation| reservation.get_ac() != AdmissionControl::UNSCHEDULABLE);
+            match reservation {
+                Some(reservation) => reservations.push(reservation),
+                None => break,
+            }
+        }
+        // 有任务不能准入时drop已经预留的容量
+        if reservations.len() == tasks.len() {
+            for reservation in reservations.iter() {
+                self.scheduler.get_metrics().admission(reservation.get_ac());
+            }
+            return Some(reservations);
+        }
+        for _ in tasks {
+            self.scheduler
+                .get_metrics()
+                .admission(AdmissionControl::UNSCHEDULABLE);
+        }
+        None
+    }
+
+    /// 调用者需要持有准入锁
+    fn reserve_locked(
+        &self,
+        expected_execution_time: Option<Duration>,
+        relative_deadline: Option<Duration>,
+        criticality: Criticality,
+        wcet_hi: Option<Duration>,
+        worker_id: Option<u8>,
+    ) -> Reservation<'_> {
+        let res = self.admission(
+            expected_execution_time,
+            relative_deadline,
+            criticality,
+            wcet_hi,
+            worker_id,
+        );
+        if let (Some(worker_id), Some(stat)) = (res.worker_id, res.costatus.as_ref()) {
+            self.scheduler
+                .update_status(stat.get_co_id(), stat.clone(), worker_id);
+        }
+        Reservation {
+            runtime: self,
+            result: Some(res),
+        }
+    }
+
+    /**
+     * 反报价：所有可用worker上能够准入这个任务的最早相对截止时间
+     * 按处理器需求计算，EDF-VD不能通过的worker会被跳过
+     * 需要检查所有worker，只在返回给调用者的最终拒绝上计算；
+     * 反报价不预留容量，之后的准入可能已经占用了这部分容量
+     */
+    pub(crate) fn counter_offer(
+        &self,
+        expected_execution_time: Option<Duration>,
+        relative_deadline: Option<Duration>,
+        criticality: Criticality,
+        wcet_hi: Option<Duration>,
+    ) -> Option<CounterOffer> {
+        if self.scheduler.is_shutdown()
+            || expected_execution_time.is_none()
+            || relative_deadline.is_none()
+        {
+            return None;
+        }
+        let mut co_stat = SchedulerStatus::new(expected_execution_time, relative_deadline);
+        co_stat.set_criticality(criticality, wcet_hi);
+        co_stat.init(crate::task::get_id());
+        let co_stat = &co_stat;
+        let now = clock::now();
+        let remaining = nanos(co_stat.expected_remaining_execution_time?);
+        let mut offer: Option<CounterOffer> = None;
+        for worker_id in self.scheduler.get_available_workers() {
+            let status_map = self.scheduler.get_status(worker_id).unwrap_or_default();
+            let curr = self.scheduler.get_curr_running_id(worker_id);
+            let jobs = realtime_jobs(&status_map, curr, co_stat.get_co_id(), now);
+            let relative_deadline = match analysis::earliest_deadline(&jobs, remaining) {
+                Some(deadline) => Duration::from_nanos(deadline),
+                None => continue,
+            };
+            if offer.is_some_and(|o| o.relative_deadline <= relative_deadline) {
+                continue;
+            }
+            let mut trial =
+                SchedulerStatus::new(co_stat.get_wcet(Criticality::LO), Some(relative_deadline));
+            trial.set_criticality(co_stat.criticality, co_stat.get_wcet(Criticality::HI));
+            if !self.edf_vd(worker_id, &mut trial) {
+                continue;
+            }
+            offer = Some(CounterOffer {
+                worker_id,
+                relative_deadline,
+            });
+        }
+        offer
+    }
+
+    /**
+     * 实时任务的准入控制
+     */
+    fn admit(&self, co_stat: &mut SchedulerStatus, worker_id: Option<u8>) -> SchedulabilityResult {
+        let worker_id = match worker_id {
+            Some(worker_id) => self
+                .scheduler
+                .get_available_workers()
+                .contains(&worker_id)
+                .then_some(worker_id),
+            None => self.select_worker(co_stat),
+        };
+        let worker_id = match worker_id {
+            Some(worker_id) => worker_id,
+            None => {
+                return SchedulabilityResult {
+                    ac: AdmissionControl::UNSCHEDULABLE,
+                    worker_id: None,
+                    costatus: None,
+                    group: None,
+                    func: None,
+                    counter_offer: None,
+                    reserved: None,
+                }
+            }
+        };
+        // EDF-VD，同时设置虚拟截止时间
+        if !self.edf_vd(worker_id, co_stat) {
+            return SchedulabilityResult {
+                ac: AdmissionControl::UNSCHEDULABLE,
+                worker_id: None,
+                costatus: None,
+                group: None,
+                func: None,
+                counter_offer: None,
+                reserved: None,
+            };
+        }
         // 准入控制
-        self.is_schedulable(&co_stat)
+        self.is_schedulable(co_stat, worker_id)
+    }
+
+    /**
+     * 记录准入控制的结果
+     * 一次请求可能尝试多次准入，准入计数由调用者在请求有最终结果时记录
+     */
+    fn trace_admission(&self, co_id: u64, res: &SchedulabilityResult) {
+        let worker_id = res.worker_id.unwrap_or(SCHEDULER_RING);
+        if res.ac == AdmissionControl::UNSCHEDULABLE {
+            self.scheduler
+                .trace(worker_id, TraceKind::Reject, co_id, res.ac as u64);
+        } else {
+            self.scheduler
+                .trace(worker_id, TraceKind::Admit, co_id, res.ac as u64);
+        }
+    }
+
+    /**
+     * HI模式下LO任务的处理方式
+     */
+    pub fn set_lo_policy(&self, policy: LoPolicy) {
+        self.scheduler.set_lo_policy(policy);
+    }
+
+    /**
+     * 获取工作核心当前的关键级别模式
+     */
+    pub fn get_mode(&self, worker_id: u8) -> Criticality {
+        self.scheduler.get_mode(worker_id)
     }
 
     /**
@@ -86,20 +661,81 @@ impl Runtime {
         F: Send + 'static,
         T: Send + 'static,
     {
+        self.micro_process_with_cancel(f, || {}, schedulability_result)
+    }
+
+    /**
+     * microprocess的实例化
+     * on_cancel在任务被取消时执行(例如HI模式下丢弃LO任务)
+     */
+    pub fn micro_process_with_cancel<F, T, C>(
+        &self,
+        f: F,
+        on_cancel: C,
+        schedulability_result: SchedulabilityResult,
+    ) -> Result<u64, Error>
+    where
+        F: FnOnce() -> T,
+        F: Send + 'static,
+        T: Send + 'static,
+        C: FnOnce() + Send + 'static,
+    {
+        let mut schedulability_result = schedulability_result;
+        // 分发成功前出错时释放旧接口预留的容量
+        let reserved = schedulability_result.reserved.take();
         // 捕获panic
         let func = Box::new(move || {
             let _ = panic::catch_unwind(AssertUnwindSafe(f));
         });
+        let on_cancel = Box::new(on_cancel);
+        // 公平调度的分组和权重
+        let (group, weight) = schedulability_result
+            .group
+            .clone()
+            .unwrap_or((String::new(), crate::scheduler::NICE_0_WEIGHT));
         // 获取准入控制结果
         let ac = schedulability_result.get_ac();
         // 获取调度的目标工作核心
         let worker_id = schedulability_result.worker_id.unwrap_or_default();
         // 这个状态用于实例化
-        let status = schedulability_result.costatus;
-        match ac {
+        let mut co = match ac {
             AdmissionControl::NOTREALTIME => {
                 tracing::info!("NOT REAL TIME");
-                let co = Coroutine::new(func, StackSize::default(), false, None, None);
+                Coroutine::new(func, self.scheduler.get_stack_size(), false, None, None)
+            }
+            AdmissionControl::PREEMPTIVE | AdmissionControl::SCHEDULABLE => {
+                let mut co = Coroutine::from_status(func, schedulability_result.costatus.unwrap());
+                co.set_stack_size(self.scheduler.get_stack_size());
+                co
+            }
+            _ => {
+                // 这个case永远不会到达
+                return Err(Error::msg("spawn failed, cause: UNSCHEDULABLE"));
+            }
+        };
+        co.set_cancel_hook(on_cancel);
+        co.set_group(&group, weight);
+        if let Some(func) = schedulability_result.func {
+            co.set_func(func);
+        }
+        let id = self.dispatch(co, ac, worker_id)?;
+        if let Some(reserved) = reserved {
+            reserved.keep();
+        }
+        Ok(id)
+    }
+
+    /**
+     * 按准入控制的结果分发microprocess
+     */
+    fn dispatch(
+        &self,
+        co: Box<Coroutine>,
+        ac: AdmissionControl,
+        worker_id: u8,
+    ) -> Result<u64, Error> {
+        match ac {
+            AdmissionControl::NOTREALTIME => {
                 let id = co.get_co_id();
                 // 这里的worker_id没用
                 if let Ok(()) = self.scheduler.push(co, false, worker_id) {
@@ -111,34 +747,45 @@ impl Runtime {
                 };
             }
             AdmissionControl::PREEMPTIVE => {
-                let co = Coroutine::from_status(func, status.unwrap());
                 let id = co.get_co_id();
                 let stat = co.get_schedulestatus();
+                // 先找到要通知的线程，找不到时任务还没有放进slot
+                let pthread_id = match self.scheduler.get_pthread_id(worker_id) {
+                    Some(pthread_id) => pthread_id,
+                    None => {
+                        tracing::error!("spawn failed, worker {} has no thread", worker_id);
+                        return Err(Error::msg("spawn failed"));
+                    }
+                };
                 // 先更新状态
                 self.scheduler.update_status(id, stat, worker_id);
-                // 放到对应工作核心的slot
-                self.scheduler.set_slots(worker_id, co);
+                // 放到对应工作核心的slot，槽被占用时到实时队列排队
+                if let Err(co) = self.scheduler.set_slots(worker_id, co) {
+                    return self.dispatch(co, AdmissionControl::SCHEDULABLE, worker_id);
+                }
 
                 // 发信号通知抢占
                 let sigval = libc::sigval {
                     sival_ptr: 0 as *mut libc::c_void,
                 };
-                if let Some(pthread_id) = self.scheduler.get_pthread_id(worker_id) {
-                    let ret = unsafe {
-                        libc::pthread_sigqueue(
-                            pthread_id,
-                            crate::scheduler::PREEMPTY as i32,
-                            sigval,
-                        )
-                    };
-                    assert!(ret == 0);
-                    return Ok(id);
+                let ret = unsafe {
+                    libc::pthread_sigqueue(
+                        pthread_id,
+                        self.scheduler.get_preempt_signal() as i32,
+                        sigval,
+                    )
+                };
+                if ret != 0 {
+                    // 信号发不出去时把slot里的任务取回来，到实时队列排队
+                    tracing::warn!("preempt signal to worker {} failed: {}", worker_id, ret);
+                    if let Some(co) = self.scheduler.get_slots(worker_id) {
+                        self.dispatch(co, AdmissionControl::SCHEDULABLE, worker_id)?;
+                    }
                 }
-                Err(Error::msg("spawn failed"))
+                Ok(id)
             }
 
             AdmissionControl::SCHEDULABLE => {
-                let co = Coroutine::from_status(func, status.unwrap());
                 let stat = co.get_schedulestatus();
                 let id = co.get_co_id();
                 // 放到目标工作核心的实时队列排队
@@ -158,262 +805,134 @@ impl Runtime {
     }
 
     /**
-     * 准入控制
+     * 选择目标工作核心
      */
-    fn is_schedulable(&self, co_stat: &SchedulerStatus) -> SchedulabilityResult {
+    fn select_worker(&self, _co_stat: &SchedulerStatus) -> Option<u8> {
         //TODO: 指定一个worker，怎么选？或者可以遍历所有的线程
-        // 这里直接随机指定一个
-        let worker_id = (co_stat.get_co_id() % self.threads.len() as u64) as u8;
-        while let Some(mut status_map) = self.scheduler.get_status(worker_id) {
-            //获取调度器的任务状态信息并进入循环，没有任务状态信息，循环将退出。
-            if status_map.is_empty() {
-                //如果任务状态信息为空，表示当前没有其他任务在运行，因此可以直接调度新任务。
-                //计算并更新可用时间
-                let available_time = (co_stat.absolute_deadline.unwrap()
-                    - Instant::now()
-                    - co_stat.expected_remaining_execution_time.unwrap())
-                .as_micros() as i128 as f64;
-                self.scheduler
-                    .update_ava_time(worker_id, co_stat.get_co_id(), available_time);
-
-                return SchedulabilityResult {
-                    ac: AdmissionControl::SCHEDULABLE,
-                    worker_id: Some(worker_id),
-                    costatus: Some(co_stat.clone()),
-                };
-            }
-            let curr: u64 = self.scheduler.get_curr_running_id(worker_id); //获取当前正在运行的任务的唯一标识符
+        // 这里轮流指定一个，跳过正在退役的worker
+        // 不使用任务id，保证模拟运行的结果可以复现
+        let workers = self.scheduler.get_available_workers();
+        if workers.is_empty() {
+            return None;
+        }
+        let next = self.next_worker.fetch_add(1, Ordering::Relaxed);
+        Some(workers[(next % workers.len() as u64) as usize])
+    }
 
-            let running = status_map.get(&curr); //获取当前运行的任务的状态信息
-            if running.is_none() {
-                //如果当前没有正在运行的任务，或者没有启动时间信息，则跳过循环并继续。
-                //没有获取到可能是任务刚开始
-                drop(status_map);
-                continue;
-            }
-            let start = running.unwrap().curr_start_time;
-            if start.is_none() {
-                //同上,可能是任务刚开始
-                drop(status_map);
-                continue;
-            }
-            let start = start.unwrap(); //当前运行任务启动时间
-            let now = Instant::now(); //当前时间
-            if status_map.get(&curr).unwrap().absolute_deadline.is_some() {
-                //如果当前运行的任务有绝对截止日期，说明它是实时任务
-                status_map.entry(curr).and_modify(|curr_stat| {
-                    let mut eret = curr_stat.expected_remaining_execution_time.unwrap(); //获取剩余执行时间
-                    let time_diff = now - start;
-                    if eret > time_diff {
-                        //剩余执行时间 eret 大于时间差
-                        eret -= time_diff;
-                        curr_stat.expected_remaining_execution_time = Some(eret);
-                    //更新剩余执行时间
-                    } else {
-                        //如果剩余执行时间小于等于时间差，将剩余执行时间设置为零。
-                        //说明任务已经结束
-                        curr_stat.expected_remaining_execution_time =
-                            Some(std::time::Duration::from_millis(0));
-                    }
-                });
-            } else {
-                //如果当前运行任务没有绝对截止日期，可以被抢占
-                // 抢占前更新可用时间
-                let available_time = (co_stat.absolute_deadline.unwrap()
-                    - now
-                    - co_stat.expected_remaining_execution_time.unwrap())
-                .as_micros() as i128 as f64;
-                self.scheduler
-                    .update_ava_time(worker_id, co_stat.get_co_id(), available_time);
+    /**
+     * EDF-VD准入测试
+     * 用 C/D 作为每个任务的利用率：
+     * u_lo_lo: LO任务在LO级别WCET下的利用率
+     * u_hi_lo: HI任务在LO级别WCET下的利用率
+     * u_hi_hi: HI任务在HI级别WCET下的利用率
+     * u_lo_lo + u_hi_hi <= 1 时直接用EDF，
+     * 否则 x = u_hi_lo / (1 - u_lo_lo)，需要 x * u_lo_lo + u_hi_hi <= 1，
+     * HI任务的虚拟截止时间为 spawn_time + x * D
+     * 同一个worker上的虚拟截止时间必须使用同一个x，worker上已有HI任务时x固定不变，
+     * 需要其他x的任务不能准入
+     */
+    fn edf_vd(&self, worker_id: u8, co_stat: &mut SchedulerStatus) -> bool {
+        let mode = self.scheduler.get_mode(worker_id);
+        // HI模式下不再接收LO实时任务
+        if mode == Criticality::HI && co_stat.criticality == Criticality::LO {
+            return false;
+        }
+        let status_map = self.scheduler.get_status(worker_id).unwrap_or_default();
+        let tasks: Vec<&SchedulerStatus> = status_map
+            .values()
+            .filter(|s| s.absolute_deadline.is_some())
+            .chain(std::iter::once(&*co_stat))
+            .collect();
+        if tasks.iter().all(|s| s.criticality == Criticality::LO) {
+            return true;
+        }
 
-                return SchedulabilityResult {
-                    ac: AdmissionControl::PREEMPTIVE,
-                    worker_id: Some(worker_id),
-                    costatus: Some(co_stat.clone()),
-                };
+        let utilization = |s: &SchedulerStatus, level: Criticality| -> f64 {
+            match (s.get_wcet(level), s.get_relative_deadline()) {
+                (Some(c), Some(d)) if !d.is_zero() => c.as_secs_f64() / d.as_secs_f64(),
+                _ => 0.0,
             }
-
-            // 以下开始是实时任务的准入控制
-            let mut stat_vec = BinaryHeap::new(); //创建一个二叉堆存储任务的状态信息
-            status_map.iter().for_each(|(_, s)| {
-                //迭代任务状态信息，将具有绝对截止日期的任务状态信息放入堆中。
-                if s.absolute_deadline.is_some() {
-                    stat_vec.push(s)
+        };
+        // 已有HI任务的虚拟截止时间所用的x
+        let fixed = status_map
+            .values()
+            .find(|s| s.absolute_deadline.is_some() && s.criticality == Criticality::HI)
+            .map(|s| match (s.virtual_deadline, s.get_relative_deadline()) {
+                (Some(vd), Some(rd)) if !rd.is_zero() => {
+                    vd.saturating_duration_since(s.get_spawn_time())
+                        .as_secs_f64()
+                        / rd.as_secs_f64()
                 }
+                _ => 1.0,
             });
-            let mut total_remaining: f64 = 0.0; //任务的总剩余执行时间
-
-            //快速判断：如果𝑑_𝑛𝑒𝑤 - 𝑑_𝑙𝑎𝑠𝑡 ≥ 𝐶_𝑛𝑒𝑤，直接准入
-            if let Some(end_ddl) = self.scheduler.get_end_ddl(worker_id) {
-                // TODO:get_end_ddl获取了最新的状态，是否可以用之前的status_map代替？
-
-                if co_stat.expected_remaining_execution_time.unwrap()
-                    <= co_stat.absolute_deadline.unwrap() - end_ddl
-                {
-                    while let Some(s) = stat_vec.pop() {
-                        total_remaining +=
-                            s.expected_remaining_execution_time.unwrap().as_micros() as i128 as f64;
-                    }
-                    let available_time = (co_stat.absolute_deadline.unwrap() - now).as_micros()
-                        as i128 as f64
-                        - total_remaining; //计算任务可用时间
-
-                    // TODO:确认一下多线程的ava_time是否正确
-                    self.scheduler
-                        .update_ava_time(worker_id, co_stat.get_co_id(), available_time);
-                    // if let Ok(map) = AVA_TIME.lock().as_mut() {
-                    //     map.insert(co_stat.get_co_id(), available_time);
-                    // }
-
-                    return SchedulabilityResult {
-                        ac: AdmissionControl::SCHEDULABLE,
-                        worker_id: Some(worker_id),
-                        costatus: Some(co_stat.clone()),
-                    };
-                }
+        let (mut u_lo_lo, mut u_hi_lo, mut u_hi_hi) = (0.0, 0.0, 0.0);
+        for s in tasks {
+            if s.criticality == Criticality::HI {
+                u_hi_lo += utilization(s, Criticality::LO);
+                u_hi_hi += utilization(s, Criticality::HI);
+            } else {
+                u_lo_lo += utilization(s, Criticality::LO);
             }
+        }
+        let x = match fixed {
+            Some(x) if analysis::edf_vd_fixed(u_lo_lo, u_hi_lo, u_hi_hi, x, mode) => x,
+            Some(_) => return false,
+            None => match analysis::edf_vd_scale(u_lo_lo, u_hi_lo, u_hi_hi, mode) {
+                Some(x) => x,
+                None => return false,
+            },
+        };
+        if x < 1.0 && co_stat.criticality == Criticality::HI {
+            let rd = co_stat.get_relative_deadline().unwrap();
+            co_stat.virtual_deadline = Some(co_stat.get_spawn_time() + rd.mul_f64(x));
+        }
+        true
+    }
 
-            //如果𝑑_𝑛𝑒𝑤 - 𝑑_𝑙𝑎𝑠𝑡 < 𝐶_𝑛𝑒𝑤
-            stat_vec.push(co_stat); //将所判断的任务的状态信息 co_stat 放入堆中
-            let s1 = stat_vec.peek().unwrap().to_owned(); //查看堆中的第一个元素，即具有最早截止日期的任务。
-            let mut found_task = false; //标志是否在二叉堆里找到指定任务
+    /**
+     * 准入控制
+     * 对worker上所有实时任务和新任务做处理器需求分析：
+     * 按EDF调度时，每个截止时间点d之前必须完成的剩余执行时间之和不能超过 d - now，
+     * 所有任务都已到达，所以这个条件是精确的(充分且必要)
+     * 任务按co_id区分，截止时间相同的任务不会被当成同一个任务
+     */
+    fn is_schedulable(&self, co_stat: &SchedulerStatus, worker_id: u8) -> SchedulabilityResult {
+        let unschedulable = SchedulabilityResult {
+            ac: AdmissionControl::UNSCHEDULABLE,
+            worker_id: None,
+            costatus: None,
+            group: None,
+            func: None,
+            counter_offer: None,
+            reserved: None,
+        };
+        let now = clock::now();
+        // 截止时间已经过了的任务不可能满足
+        let new_job = to_job(
+            co_stat,
+            co_stat.expected_remaining_execution_time.unwrap(),
+            now,
+        );
+        if new_job.deadline == 0 {
+            return unschedulable;
+        }
 
-            // pop更高优先级的任务
-            while let Some(s) = stat_vec.pop() {
-                if !found_task && s == co_stat {
-                    found_task = true;
-                }
-                if !found_task {
-                    if s.absolute_deadline.is_some() {
-                        total_remaining +=
-                            s.expected_remaining_execution_time.unwrap().as_micros() as i128 as f64;
-                    }
-                }
-                if found_task {
-                    break;
-                }
-            }
-
-            // d_new - t - RC >= C_new
-            let available_time = (co_stat.absolute_deadline.unwrap() - now).as_micros() as i128
-                as f64
-                - total_remaining; //计算任务可用时间
-            if available_time
-                < co_stat
-                    .expected_remaining_execution_time
-                    .unwrap()
-                    .as_micros() as i128 as f64
-            {
-                return SchedulabilityResult {
-                    ac: AdmissionControl::UNSCHEDULABLE,
-                    worker_id: None,
-                    costatus: None,
-                };
-            } else {
-                // TODO:这里需要更新AVA_TIME吗？先注释了
-                // self.scheduler
-                //     .update_ava_time(worker_id, co_stat.get_co_id(), available_time);
-                // if let Ok(map) = AVA_TIME.lock().as_mut() {
-                //     map.insert(co_stat.get_co_id(), available_time);
-                // }
-            }
-
-            // TODO:确认是否已经pop了？先注释了
-            // stat_vec.pop(); //弹出co_stat
-
-            // 继续验证低优先级任务
-            if let Some(mut map) = self.scheduler.get_ava_time(worker_id) {
-                //先复制AVA_TIME的状态
-                while let Some(s) = stat_vec.pop() {
-                    //验证后面的任务是否满足
-                    // time_i - C_new >= C_i
-                    if s.absolute_deadline > co_stat.absolute_deadline {
-                        let time = map.get(&s.get_co_id());
-                        if let Some(time) = time {
-                            if (time
-                                - (co_stat.expected_remaining_execution_time.unwrap()).as_micros()
-                                    as i128 as f64)
-                                < (s.expected_remaining_execution_time.unwrap()).as_micros() as i128
-                                    as f64
-                            {
-                                // 不可调度，无需改变AVA_TIME的状态
-                                return SchedulabilityResult {
-                                    ac: AdmissionControl::UNSCHEDULABLE,
-                                    worker_id: None,
-                                    costatus: None,
-                                };
-                            } else {
-                                //改变后面任务的可用时间
-                                map.insert(
-                                    s.get_co_id(),
-                                    time - co_stat
-                                        .expected_remaining_execution_time
-                                        .unwrap()
-                                        .as_micros()
-                                        as f64,
-                                );
-                            }
-                        }
-                    }
-                }
-                // 循环结束更新整个AVA_TIME的状态
-                self.scheduler.update_ava_time_map(worker_id, map);
-            }
-
-            // while let Some(s) = stat_vec.pop() {
-            //     //验证后面的任务是否满足
-            //     if s.absolute_deadline > co_stat.absolute_deadline {
-            //         if let Ok(mut map) = AVA_TIME.lock() {
-            //             //先备份 AVA_TIME 的状态
-            //             // TODO: 在循环中备份状态是否有问题？
-            //             let backup_ava_time = map.clone();
-            //             let time = map.get(&s.get_co_id()).cloned();
-            //             if let Some(time) = time {
-            //                 if (time
-            //                     - (co_stat.expected_remaining_execution_time.unwrap()).as_micros()
-            //                         as i128 as f64)
-            //                     < (s.expected_remaining_execution_time.unwrap()).as_micros() as i128
-            //                         as f64
-            //                 {
-            //                     *map = backup_ava_time;
-            //                     return SchedulabilityResult {
-            //                         ac: AdmissionControl::UNSCHEDULABLE,
-            //                         worker_id: None,
-            //                         costatus: None,
-            //                     };
-            //                 } else {
-            //                     //改变后面任务的可用时间
-            //                     map.insert(
-            //                         s.get_co_id(),
-            //                         time - co_stat
-            //                             .expected_remaining_execution_time
-            //                             .unwrap()
-            //                             .as_micros()
-            //                             as f64,
-            //                     );
-            //                 }
-            //             }
-            //         }
-            //     }
-            // }
-
-            // 如果第一个任务恰好是这个任务，即最早截止时间
-            if s1.eq(&co_stat) {
-                return SchedulabilityResult {
-                    ac: AdmissionControl::PREEMPTIVE,
-                    worker_id: Some(worker_id),
-                    costatus: Some(co_stat.clone()),
-                };
-            }
-            break;
+        let status_map = self.scheduler.get_status(worker_id).unwrap_or_default();
+        let curr = self.scheduler.get_curr_running_id(worker_id);
+        let mut jobs = realtime_jobs(&status_map, curr, co_stat.get_co_id(), now);
+        jobs.push(new_job);
+        if analysis::job_slack(&jobs) < 0 {
+            return unschedulable;
         }
-
-        //后面所有任务验证完再返回可调度
-        return SchedulabilityResult {
-            ac: AdmissionControl::SCHEDULABLE,
+        SchedulabilityResult {
+            ac: dispatch_mode(&status_map, curr, co_stat),
             worker_id: Some(worker_id),
             costatus: Some(co_stat.clone()),
-        };
+            group: None,
+            func: None,
+            counter_offer: None,
+            reserved: None,
+        }
     }
 
     /**
@@ -428,31 +947,488 @@ impl Runtime {
      */
     pub fn get_status(&self) -> Option<BTreeMap<u64, SchedulerStatus>> {
         let mut status = BTreeMap::new();
-        for id in 0..self.threads.len() {
-            if let Some(mut map) = self.scheduler.get_status(id as u8) {
+        for id in self.scheduler.get_workers().into_keys() {
+            if let Some(mut map) = self.scheduler.get_status(id) {
                 status.append(&mut map);
             }
         }
         Some(status)
     }
 
+    /**
+     * 获取每个工作核心实时任务收件箱的提交和取走延迟
+     */
+    pub fn get_inbox_latency(&self) -> BTreeMap<u8, InboxLatency> {
+        let mut latency = BTreeMap::new();
+        for id in self.scheduler.get_workers().into_keys() {
+            if let Some(l) = self.scheduler.get_inbox_latency(id) {
+                latency.insert(id, l);
+            }
+        }
+        latency
+    }
+
+    /**
+     * 获取非实时任务各分组的虚拟运行时间
+     */
+    pub fn get_vruntime(&self) -> HashMap<String, Duration> {
+        self.scheduler.get_vruntime()
+    }
+
+    /**
+     * 导出调度事件，Chrome Trace Event格式的JSON，可以用Perfetto打开
+     */
+    pub fn get_trace(&self) -> String {
+        self.scheduler.get_tracer().to_chrome_trace().to_string()
+    }
+
+    /**
+     * 开启或关闭调度事件记录，默认开启
+     */
+    pub fn set_tracing(&self, enabled: bool) {
+        self.scheduler.get_tracer().set_enabled(enabled);
+    }
+
+    /**
+     * 清空已记录的调度事件
+     */
+    pub fn clear_trace(&self) {
+        self.scheduler.get_tracer().clear();
+    }
+
+    /**
+     * 各worker的健康状态，按看门狗的配置判断失控任务
+     * 没有开启看门狗时使用默认配置，只报告不处理
+     */
+    pub fn get_health(&self) -> Vec<WorkerHealth> {
+        match self.scheduler.get_watchdog() {
+            Some(config) => self.scheduler.check_workers(config),
+            None => self.scheduler.check_workers(&WatchdogConfig::default()),
+        }
+    }
+
+    /// worker上每单位wasm fuel的纳秒数，还没有开启了fuel计量的任务完成时返回None
+    pub fn get_fuel_rate(&self, worker_id: u8) -> Option<f64> {
+        self.scheduler.get_fuel_calibration().get(worker_id)
+    }
+
+    /**
+     * 按校准结果估计消耗fuel的运行时间
+     * 不指定worker时取所有worker中最慢的估计
+     */
+    pub fn estimate_fuel_time(&self, fuel: u64, worker_id: Option<u8>) -> Option<Duration> {
+        let calibration = self.scheduler.get_fuel_calibration();
+        match worker_id {
+            Some(worker_id) => calibration.estimate(worker_id, fuel),
+            None => self
+                .get_workers()
+                .keys()
+                .filter_map(|&worker_id| calibration.estimate(worker_id, fuel))
+                .max(),
+        }
+    }
+
+    /// 按已完成任务学习到的执行时间，还没有完成的调用时返回None
+    pub fn get_execution_estimate(&self, func: &FuncKey) -> Option<ExecutionEstimate> {
+        self.scheduler.get_estimator().get(func)
+    }
+
+    /// 所有学习到的执行时间，按模块和导出函数排序
+    pub fn get_execution_estimates(&self) -> Vec<ExecutionEstimate> {
+        self.scheduler.get_estimator().list()
+    }
+
+    /**
+     * 准入使用的执行时间
+     * 开启了learned_admission并且样本足够时取声明值和学习到的p99中较大的一个，
+     * 长期少报执行时间的调用不会挤占其他任务的保证
+     */
+    pub fn admission_wcet(&self, func: &FuncKey, declared: Duration) -> Duration {
+        if !self.scheduler.is_learned_admission() {
+            return declared;
+        }
+        match self.get_execution_estimate(func).and_then(|e| e.get_p99()) {
+            Some(p99) => declared.max(p99),
+            None => declared,
+        }
+    }
+
+    /// 调度指标，Server也在这里累加请求的时延
+    pub fn metrics(&self) -> &Metrics {
+        self.scheduler.get_metrics()
+    }
+
+    /**
+     * 导出Prometheus文本格式的指标
+     */
+    pub fn get_metrics(&self) -> String {
+        self.scheduler.encode_metrics()
+    }
+
     /**
      * 获取已完成任务的状态
      */
     pub fn get_completed_status(&self) -> Option<BTreeMap<u64, SchedulerStatus>> {
         self.scheduler.get_completed_status()
     }
+
+    /**
+     * 查询持久化的已完成任务历史，按完成时间排序
+     * 没有通过RuntimeBuilder::history开启时返回错误
+     */
+    pub fn query_history(&self, query: &HistoryQuery) -> Result<Vec<HistoryRecord>, Error> {
+        match self.scheduler.get_history() {
+            Some(history) => history.query(query),
+            None => Err(Error::msg("history disabled")),
+        }
+    }
+
+    /// Runtime的名字，也是它在hyperwasm下的cgroup子树的名字
+    pub fn get_name(&self) -> &str {
+        self.scheduler.get_name()
+    }
+
+    /// 启动时各线程的CPU
+    pub fn get_layout(&self) -> &CpuLayout {
+        self.scheduler.get_layout()
+    }
+
+    /// 所有Runtime共用的cgroup根目录
+    pub fn get_cgroup_root(&self) -> &Path {
+        self.scheduler.get_cgroup_root()
+    }
+
+    /// 保存剖析结果，覆盖同一个键之前的结果
+    pub fn record_profile(&self, stats: ProfileStats) {
+        self.profiles.record(stats);
+    }
+
+    pub fn get_profile(&self, key: &ProfileKey) -> Option<ProfileStats> {
+        self.profiles.get(key)
+    }
+
+    /// 所有剖析结果，按键排序
+    pub fn get_profiles(&self) -> Vec<ProfileStats> {
+        self.profiles.list()
+    }
+
+    /// 剖析时干扰线程使用的cgroup，见Scheduler::interference_cgroup
+    pub(crate) fn interference_cgroup(&self) -> Option<Controllerv2> {
+        self.scheduler.interference_cgroup()
+    }
+
+    /**
+     * 结束或取消的任务数量，变化时说明有容量被释放
+     */
+    pub fn get_completions(&self) -> u64 {
+        self.scheduler.get_completions()
+    }
+}
+
+/**
+ * Runtime的构建器
+ * 没有设置的项使用默认值，build时检查所有配置
+ */
+#[derive(Clone, Debug)]
+pub struct RuntimeBuilder {
+    name: Option<String>,
+    worker_threads: u8,
+    start_cpu: u16,
+    layout: Option<CpuLayout>,
+    scheduler_cpu_offset: Option<u16>,
+    tester_cpu_offset: Option<u16>,
+    timer_exp: u64,
+    worker_capacity: usize,
+    completed_capacity: usize,
+    stack_size: StackSize,
+    preempt_signal: Signal,
+    timer_signal: Signal,
+    cgroup_root: PathBuf,
+    history: Option<HistoryConfig>,
+    watchdog: Option<WatchdogConfig>,
+    epoch: EpochConfig,
+    learned_admission: bool,
+}
+
+impl Default for RuntimeBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RuntimeBuilder {
+    pub fn new() -> RuntimeBuilder {
+        RuntimeBuilder {
+            name: None,
+            worker_threads: 1,
+            start_cpu: 0,
+            layout: None,
+            scheduler_cpu_offset: None,
+            tester_cpu_offset: None,
+            timer_exp: 0,
+            worker_capacity: WORKER_CAPACITY,
+            completed_capacity: COMPLETED_CAPACITY,
+            stack_size: StackSize::default(),
+            preempt_signal: PREEMPTY,
+            timer_signal: SIG,
+            cgroup_root: PathBuf::from(CG_ROOT),
+            history: None,
+            watchdog: None,
+            epoch: EpochConfig::default(),
+            learned_admission: false,
+        }
+    }
+
+    /// cgroup子树的名字，默认runtime{n}，同一个进程中不能重复
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// worker数量，1 ~ MAX_WORKERS，默认1
+    pub fn worker_threads(mut self, worker_threads: u8) -> Self {
+        self.worker_threads = worker_threads;
+        self
+    }
+
+    /// 连续分配CPU时的第一个CPU，给listener使用，默认0
+    pub fn start_cpu(mut self, start_cpu: u16) -> Self {
+        self.start_cpu = start_cpu;
+        self
+    }
+
+    /// 指定各线程的CPU，会覆盖worker_threads、start_cpu和CPU偏移
+    pub fn layout(mut self, layout: CpuLayout) -> Self {
+        self.layout = Some(layout);
+        self
+    }
+
+    /// 全局调度器线程的CPU相对start_cpu的偏移，默认1
+    pub fn scheduler_cpu_offset(mut self, offset: u16) -> Self {
+        self.scheduler_cpu_offset = Some(offset);
+        self
+    }
+
+    /// 测试线程的CPU相对start_cpu的偏移，默认2 + worker_threads
+    pub fn tester_cpu_offset(mut self, offset: u16) -> Self {
+        self.tester_cpu_offset = Some(offset);
+        self
+    }
+
+    /// 非实时任务的时间片（微秒），0表示不轮转
+    pub fn timer_exp(mut self, timer_exp: u64) -> Self {
+        self.timer_exp = timer_exp;
+        self
+    }
+
+    /// worker本地队列容量，默认256
+    pub fn worker_capacity(mut self, capacity: usize) -> Self {
+        self.worker_capacity = capacity;
+        self
+    }
+
+    /// 保留的已完成任务状态数量，默认100000
+    pub fn completed_capacity(mut self, capacity: usize) -> Self {
+        self.completed_capacity = capacity;
+        self
+    }
+
+    /// 协程的栈大小
+    pub fn stack_size(mut self, stack_size: StackSize) -> Self {
+        self.stack_size = stack_size;
+        self
+    }
+
+    /// 抢占信号，默认SIGURG
+    pub fn preempt_signal(mut self, signal: Signal) -> Self {
+        self.preempt_signal = signal;
+        self
+    }
+
+    /// 定时器信号，默认SIGALRM
+    pub fn timer_signal(mut self, signal: Signal) -> Self {
+        self.timer_signal = signal;
+        self
+    }
+
+    /// cgroup根目录，默认/sys/fs/cgroup/hyperwasm
+    pub fn cgroup_root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.cgroup_root = root.into();
+        self
+    }
+
+    /// 启动看门狗线程，检查失控的任务，默认不启动
+    pub fn watchdog(mut self, config: WatchdogConfig) -> Self {
+        self.watchdog = Some(config);
+        self
+    }
+
+    /// wasm任务超出截止时间或预算时在epoch检查点trap，默认只按原方式调度
+    pub fn epoch(mut self, config: EpochConfig) -> Self {
+        self.epoch = config;
+        self
+    }
+
+    /// 准入时使用声明的和学习到的p99中较大的执行时间，默认只使用声明的执行时间
+    pub fn learned_admission(mut self, enabled: bool) -> Self {
+        self.learned_admission = enabled;
+        self
+    }
+
+    /// 把已完成任务追加到JSON Lines文件，默认只保留在内存中
+    pub fn history(mut self, config: HistoryConfig) -> Self {
+        self.history = Some(config);
+        self
+    }
+
+    /// 检查配置并生成各线程的CPU
+    fn config(&self) -> Result<SchedulerConfig, Error> {
+        let layout = match &self.layout {
+            Some(layout) => layout.clone(),
+            None => {
+                if self.worker_threads == 0 || self.worker_threads > MAX_WORKERS {
+                    return Err(Error::msg(format!(
+                        "worker_threads must be between 1 and {}",
+                        MAX_WORKERS
+                    )));
+                }
+                let tester_offset = match self.tester_cpu_offset {
+                    Some(offset) => offset,
+                    None => self.worker_threads as u16 + 2,
+                };
+                CpuLayout::with_offsets(
+                    self.worker_threads,
+                    self.start_cpu,
+                    self.scheduler_cpu_offset.unwrap_or(1),
+                    tester_offset,
+                )?
+            }
+        };
+        layout.validate()?;
+        if layout.workers.len() > MAX_WORKERS as usize {
+            return Err(Error::msg(format!("at most {} workers", MAX_WORKERS)));
+        }
+        if self.worker_capacity == 0 {
+            return Err(Error::msg("worker_capacity must be positive"));
+        }
+        if self.completed_capacity == 0 {
+            return Err(Error::msg("completed_capacity must be positive"));
+        }
+        if self.preempt_signal == self.timer_signal {
+            return Err(Error::msg("preempt_signal and timer_signal must differ"));
+        }
+        for signal in [self.preempt_signal, self.timer_signal] {
+            if signal == Signal::SIGKILL || signal == Signal::SIGSTOP {
+                return Err(Error::msg(format!("{} cannot be handled", signal)));
+            }
+        }
+        let root = &self.cgroup_root;
+        if !root.is_absolute() || root.parent().is_none() || root.file_name().is_none() {
+            return Err(Error::msg(format!(
+                "invalid cgroup_root: {}",
+                root.display()
+            )));
+        }
+        if let Some(name) = &self.name {
+            if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+                return Err(Error::msg(format!("invalid runtime name: {:?}", name)));
+            }
+        }
+        if let Some(watchdog) = &self.watchdog {
+            if watchdog.interval.is_zero() {
+                return Err(Error::msg("watchdog interval must be positive"));
+            }
+            if watchdog.budget_factor == 0 {
+                return Err(Error::msg("watchdog budget_factor must be positive"));
+            }
+        }
+        let history = match &self.history {
+            Some(config) => Some(History::open(config.clone())?),
+            None => None,
+        };
+        Ok(SchedulerConfig {
+            // 最后登记名字，之前的检查失败时不占用名字
+            name: Some(claim_name(self.name.as_deref())?),
+            layout,
+            worker_capacity: self.worker_capacity,
+            completed_capacity: self.completed_capacity,
+            stack_size: self.stack_size,
+            preempt_signal: self.preempt_signal,
+            timer_signal: self.timer_signal,
+            cgroup_root: self.cgroup_root.clone(),
+            watchdog: self.watchdog.clone(),
+            epoch: self.epoch.clone(),
+            learned_admission: self.learned_admission,
+            history,
+        })
+    }
+
+    /// 创建cgroup和worker线程
+    pub fn build(&self) -> Result<Runtime, Error> {
+        let scheduler = Scheduler::with_config(self.config()?);
+        let threads = Scheduler::start(&scheduler, self.timer_exp);
+        Ok(Runtime::with_scheduler(scheduler, threads, self.timer_exp))
+    }
+
+    /// 模拟运行使用的Runtime，没有worker线程、cgroup和信号
+    pub fn build_simulated(&self) -> Result<Runtime, Error> {
+        let scheduler = Scheduler::with_config(self.config()?);
+        scheduler.start_simulated();
+        Ok(Runtime::with_scheduler(
+            scheduler,
+            BTreeMap::new(),
+            self.timer_exp,
+        ))
+    }
 }
 
 impl Drop for Runtime {
     fn drop(&mut self) {
-        while let Some(t) = self.threads.pop() {
-            t.join().unwrap();
+        let has_threads = self
+            .threads
+            .get_mut()
+            .is_ok_and(|threads| !threads.is_empty());
+        if has_threads {
+            let _ = self.shutdown(ShutdownMode::IMMEDIATE, DROP_TIMEOUT);
         }
     }
 }
 
-#[derive(PartialEq, Clone, Copy)]
+/// drop时等待worker退出的时间
+const DROP_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Runtime关闭的方式
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum ShutdownMode {
+    /// 取消还没开始运行的任务；已经开始运行的wasm调用在下一个epoch检查点trap，
+    /// 已经开始运行的原生闭包不再调度，它的栈不会被释放
+    IMMEDIATE,
+    /// 完成已经分发到worker的任务，取消全局队列中的任务
+    DRAINADMITTED,
+    /// 完成所有已经提交的任务
+    DRAINALL,
+}
+
+/// worker退役的方式
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum RetireMode {
+    /// 还没开始运行的任务迁移到其他worker，已经开始运行的任务在本地完成
+    MIGRATE,
+    /// 所有任务都在本地完成
+    FINISH,
+}
+
+/// HI模式下LO实时任务的处理方式
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+pub enum LoPolicy {
+    /// 取消还没开始运行的任务，已经开始运行的wasm调用在下一个epoch检查点trap，原生闭包降级
+    DROP,
+    /// 降级为非实时任务
+    #[default]
+    DEGRADE,
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
 pub enum AdmissionControl {
     NOTREALTIME,
     PREEMPTIVE,
@@ -460,15 +1436,254 @@ pub enum AdmissionControl {
     UNSCHEDULABLE,
 }
 
+/// 纳秒
+fn nanos(d: Duration) -> u64 {
+    d.as_nanos().min(u64::MAX as u128) as u64
+}
+
+/// 任务相对于now的处理器需求，单位纳秒
+fn to_job(s: &SchedulerStatus, remaining: Duration, now: Instant) -> analysis::Job {
+    analysis::Job {
+        remaining: nanos(remaining),
+        deadline: nanos(
+            s.effective_deadline()
+                .unwrap()
+                .saturating_duration_since(now),
+        ),
+    }
+}
+
+/**
+ * 准入成功的实时任务如何分发
+ * 正在运行的是非实时任务，或者新任务比其他实时任务都早截止时需要抢占，
+ * 没有正在运行的任务时由worker按EDF从实时队列中选择
+ */
+fn dispatch_mode(
+    status_map: &BTreeMap<u64, SchedulerStatus>,
+    curr: u64,
+    co_stat: &SchedulerStatus,
+) -> AdmissionControl {
+    let earliest = status_map
+        .iter()
+        .filter(|(id, s)| **id != co_stat.get_co_id() && s.effective_deadline().is_some())
+        .all(|(_, s)| s.effective_deadline() > co_stat.effective_deadline());
+    match status_map.get(&curr) {
+        Some(running) if running.effective_deadline().is_none() || earliest => {
+            AdmissionControl::PREEMPTIVE
+        }
+        _ => AdmissionControl::SCHEDULABLE,
+    }
+}
+
+/**
+ * worker上除了co_id以外的实时任务的处理器需求
+ * 正在运行的任务减去这次已经运行的时间
+ */
+fn realtime_jobs(
+    status_map: &BTreeMap<u64, SchedulerStatus>,
+    curr: u64,
+    co_id: u64,
+    now: Instant,
+) -> Vec<analysis::Job> {
+    status_map
+        .iter()
+        .filter(|(id, s)| **id != co_id && s.effective_deadline().is_some())
+        .map(|(id, s)| {
+            let mut remaining = s.expected_remaining_execution_time.unwrap_or_default();
+            if *id == curr {
+                if let Some(start) = s.curr_start_time {
+                    remaining = remaining.saturating_sub(now.saturating_duration_since(start));
+                }
+            }
+            to_job(s, remaining, now)
+        })
+        .collect()
+}
+
 /// 准入控制的结果
 pub struct SchedulabilityResult {
     ac: AdmissionControl,
     worker_id: Option<u8>,
     costatus: Option<SchedulerStatus>,
+    group: Option<(String, u32)>,
+    func: Option<FuncKey>,               // wasm调用的模块和导出函数
+    counter_offer: Option<CounterOffer>, // 不可调度时能够准入的最早截止时间
+    reserved: Option<Reserved>, // 旧接口预留的容量，没有交给micro_process或者分发失败时释放
+}
+
+/// 旧接口的准入结果持有的预留容量，drop时释放
+struct Reserved {
+    scheduler: Arc<Scheduler>,
+    task: Option<(u64, u8)>, // 预留的任务id和worker，交给worker后为None
+}
+
+impl Reserved {
+    /// 任务已经分发，容量由worker释放
+    fn keep(mut self) {
+        self.task = None;
+    }
+}
+
+impl Drop for Reserved {
+    fn drop(&mut self) {
+        if let Some((co_id, worker_id)) = self.task.take() {
+            self.scheduler.delete_status(co_id, worker_id);
+        }
+    }
+}
+
+/// 反报价：在worker_id上以relative_deadline为相对截止时间可以准入
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CounterOffer {
+    pub worker_id: u8,
+    pub relative_deadline: Duration,
 }
 
 impl SchedulabilityResult {
     pub fn get_ac(&self) -> AdmissionControl {
         self.ac
     }
+
+    pub fn get_worker_id(&self) -> Option<u8> {
+        self.worker_id
+    }
+
+    pub fn get_costatus(&self) -> Option<&SchedulerStatus> {
+        self.costatus.as_ref()
+    }
+
+    /// 不可调度时的反报价
+    pub fn get_counter_offer(&self) -> Option<CounterOffer> {
+        self.counter_offer
+    }
+
+    /**
+     * 指定公平调度的分组(模块或租户)和权重
+     * 非实时任务按分组的虚拟运行时间调度
+     */
+    pub fn with_group(mut self, group: &str, weight: u32) -> SchedulabilityResult {
+        self.group = Some((group.to_owned(), weight));
+        self
+    }
+
+    /// 指定执行的wasm模块和导出函数，完成时学习执行时间
+    pub fn with_func(mut self, func: FuncKey) -> SchedulabilityResult {
+        self.func = Some(func);
+        self
+    }
+}
+
+/**
+ * 预留的容量
+ * 准入成功的实时任务状态已经加入目标worker，commit把任务交给worker，
+ * 没有commit或commit失败时释放，不会留下不存在的任务
+ */
+pub struct Reservation<'a> {
+    runtime: &'a Runtime,
+    result: Option<SchedulabilityResult>,
+}
+
+impl Reservation<'_> {
+    pub fn get_ac(&self) -> AdmissionControl {
+        self.result.as_ref().unwrap().get_ac()
+    }
+
+    pub fn get_worker_id(&self) -> Option<u8> {
+        self.result.as_ref().unwrap().get_worker_id()
+    }
+
+    pub fn get_costatus(&self) -> Option<&SchedulerStatus> {
+        self.result.as_ref().unwrap().get_costatus()
+    }
+
+    pub fn get_counter_offer(&self) -> Option<CounterOffer> {
+        self.result.as_ref().unwrap().get_counter_offer()
+    }
+
+    /**
+     * 指定公平调度的分组(模块或租户)和权重
+     */
+    pub fn with_group(mut self, group: &str, weight: u32) -> Self {
+        self.result = self.result.take().map(|res| res.with_group(group, weight));
+        self
+    }
+
+    /// 指定执行的wasm模块和导出函数
+    pub fn with_func(mut self, func: FuncKey) -> Self {
+        self.result = self.result.take().map(|res| res.with_func(func));
+        self
+    }
+
+    pub(crate) fn with_counter_offer(mut self, counter_offer: Option<CounterOffer>) -> Self {
+        if let Some(res) = self.result.as_mut() {
+            res.counter_offer = counter_offer;
+        }
+        self
+    }
+
+    /**
+     * 用预留的容量生成microprocess
+     */
+    pub fn commit<F, T>(self, f: F) -> Result<u64, Error>
+    where
+        F: FnOnce() -> T,
+        F: Send + 'static,
+        T: Send + 'static,
+    {
+        self.commit_with_cancel(f, || {})
+    }
+
+    /**
+     * 用预留的容量生成microprocess
+     * on_cancel在任务被取消时执行
+     */
+    pub fn commit_with_cancel<F, T, C>(mut self, f: F, on_cancel: C) -> Result<u64, Error>
+    where
+        F: FnOnce() -> T,
+        F: Send + 'static,
+        T: Send + 'static,
+        C: FnOnce() + Send + 'static,
+    {
+        let mut res = self.result.take().unwrap();
+        let reserved = Self::reserved(&res);
+        // 提交时worker上的任务可能已经变化，重新决定是否抢占
+        if let (Some(stat), Some((_, worker_id))) = (res.costatus.as_ref(), reserved) {
+            let scheduler = &self.runtime.scheduler;
+            let status_map = scheduler.get_status(worker_id).unwrap_or_default();
+            let curr = scheduler.get_curr_running_id(worker_id);
+            res.ac = dispatch_mode(&status_map, curr, stat);
+        }
+        let id = self.runtime.micro_process_with_cancel(f, on_cancel, res);
+        if id.is_err() {
+            self.release(reserved);
+        }
+        id
+    }
+
+    /// 取出准入控制的结果，预留的容量交给调用者
+    pub(crate) fn into_result(mut self) -> SchedulabilityResult {
+        self.result.take().unwrap()
+    }
+
+    /// 预留的任务id和worker
+    fn reserved(res: &SchedulabilityResult) -> Option<(u64, u8)> {
+        res.costatus
+            .as_ref()
+            .map(|s| s.get_co_id())
+            .zip(res.worker_id)
+    }
+
+    fn release(&self, reserved: Option<(u64, u8)>) {
+        if let Some((co_id, worker_id)) = reserved {
+            self.runtime.scheduler.delete_status(co_id, worker_id);
+        }
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        if let Some(res) = self.result.take() {
+            self.release(Self::reserved(&res));
+        }
+    }
 }
@@ -1,8 +1,10 @@
 use crate::{
     axum::{CallConfigRequest, TestRequest},
+    profile::{self, Interference, ProfileKey, ProfileStats},
     result::FuncResult,
-    runtime::{AdmissionControl, Runtime, SchedulabilityResult},
-    task::SchedulerStatus,
+    runtime::{AdmissionControl, FuncKey, Reservation, Runtime},
+    scheduler::epoch,
+    task::{Criticality, SchedulerStatus},
 };
 use anyhow::Error;
 use once_cell::sync::Lazy;
@@ -12,15 +14,16 @@ use std::{
     collections::{HashMap, VecDeque},
     fmt,
     sync::{Arc, Mutex},
-    time::Duration,
+    time::{Duration, Instant},
 };
-use wasmtime::{Engine, Linker, Module, Store};
+use wasmtime::{Engine, ExternType, FuncType, Linker, Module, Store, Val, ValType};
 use wasmtime_wasi::{sync::WasiCtxBuilder, WasiCtx};
 thread_local! {
     static NAME_ID: RefCell<HashMap<String, u64>> = RefCell::new(HashMap::new());
 }
 
 static TEST_QUEUE: Lazy<Mutex<VecDeque<Tester>>> = Lazy::new(|| Mutex::new(VecDeque::new()));
+static PROFILE_QUEUE: Lazy<Mutex<VecDeque<ProfileJob>>> = Lazy::new(|| Mutex::new(VecDeque::new()));
 // pub static ref MODEL: Arc<ort::Session> = infer::detect::prepare_model();
 
 pub fn set_test_env(tester: Tester) {
@@ -37,11 +40,27 @@ pub fn get_test_env() -> Option<Tester> {
     }
 }
 
+pub fn set_profile_job(job: ProfileJob) {
+    if let Ok(queue) = PROFILE_QUEUE.lock().as_mut() {
+        queue.push_back(job);
+    }
+}
+
+pub fn get_profile_job() -> Option<ProfileJob> {
+    PROFILE_QUEUE.lock().ok()?.pop_front()
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct RegisterConfig {
     path: String,
     wasm_name: String,
     is_infer: bool,
+    #[serde(default)]
+    group: String, // 公平调度的分组(租户)，默认按模块分组
+    #[serde(default)]
+    weight: u32, // 公平调度的权重，默认1024
+    #[serde(default)]
+    fuel: bool, // 开启fuel计量，记录每次调用消耗的fuel，可以按fuel预算trap
 }
 
 impl RegisterConfig {
@@ -50,9 +69,47 @@ impl RegisterConfig {
             path: path.to_string(),
             wasm_name: wasm_name.to_string(),
             is_infer: false,
+            group: String::new(),
+            weight: 0,
+            fuel: false,
         }
     }
 
+    /// 设置公平调度的分组，同一分组的模块共享虚拟运行时间
+    pub fn set_group(&mut self, group: &str) {
+        self.group = group.to_string();
+    }
+
+    /// 设置公平调度的权重，默认1024
+    pub fn set_weight(&mut self, weight: u32) {
+        self.weight = weight;
+    }
+
+    pub fn get_group(&self) -> &str {
+        if self.group.is_empty() {
+            &self.wasm_name
+        } else {
+            &self.group
+        }
+    }
+
+    pub fn get_weight(&self) -> u32 {
+        if self.weight == 0 {
+            crate::scheduler::NICE_0_WEIGHT
+        } else {
+            self.weight
+        }
+    }
+
+    /// 开启fuel计量，执行会稍慢一些
+    pub fn set_fuel(&mut self) {
+        self.fuel = true;
+    }
+
+    pub fn is_fuel(&self) -> bool {
+        self.fuel
+    }
+
     pub fn set_infer(&mut self) {
         self.is_infer = true;
     }
@@ -76,6 +133,15 @@ pub struct Tester {
     pub result: Arc<FuncResult>,
 }
 
+/// 在测试线程上运行的剖析任务
+pub struct ProfileJob {
+    pub env: Environment,
+    pub conf: FuncConfig,
+    pub iterations: usize,
+    pub interference: u32, // 干扰线程数量
+    pub result: Arc<FuncResult>,
+}
+
 /// wasm环境配置
 #[derive(Clone)]
 pub struct Environment {
@@ -84,13 +150,17 @@ pub struct Environment {
     module: Module,
     linker: Arc<Linker<WasiCtx>>,
     func_config: Option<FuncConfig>,
+    group: String,
+    weight: u32,
+    fuel: bool, // 开启了fuel计量
 }
 
 impl Environment {
     pub async fn new(config: &RegisterConfig) -> Result<Self, Error> {
         // let start = std::time::Instant::now();
         let wasm_name = config.get_wasm_name().to_owned();
-        let engine = Engine::default();
+        // 所有模块共用开启了epoch中断的Engine，开启fuel计量的模块共用另一个
+        let engine = epoch::engine(config.is_fuel()).clone();
         let module = Module::from_file(&engine, config.get_path())?;
 
         let mut linker = Linker::new(&engine);
@@ -137,6 +207,9 @@ impl Environment {
             module,
             linker: Arc::new(linker),
             func_config: None,
+            group: config.get_group().to_owned(),
+            weight: config.get_weight(),
+            fuel: config.is_fuel(),
         })
     }
 
@@ -152,6 +225,54 @@ impl Environment {
         &self.wasm_name
     }
 
+    pub fn get_group(&self) -> &str {
+        &self.group
+    }
+
+    pub fn get_weight(&self) -> u32 {
+        self.weight
+    }
+
+    pub fn is_fuel(&self) -> bool {
+        self.fuel
+    }
+
+    /// 导出函数的签名，没有这个导出函数时返回None
+    pub fn get_func_type(&self, export_func: &str) -> Option<FuncType> {
+        match self.module.get_export(export_func) {
+            Some(ExternType::Func(ty)) => Some(ty),
+            _ => None,
+        }
+    }
+
+    /**
+     * 创建Store
+     * 开启了fuel计量时加入fuel，不指定预算时不限制
+     */
+    pub fn new_store(
+        &self,
+        wasi: WasiCtx,
+        fuel_budget: Option<u64>,
+    ) -> Result<Store<WasiCtx>, Error> {
+        let mut store = epoch::new_store(&self.engine, wasi);
+        if self.fuel {
+            store.add_fuel(fuel_budget.unwrap_or(u64::MAX))?;
+        }
+        Ok(store)
+    }
+
+    pub fn get_engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    pub fn get_module(&self) -> &Module {
+        &self.module
+    }
+
+    pub fn get_linker(&self) -> &Linker<WasiCtx> {
+        &self.linker
+    }
+
     pub fn set_test_time(&mut self, test_time: u64) {
         if let Some(func_config) = &mut self.func_config {
             func_config.set_expected_execution_time(test_time);
@@ -176,30 +297,77 @@ pub struct FuncConfig {
     results: Vec<wasmtime::Val>,
     expected_execution_time: u64, //预期执行时长(必须小于相对截止时间，单位毫秒)
     relative_deadline: u64,       //相对截止时间(单位毫秒)
+    criticality: Criticality,     //关键级别
+    expected_execution_time_hi: u64, //HI级别的预期执行时长(单位毫秒)
+    auto_accept: Option<u64>,     //自动接受反报价的范围(单位毫秒)
+    worker_id: Option<u8>,        //指定的worker
+    wait: bool,                   //不可调度时等待重新准入
+    deadline_at: Option<Instant>, //等待准入时固定的绝对截止时间
+    admission_wcet: Option<Duration>, //准入控制使用的WCET，开启learned_admission时可能是学习到的p99
+    fuel_budget: Option<u64>,     //fuel预算，用完时trap
+    param_class: String,          //参数类别，用于查找剖析结果
+    args: Option<Vec<serde_json::Value>>, //按签名解析的参数，绑定导出函数时转换为params
 }
 
 impl FuncConfig {
     pub fn new(call_config: CallConfigRequest) -> Result<FuncConfig, Error> {
+        let criticality = match Criticality::parse(&call_config.criticality) {
+            Some(criticality) => criticality,
+            None => {
+                return Err(
+                    wasmtime::Error::msg("Invalid_criticality").context("Invalid_criticality")
+                )
+            }
+        };
+        let fuel_budget = if call_config.fuel_budget.is_empty() {
+            None
+        } else {
+            match call_config.fuel_budget.parse::<u64>() {
+                Ok(fuel) if fuel > 0 => Some(fuel),
+                _ => {
+                    return Err(
+                        wasmtime::Error::msg("Invalid_fuel_budget").context("Invalid_fuel_budget")
+                    )
+                }
+            }
+        };
+        let param_class = if call_config.param_class.is_empty() {
+            default_param_class(
+                &call_config.param_type,
+                &call_config.params,
+                &call_config.args,
+            )
+        } else {
+            call_config.param_class.clone()
+        };
+        let args = Some(call_config.args).filter(|args| !args.is_empty());
         match cvt_params(call_config.param_type, call_config.params) {
             Ok(params) => {
-                let results_len = call_config.results_length.parse().unwrap_or(0);
-                let results;
-                if params.len() == 0 {
-                    results = vec![wasmtime::Val::from(0); results_len];
-                } else {
-                    results = vec![wasmtime::Val::from(1.2); results_len];
-                }
                 // tracing::info!("params: {:?}", params);
+                // 返回值在bind时按签名分配
                 let fc = FuncConfig {
                     task_unique_name: call_config.task_unique_name,
                     export_func: call_config.export_func,
                     params,
-                    results,
+                    results: Vec::new(),
                     expected_execution_time: call_config
                         .expected_execution_time
                         .parse::<u64>()
                         .unwrap_or(0),
                     relative_deadline: call_config.expected_deadline.parse::<u64>().unwrap_or(0),
+                    criticality,
+                    expected_execution_time_hi: call_config
+                        .expected_execution_time_hi
+                        .parse::<u64>()
+                        .unwrap_or(0),
+                    auto_accept: call_config.auto_accept.parse::<u64>().ok(),
+                    worker_id: call_config.worker_id.parse::<u8>().ok(),
+                    wait: call_config.wait,
+                    deadline_at: None,
+                    admission_wcet: None,
+                    fuel_budget,
+                    param_class,
+                    args,
                 };
                 Ok(fc)
             }
@@ -208,23 +376,33 @@ impl FuncConfig {
     }
 
     pub fn from(test_config: TestRequest) -> Result<FuncConfig, Error> {
+        let param_class = default_param_class(
+            &test_config.param_type,
+            &test_config.params,
+            &test_config.args,
+        );
+        let args = Some(test_config.args).filter(|args| !args.is_empty());
         match cvt_params(test_config.param_type, test_config.params) {
             Ok(params) => {
-                let results_len = test_config.results_length.parse().unwrap_or(0);
-                let results;
-                if params.len() == 0 {
-                    results = vec![wasmtime::Val::from(0); results_len];
-                } else {
-                    results = vec![params[0].clone(); results_len];
-                }
                 // tracing::info!("params: {:?}", params);
+                // 返回值在bind时按签名分配
                 let fc = FuncConfig {
                     task_unique_name: "anon".to_owned(),
                     export_func: test_config.export_func,
                     params,
-                    results,
+                    results: Vec::new(),
                     expected_execution_time: 0,
                     relative_deadline: test_config.expected_deadline.parse().unwrap_or(0),
+                    criticality: Criticality::LO,
+                    expected_execution_time_hi: 0,
+                    auto_accept: None,
+                    worker_id: None,
+                    wait: false,
+                    deadline_at: None,
+                    admission_wcet: None,
+                    fuel_budget: None,
+                    param_class,
+                    args,
                 };
                 Ok(fc)
             }
@@ -244,18 +422,227 @@ impl FuncConfig {
         self.expected_execution_time = expected_execution_time;
     }
 
+    pub fn set_admission_wcet(&mut self, admission_wcet: Option<Duration>) {
+        self.admission_wcet = admission_wcet;
+    }
+
     pub fn get_expected_execution_time(&self) -> u64 {
         self.expected_execution_time
     }
+
+    pub fn get_criticality(&self) -> Criticality {
+        self.criticality
+    }
+
+    /**
+     * 按导出函数的签名检查参数，分配返回值
+     * 给出了args时按签名解析，否则检查按param_type解析的参数的数量和类型
+     */
+    pub fn bind(&mut self, env: &Environment) -> Result<(), Error> {
+        let ty = env
+            .get_func_type(&self.export_func)
+            .ok_or_else(|| Error::msg(format!("Invalid_export_func: {}", self.export_func)))?;
+        match self.args.take() {
+            Some(args) => {
+                let params: Vec<ValType> = ty.params().collect();
+                self.params = typed_params(&self.export_func, &params, &args)?
+            }
+            None => check_params(&self.export_func, &ty, &self.params)?,
+        }
+        self.results = ty.results().map(zero).collect::<Result<_, _>>()?;
+        Ok(())
+    }
+
+    pub fn set_param_class(&mut self, param_class: &str) {
+        self.param_class = param_class.to_owned();
+    }
+
+    /// 剖析结果的键
+    pub fn get_profile_key(&self, module: &str) -> ProfileKey {
+        ProfileKey {
+            module: module.to_owned(),
+            export: self.export_func.clone(),
+            param_class: self.param_class.clone(),
+        }
+    }
+
+    pub fn get_fuel_budget(&self) -> Option<u64> {
+        self.fuel_budget
+    }
+
+    pub fn is_wait(&self) -> bool {
+        self.wait
+    }
+
+    /**
+     * 固定等待准入的请求的绝对截止时间
+     * 之后每次重新准入使用剩余的相对截止时间
+     */
+    pub fn fix_deadline(&mut self, now: Instant) {
+        if self.wait && self.relative_deadline > 0 && self.deadline_at.is_none() {
+            self.deadline_at = Some(now + Duration::from_millis(self.relative_deadline));
+        }
+    }
+
+    pub fn get_deadline_at(&self) -> Option<Instant> {
+        self.deadline_at
+    }
+
+    /**
+     * 最晚开始时间，与SchedulerStatus::init中的worst_start_time相同
+     * 按准入控制使用的WCET计算，过了这个时间就不可能在截止时间前完成
+     */
+    pub fn get_worst_start_time(&self) -> Option<Instant> {
+        let wcet = self
+            .admission_wcet
+            .unwrap_or(Duration::from_millis(self.expected_execution_time));
+        self.deadline_at.map(|deadline| deadline - wcet)
+    }
+
+    fn can_wait(&self, now: Instant) -> bool {
+        self.wait && self.get_worst_start_time().is_some_and(|t| now <= t)
+    }
+}
+
+/**
+ * 请求正在等待准入，有任务结束后调用者需要重新调用call_func
+ * expected_execution_time是按剖析结果或fuel预算补全后的预期执行时间(毫秒)，
+ * admission_wcet是准入控制使用的WCET，调用者保存的请求需要用它们计算最晚开始时间
+ */
+#[derive(Debug)]
+pub struct Deferred {
+    pub expected_execution_time: u64,
+    pub admission_wcet: Option<Duration>,
+}
+
+impl fmt::Display for Deferred {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Deferred")
+    }
+}
+
+impl std::error::Error for Deferred {}
+
+/// 默认的参数类别，给出了args时按它们的JSON文本区分
+fn default_param_class(param_type: &str, params: &[String], args: &[serde_json::Value]) -> String {
+    if args.is_empty() {
+        profile::param_class(param_type, params)
+    } else {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        profile::param_class("args", &args)
+    }
+}
+
+/**
+ * 按导出函数的签名解析JSON参数
+ * 每个参数可以是数字、数字字符串，或者标注了类型的{"i64": ...}，标注的类型必须和签名一致
+ */
+pub(crate) fn typed_params(
+    export_func: &str,
+    params: &[ValType],
+    args: &[serde_json::Value],
+) -> Result<Vec<Val>, Error> {
+    if args.len() != params.len() {
+        return Err(Error::msg(format!(
+            "Invalid_args: {} expects {} arguments, got {}",
+            export_func,
+            params.len(),
+            args.len()
+        )));
+    }
+    params
+        .iter()
+        .cloned()
+        .zip(args)
+        .enumerate()
+        .map(|(i, (ty, arg))| typed_param(i, ty, arg))
+        .collect()
+}
+
+fn typed_param(i: usize, ty: ValType, arg: &serde_json::Value) -> Result<Val, Error> {
+    use serde_json::Value;
+    let invalid = |msg: String| Error::msg(format!("Invalid_args: args[{}] {}", i, msg));
+    let value = match arg {
+        Value::Object(tagged) if tagged.len() == 1 => {
+            let (tag, value) = tagged.iter().next().unwrap();
+            if !tag.eq_ignore_ascii_case(&ty.to_string()) {
+                return Err(invalid(format!("expects {}, got {}", ty, tag)));
+            }
+            value
+        }
+        value => value,
+    };
+    let text = match value {
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => s.clone(),
+        _ => return Err(invalid(format!("expects {}, got {}", ty, arg))),
+    };
+    // 整数也接受无符号的写法，按位转换
+    let parsed = match ty {
+        ValType::I32 => text
+            .parse::<i32>()
+            .ok()
+            .or_else(|| text.parse::<u32>().ok().map(|v| v as i32))
+            .map(Val::I32),
+        ValType::I64 => text
+            .parse::<i64>()
+            .ok()
+            .or_else(|| text.parse::<u64>().ok().map(|v| v as i64))
+            .map(Val::I64),
+        ValType::F32 => text.parse::<f32>().ok().map(Val::from),
+        ValType::F64 => text.parse::<f64>().ok().map(Val::from),
+        ValType::V128 => text.parse::<u128>().ok().map(Val::from),
+        _ => return Err(invalid(format!("has unsupported type {}", ty))),
+    };
+    parsed.ok_or_else(|| invalid(format!("expects {}, got {}", ty, arg)))
+}
+
+/// 检查按param_type解析的参数是否符合签名
+fn check_params(export_func: &str, ty: &FuncType, params: &[Val]) -> Result<(), Error> {
+    if params.len() != ty.params().len() {
+        return Err(Error::msg(format!(
+            "Invalid_params: {} expects {} arguments, got {}",
+            export_func,
+            ty.params().len(),
+            params.len()
+        )));
+    }
+    for (i, (expected, param)) in ty.params().zip(params).enumerate() {
+        if param.ty() != expected {
+            return Err(Error::msg(format!(
+                "Invalid_params: params[{}] expects {}, got {}",
+                i,
+                expected,
+                param.ty()
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// 导出函数返回值的初始值
+pub(crate) fn zero(ty: ValType) -> Result<Val, Error> {
+    match ty {
+        ValType::I32 => Ok(Val::I32(0)),
+        ValType::I64 => Ok(Val::I64(0)),
+        ValType::F32 => Ok(Val::F32(0)),
+        ValType::F64 => Ok(Val::F64(0)),
+        ValType::V128 => Ok(Val::from(0u128)),
+        _ => Err(Error::msg(format!("Invalid_result_type: {}", ty))),
+    }
 }
 
 /**
  * 参数解析，或许有更好的写法
+ * 没有param_type时没有参数
  */
-fn cvt_params(param_type: String, params: Vec<String>) -> Result<Vec<wasmtime::Val>, Error> {
+pub(crate) fn cvt_params(
+    param_type: String,
+    params: Vec<String>,
+) -> Result<Vec<wasmtime::Val>, Error> {
     let mut res = Vec::new();
     let mut ok = true;
-    if param_type.to_ascii_lowercase() == "void" {
+    if param_type.is_empty() || param_type.to_ascii_lowercase() == "void" {
     } else if param_type.to_ascii_lowercase() == "i32" {
         params.iter().for_each(|param| {
             match param.parse::<i32>() {
@@ -307,12 +694,13 @@ fn cvt_params(param_type: String, params: Vec<String>) -> Result<Vec<wasmtime::V
 pub fn call_func_sync(env: Environment) -> Result<Duration, Error> {
     let start = std::time::Instant::now();
     let mut conf = env.get_func_config().unwrap();
+    conf.bind(&env)?;
     let wasi = WasiCtxBuilder::new()
         .inherit_stdio()
         .inherit_args()?
         .build();
 
-    let mut store = Store::new(&env.engine, wasi);
+    let mut store = env.new_store(wasi, None)?;
     let instance = env.linker.instantiate(&mut store, &env.module)?;
 
     if let Some(caller) = instance.get_func(&mut store, &conf.export_func) {
@@ -329,21 +717,76 @@ pub fn call_func_sync(env: Environment) -> Result<Duration, Error> {
     }
 }
 
+/**
+ * 在当前线程上运行iterations次，每次使用新的实例
+ * 只计算函数调用本身的线程CPU时间，不包括实例化
+ */
+pub fn profile_func(
+    env: &Environment,
+    conf: &FuncConfig,
+    iterations: usize,
+) -> Result<Vec<Duration>, Error> {
+    let cpu_time = || -> Result<Duration, Error> {
+        Ok(nix::time::clock_gettime(nix::time::ClockId::CLOCK_THREAD_CPUTIME_ID)?.into())
+    };
+    let mut samples = Vec::with_capacity(iterations);
+    let mut results = conf.results.clone();
+    for _ in 0..iterations {
+        let wasi = WasiCtxBuilder::new()
+            .inherit_stdio()
+            .inherit_args()?
+            .build();
+        let mut store = env.new_store(wasi, conf.fuel_budget)?;
+        let instance = env.linker.instantiate(&mut store, &env.module)?;
+        let caller = instance
+            .get_func(&mut store, &conf.export_func)
+            .ok_or_else(|| {
+                wasmtime::Error::msg("Invalid_export_func").context("Invalid_export_func")
+            })?;
+        let start = cpu_time()?;
+        caller.call(&mut store, &conf.params, &mut results)?;
+        samples.push(cpu_time()?.saturating_sub(start));
+    }
+    Ok(samples)
+}
+
+/**
+ * 运行剖析任务并保存结果
+ * 测试线程用的
+ */
+pub fn run_profile(rt: &Runtime, job: &ProfileJob) -> Result<ProfileStats, Error> {
+    let mut conf = job.conf.clone();
+    conf.bind(&job.env)?;
+    let samples = {
+        let cgroup = if job.interference > 0 {
+            rt.interference_cgroup()
+        } else {
+            None
+        };
+        let _interference = Interference::start(job.interference, cgroup);
+        profile_func(&job.env, &conf, job.iterations)?
+    };
+    let key = conf.get_profile_key(job.env.get_wasm_name());
+    let stats = ProfileStats::from_samples(key, &samples, job.interference)
+        .ok_or_else(|| Error::msg("no samples"))?;
+    rt.record_profile(stats.clone());
+    Ok(stats)
+}
+
 /**
  * wasm实例化
  */
 fn instantiate(
-    rt: &Runtime,
     env: Environment,
     mut conf: FuncConfig,
     func_result: &Arc<FuncResult>,
-    schedulability_result: SchedulabilityResult,
+    reservation: Reservation,
 ) -> Result<u64, Error> {
     let wasi = WasiCtxBuilder::new()
         .inherit_stdio()
         .inherit_args()?
         .build();
-    let mut store = Store::new(&env.engine, wasi);
+    let mut store = env.new_store(wasi, conf.fuel_budget)?;
     let instance = env.linker.instantiate(&mut store, &env.module)?;
 
     let func_result_1 = func_result.clone();
@@ -351,7 +794,11 @@ fn instantiate(
     // 获取导出函数
     if let Some(caller) = instance.get_func(&mut store, &conf.export_func) {
         // 函数调用
-        let func = move || match caller.call(&mut store, &conf.params, &mut conf.results) {
+        let func = move || match epoch::cooperative(|| {
+            let res = caller.call(&mut store, &conf.params, &mut conf.results);
+            epoch::record_fuel(&store);
+            res
+        }) {
             Ok(_) => {
                 func_result_1.set_result(&format!("{:?}", conf.results));
                 func_result_1.set_completed();
@@ -359,13 +806,20 @@ fn instantiate(
             }
             Err(err) => {
                 tracing::warn!("run_wasm_error: {}", err);
+                epoch::record_trap();
                 func_result_1.set_result(&format!("{:?}", err));
                 func_result_1.set_completed();
                 Err(err)
             }
         };
+        // 任务被取消时通知调用者
+        let func_result_2 = func_result.clone();
+        let on_cancel = move || {
+            func_result_2.set_result("cancelled");
+            func_result_2.set_completed();
+        };
         // 打包成microprocess
-        let id = rt.micro_process(func, schedulability_result);
+        let id = reservation.commit_with_cancel(func, on_cancel);
         match id {
             Ok(id) => {
                 // microprocess成功生成,名字唯一
@@ -393,6 +847,21 @@ pub fn call_func(
     mut conf: FuncConfig,
     func_result: &Arc<FuncResult>,
 ) -> Result<u64, Error> {
+    if conf.fuel_budget.is_some() && !env.is_fuel() {
+        return Err(wasmtime::Error::msg("Invalid_fuel_budget").context("Invalid_fuel_budget"));
+    }
+    conf.bind(&env)?;
+    // 没有给出预期执行时间时，优先使用剖析得到的WCET界，其次按fuel预算和校准结果估计
+    if conf.expected_execution_time == 0 {
+        if let Some(stats) = rt.get_profile(&conf.get_profile_key(env.get_wasm_name())) {
+            conf.expected_execution_time = stats.wcet_us.div_ceil(1000).max(1);
+        }
+    }
+    if let (0, Some(fuel)) = (conf.expected_execution_time, conf.fuel_budget) {
+        if let Some(time) = rt.estimate_fuel_time(fuel, conf.worker_id) {
+            conf.expected_execution_time = time.as_micros().div_ceil(1000).max(1) as u64;
+        }
+    }
     if conf.relative_deadline == 0 || conf.expected_execution_time == 0 {
     } else if conf.relative_deadline <= conf.expected_execution_time {
         return Err(wasmtime::Error::msg("Invalid_deadline").context("Invalid_deadline"));
@@ -404,31 +873,92 @@ pub fn call_func(
         return Err(wasmtime::Error::msg("Invalid_unique_name").context("Invalid_unique_name"));
     }
 
+    // 开启了learned_admission时按学习到的执行时间准入
+    let func = FuncKey::new(env.get_wasm_name(), &conf.export_func);
     let expected_execution_time =
         if conf.relative_deadline == 0 || conf.expected_execution_time == 0 {
             None
         } else {
-            Some(std::time::Duration::from_millis(
-                conf.expected_execution_time,
-            ))
+            Some(rt.admission_wcet(&func, Duration::from_millis(conf.expected_execution_time)))
         };
+    conf.admission_wcet = expected_execution_time;
     let relative_deadline = if conf.relative_deadline == 0 || conf.expected_execution_time == 0 {
         None
     } else {
-        Some(std::time::Duration::from_millis(conf.relative_deadline))
+        // 等待准入的请求截止时间不变
+        Some(match conf.deadline_at {
+            Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+            None => Duration::from_millis(conf.relative_deadline),
+        })
     };
 
-    let res = rt.admission_control_result(expected_execution_time, relative_deadline);
+    let wcet_hi = if conf.expected_execution_time_hi == 0 {
+        None
+    } else {
+        Some(std::time::Duration::from_millis(
+            conf.expected_execution_time_hi,
+        ))
+    };
+    // 预留容量，实例化失败时释放
+    let (group, weight) = (env.get_group().to_owned(), env.get_weight());
+    let reserve = |relative_deadline: Option<Duration>, worker_id: Option<u8>| {
+        rt.try_reserve(
+            expected_execution_time,
+            relative_deadline,
+            conf.criticality,
+            wcet_hi,
+            worker_id,
+        )
+        .with_group(&group, weight)
+        .with_func(func.clone())
+    };
+    let mut res = reserve(relative_deadline, conf.worker_id);
+
+    // 反报价要检查所有worker，只在需要自动接受或者最终拒绝时计算
+    let criticality = conf.criticality;
+    let counter_offer = || {
+        rt.counter_offer(
+            expected_execution_time,
+            relative_deadline,
+            criticality,
+            wcet_hi,
+        )
+    };
+    let mut offered = None;
+    if res.get_ac() == AdmissionControl::UNSCHEDULABLE && conf.auto_accept.is_some() {
+        offered = counter_offer();
+    }
+    // 反报价在允许的范围内时自动接受
+    if let (Some(offer), Some(auto_accept), Some(relative_deadline)) =
+        (offered, conf.auto_accept, relative_deadline)
+    {
+        if offer.relative_deadline <= relative_deadline + Duration::from_millis(auto_accept) {
+            let accepted = reserve(Some(offer.relative_deadline), Some(offer.worker_id));
+            if accepted.get_ac() != AdmissionControl::UNSCHEDULABLE {
+                conf.relative_deadline = offer.relative_deadline.as_micros().div_ceil(1000) as u64;
+                res = accepted;
+            }
+        }
+    }
 
-    if res.get_ac() == AdmissionControl::UNSCHEDULABLE {
-        // 不可调度
+    if res.get_ac() == AdmissionControl::UNSCHEDULABLE && conf.can_wait(Instant::now()) {
+        // 最晚开始时间之前等待重新准入，还没有最终结果，不计数
+        Err(Error::new(Deferred {
+            expected_execution_time: conf.expected_execution_time,
+            admission_wcet: conf.admission_wcet,
+        }))
+    } else if res.get_ac() == AdmissionControl::UNSCHEDULABLE {
+        // 不可调度，返回能够准入的最早截止时间
+        rt.metrics().admission(AdmissionControl::UNSCHEDULABLE);
         let msg = "spawn failed, cause: UNSCHEDULABLE";
+        func_result.set_counter_offer(offered.or_else(counter_offer));
         func_result.set_result(msg);
         func_result.set_completed();
         Err(Error::msg(msg))
     } else {
         // 实例化
-        instantiate(rt, env, conf, func_result, res)
+        rt.metrics().admission(res.get_ac());
+        instantiate(env, conf, func_result, res)
     }
 }
 
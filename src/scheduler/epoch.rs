@@ -0,0 +1,108 @@
+use super::worker::try_get_worker;
+use crate::task::current;
+use once_cell::sync::Lazy;
+use wasmtime::{Config, Engine, Store, UpdateDeadline};
+
+/**
+ * 所有wasm模块共用的Engine，开启epoch中断
+ * 编译后的代码在循环和函数入口检查epoch，超过Store的截止epoch时调用回调，
+ * 回调运行在协程栈上，可以安全地挂起协程或者trap
+ */
+static ENGINE: Lazy<Engine> = Lazy::new(|| new_engine(false));
+
+/// 开启了fuel计量的模块共用的Engine，每条指令消耗fuel，用完时trap
+static FUEL_ENGINE: Lazy<Engine> = Lazy::new(|| new_engine(true));
+
+fn new_engine(fuel: bool) -> Engine {
+    let mut config = Config::new();
+    config.epoch_interruption(true);
+    config.consume_fuel(fuel);
+    Engine::new(&config).expect("could not create wasm engine")
+}
+
+/// wasm任务在epoch检查点超出限制时的处理方式
+#[derive(Clone, Debug, Default)]
+pub struct EpochConfig {
+    pub trap_deadline: bool, // 超过截止时间的实时任务直接trap
+    pub trap_budget: bool,   // 运行时间超过自己关键级别WCET的实时任务直接trap
+}
+
+/// fuel为true时返回开启了fuel计量的Engine
+pub fn engine(fuel: bool) -> &'static Engine {
+    if fuel {
+        &FUEL_ENGINE
+    } else {
+        &ENGINE
+    }
+}
+
+/**
+ * 推进epoch，所有正在运行wasm的Store都会在下一个检查点调用回调
+ * 只有原子操作，可以在信号处理函数中调用，由Worker::defer_signal调用，
+ * 它同时推进worker自己的计数，其他worker的Store在回调中比较计数后直接继续
+ */
+pub fn tick() {
+    for engine in [&ENGINE, &FUEL_ENGINE] {
+        if let Some(engine) = Lazy::get(engine) {
+            engine.increment_epoch();
+        }
+    }
+}
+
+/**
+ * 创建Store，每推进一次epoch调用一次回调
+ * 不在worker上运行，或者epoch是其他worker推进的时候回调直接返回，
+ * 一个worker的信号不会让其他worker上的wasm调用进入调度
+ */
+pub fn new_store<T>(engine: &Engine, data: T) -> Store<T> {
+    let mut store = Store::new(engine, data);
+    store.set_epoch_deadline(1);
+    // 上次处理时worker推进epoch的次数
+    let mut seen = None;
+    store.epoch_deadline_callback(move |_| {
+        if let Some(mut worker) = try_get_worker() {
+            let worker = unsafe { worker.as_mut() };
+            let ticks = worker.get_ticks();
+            if seen != Some(ticks) {
+                seen = Some(ticks);
+                worker.epoch(true)?;
+            }
+        }
+        Ok(UpdateDeadline::Continue(1))
+    });
+    store
+}
+
+/// 把wasm调用消耗的fuel记录到当前任务的状态中
+pub fn record_fuel<T>(store: &Store<T>) {
+    if let (Some(fuel), Some(mut co)) = (store.fuel_consumed(), current()) {
+        unsafe { co.as_mut() }.set_fuel(fuel);
+    }
+}
+
+/// 把wasm调用trap记录到当前任务的状态中
+pub fn record_trap() {
+    if let Some(mut co) = current() {
+        unsafe { co.as_mut() }.set_trapped();
+    }
+}
+
+/**
+ * 运行一次wasm调用
+ * 调用期间worker的信号只推进epoch，调度在epoch回调中进行，
+ * 调用返回后处理期间推迟的信号
+ */
+pub fn cooperative<R>(f: impl FnOnce() -> R) -> R {
+    let set = |cooperative| {
+        if let Some(mut co) = current() {
+            unsafe { co.as_mut() }.set_cooperative(cooperative);
+        }
+    };
+    set(true);
+    let res = f();
+    set(false);
+    if let Some(mut worker) = try_get_worker() {
+        let _ = unsafe { worker.as_mut() }.epoch(false);
+    }
+    res
+}
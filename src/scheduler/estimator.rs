@@ -0,0 +1,168 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::RwLock,
+    time::Duration,
+};
+
+/// 新样本的权重
+const ALPHA: f64 = 0.125;
+/// 分位数草图的相对误差
+const ACCURACY: f64 = 0.01;
+/// 样本太少时分位数不可靠，不参与准入
+pub const MIN_SAMPLES: u64 = 20;
+
+/// 学习结果按模块和导出函数区分
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct FuncKey {
+    pub module: String,
+    pub export: String,
+}
+
+impl FuncKey {
+    pub fn new(module: &str, export: &str) -> FuncKey {
+        FuncKey {
+            module: module.to_owned(),
+            export: export.to_owned(),
+        }
+    }
+}
+
+/// 学习到的执行时间，时间单位微秒
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ExecutionEstimate {
+    #[serde(flatten)]
+    pub key: FuncKey,
+    pub samples: u64,
+    pub ewma_us: u64,
+    pub p50_us: u64,
+    pub p99_us: u64,
+    pub max_us: u64,
+}
+
+impl ExecutionEstimate {
+    /// 样本足够时作为准入预算使用的p99
+    pub fn get_p99(&self) -> Option<Duration> {
+        if self.samples >= MIN_SAMPLES {
+            Some(Duration::from_micros(self.p99_us))
+        } else {
+            None
+        }
+    }
+}
+
+/**
+ * 对数分桶的分位数草图
+ * 第i个桶覆盖(gamma^(i-1), gamma^i]纳秒，估计值的相对误差不超过ACCURACY，
+ * 桶的数量只和运行时间的范围有关
+ */
+#[derive(Default)]
+struct Sketch {
+    buckets: BTreeMap<i32, u64>,
+    count: u64,
+}
+
+impl Sketch {
+    fn gamma() -> f64 {
+        (1.0 + ACCURACY) / (1.0 - ACCURACY)
+    }
+
+    fn add(&mut self, nanos: f64) {
+        let index = (nanos.max(1.0).ln() / Self::gamma().ln()).ceil() as i32;
+        *self.buckets.entry(index).or_insert(0) += 1;
+        self.count += 1;
+    }
+
+    /// 最近秩法的分位数，单位纳秒
+    fn quantile(&self, q: f64) -> f64 {
+        let rank = ((self.count as f64 * q).ceil() as u64).clamp(1, self.count.max(1));
+        let mut seen = 0;
+        for (&index, &n) in &self.buckets {
+            seen += n;
+            if seen >= rank {
+                let gamma = Self::gamma();
+                return 2.0 * gamma.powi(index) / (gamma + 1.0);
+            }
+        }
+        0.0
+    }
+}
+
+struct Learned {
+    ewma: f64, // 纳秒
+    max: Duration,
+    sketch: Sketch,
+}
+
+/**
+ * 按已完成任务的实际运行时间在线学习每个导出函数的执行时间
+ * 每个(模块, 导出函数)保存指数移动平均和分位数草图，
+ * 只在worker完成任务时更新
+ */
+#[derive(Default)]
+pub struct ExecutionEstimator {
+    funcs: RwLock<HashMap<FuncKey, Learned>>,
+}
+
+impl ExecutionEstimator {
+    pub fn new() -> ExecutionEstimator {
+        Self::default()
+    }
+
+    /// 一次调用实际运行了running_time
+    pub fn record(&self, key: &FuncKey, running_time: Duration) {
+        let nanos = running_time.as_nanos() as f64;
+        if let Ok(mut funcs) = self.funcs.write() {
+            match funcs.get_mut(key) {
+                Some(learned) => {
+                    learned.ewma += ALPHA * (nanos - learned.ewma);
+                    learned.max = learned.max.max(running_time);
+                    learned.sketch.add(nanos);
+                }
+                None => {
+                    let mut sketch = Sketch::default();
+                    sketch.add(nanos);
+                    funcs.insert(
+                        key.clone(),
+                        Learned {
+                            ewma: nanos,
+                            max: running_time,
+                            sketch,
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    pub fn get(&self, key: &FuncKey) -> Option<ExecutionEstimate> {
+        let funcs = self.funcs.read().ok()?;
+        funcs.get(key).map(|learned| Self::estimate(key, learned))
+    }
+
+    /// 所有学习结果，按键排序
+    pub fn list(&self) -> Vec<ExecutionEstimate> {
+        let mut estimates: Vec<ExecutionEstimate> = match self.funcs.read() {
+            Ok(funcs) => funcs
+                .iter()
+                .map(|(key, learned)| Self::estimate(key, learned))
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        estimates.sort_by(|a, b| a.key.cmp(&b.key));
+        estimates
+    }
+
+    fn estimate(key: &FuncKey, learned: &Learned) -> ExecutionEstimate {
+        let us = |nanos: f64| (nanos / 1000.0).ceil() as u64;
+        let max_us = learned.max.as_nanos().div_ceil(1000) as u64;
+        ExecutionEstimate {
+            key: key.clone(),
+            samples: learned.sketch.count,
+            ewma_us: us(learned.ewma),
+            p50_us: us(learned.sketch.quantile(0.5)).min(max_us),
+            p99_us: us(learned.sketch.quantile(0.99)).min(max_us),
+            max_us,
+        }
+    }
+}
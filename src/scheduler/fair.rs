@@ -0,0 +1,218 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+
+/// 权重为NICE_0_WEIGHT时，虚拟运行时间等于实际运行时间
+pub const NICE_0_WEIGHT: u32 = 1024;
+
+struct Group<T> {
+    vruntime: u64, // 纳秒
+    weight: u32,
+    queue: VecDeque<T>,
+}
+
+/// 类似CFS的加权公平队列，用于非实时任务
+///
+/// 每个分组(模块或租户)维护一个虚拟运行时间，
+/// 出队时选择虚拟运行时间最小的非空分组，组内先进先出。
+/// 空分组的虚拟运行时间不超过min_vruntime时会被删除，再次入队时从min_vruntime开始
+pub struct FairQueue<T> {
+    groups: HashMap<String, Group<T>>,
+    min_vruntime: u64,
+    len: usize,
+}
+
+impl<T> Default for FairQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> FairQueue<T> {
+    pub fn new() -> FairQueue<T> {
+        FairQueue {
+            groups: HashMap::new(),
+            min_vruntime: 0,
+            len: 0,
+        }
+    }
+
+    /**
+     * 入队
+     * 分组从空变为非空时，虚拟运行时间不小于min_vruntime，避免长时间空闲的分组抢占太久
+     * 添加新分组前删除可以丢弃的空分组，分组的数量不会一直增长
+     */
+    pub fn push(&mut self, group: &str, weight: u32, item: T) {
+        let min_vruntime = self.min_vruntime;
+        if !self.groups.contains_key(group) {
+            self.groups
+                .retain(|_, g| !g.queue.is_empty() || g.vruntime > min_vruntime);
+        }
+        let g = self.groups.entry(group.to_owned()).or_insert(Group {
+            vruntime: min_vruntime,
+            weight,
+            queue: VecDeque::new(),
+        });
+        if g.queue.is_empty() {
+            g.vruntime = g.vruntime.max(min_vruntime);
+        }
+        g.weight = weight.max(1);
+        g.queue.push_back(item);
+        self.len += 1;
+        // 只有一个分组时出队和累计都不会推进min_vruntime，在入队时补上
+        self.update_min_vruntime();
+    }
+
+    /// 出队，选择虚拟运行时间最小的分组
+    pub fn pop(&mut self) -> Option<T> {
+        let (_, g) = self
+            .groups
+            .iter_mut()
+            .filter(|(_, g)| !g.queue.is_empty())
+            .min_by(|(n1, g1), (n2, g2)| g1.vruntime.cmp(&g2.vruntime).then(n1.cmp(n2)))?;
+        let item = g.queue.pop_front();
+        self.len -= 1;
+        self.update_min_vruntime();
+        item
+    }
+
+    /**
+     * 按权重累计分组的虚拟运行时间
+     * 任务运行时分组可能已经作为空分组被删除，这时从min_vruntime开始累计
+     */
+    pub fn charge(&mut self, group: &str, weight: u32, running_time: Duration) {
+        if !self.groups.contains_key(group) {
+            self.groups.insert(
+                group.to_owned(),
+                Group {
+                    vruntime: self.min_vruntime,
+                    weight: weight.max(1),
+                    queue: VecDeque::new(),
+                },
+            );
+        }
+        if let Some(g) = self.groups.get_mut(group) {
+            let delta = running_time.as_nanos() as u64 * NICE_0_WEIGHT as u64 / g.weight as u64;
+            g.vruntime = g.vruntime.saturating_add(delta);
+        }
+        self.update_min_vruntime();
+    }
+
+    fn update_min_vruntime(&mut self) {
+        if let Some(min) = self
+            .groups
+            .values()
+            .filter(|g| !g.queue.is_empty())
+            .map(|g| g.vruntime)
+            .min()
+        {
+            self.min_vruntime = self.min_vruntime.max(min);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// 获取各分组的虚拟运行时间
+    pub fn get_vruntime(&self) -> HashMap<String, Duration> {
+        self.groups
+            .iter()
+            .map(|(name, g)| (name.clone(), Duration::from_nanos(g.vruntime)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    /// 每个分组一直有任务，每次运行1ms后重新入队
+    fn run(
+        queue: &mut FairQueue<&'static str>,
+        weights: &[(&'static str, u32)],
+        rounds: usize,
+    ) -> HashMap<&'static str, usize> {
+        let weight = |group: &str| weights.iter().find(|(g, _)| *g == group).unwrap().1;
+        let mut runs = HashMap::new();
+        for _ in 0..rounds {
+            let group = queue.pop().unwrap();
+            *runs.entry(group).or_insert(0) += 1;
+            queue.charge(group, weight(group), MS);
+            queue.push(group, weight(group), group);
+        }
+        runs
+    }
+
+    #[test]
+    fn weighted_share() {
+        let weights = [
+            ("a", 2 * NICE_0_WEIGHT),
+            ("b", NICE_0_WEIGHT),
+            ("c", NICE_0_WEIGHT / 2),
+        ];
+        let mut queue = FairQueue::new();
+        for (group, weight) in weights {
+            queue.push(group, weight, group);
+        }
+        let runs = run(&mut queue, &weights, 700);
+        // 2 : 1 : 0.5
+        assert!((399..=401).contains(&runs["a"]), "{:?}", runs);
+        assert!((199..=201).contains(&runs["b"]), "{:?}", runs);
+        assert!((99..=101).contains(&runs["c"]), "{:?}", runs);
+        assert_eq!(queue.len(), 3);
+    }
+
+    #[test]
+    fn empty_groups_are_removed() {
+        let mut queue = FairQueue::new();
+        queue.push("idle", NICE_0_WEIGHT, "idle");
+        queue.push("busy", NICE_0_WEIGHT, "busy");
+        // idle运行一次后没有任务
+        assert_eq!(queue.pop(), Some("busy"));
+        queue.charge("busy", NICE_0_WEIGHT, MS);
+        queue.push("busy", NICE_0_WEIGHT, "busy");
+        assert_eq!(queue.pop(), Some("idle"));
+        queue.charge("idle", NICE_0_WEIGHT, MS);
+        run(&mut queue, &[("busy", NICE_0_WEIGHT)], 100);
+        // 新分组入队时删除落后于min_vruntime的空分组
+        queue.push("new", NICE_0_WEIGHT, "new");
+        let vruntime = queue.get_vruntime();
+        assert!(!vruntime.contains_key("idle"), "{:?}", vruntime);
+        assert_eq!(vruntime["new"], vruntime["busy"]);
+
+        // 回来的分组从min_vruntime开始，不会长时间独占
+        queue.push("idle", NICE_0_WEIGHT, "idle");
+        assert_eq!(queue.get_vruntime()["idle"], vruntime["busy"]);
+
+        // 虚拟运行时间超过min_vruntime的空分组保留，空闲不会清掉它的运行时间
+        let mut queue = FairQueue::new();
+        queue.push("hog", NICE_0_WEIGHT, "hog");
+        queue.push("other", NICE_0_WEIGHT, "other");
+        assert_eq!(queue.pop(), Some("hog"));
+        queue.charge("hog", NICE_0_WEIGHT, 10 * MS);
+        queue.push("new", NICE_0_WEIGHT, "new");
+        assert_eq!(queue.get_vruntime()["hog"], 10 * MS);
+        queue.push("hog", NICE_0_WEIGHT, "hog");
+        assert_eq!(queue.pop(), Some("new"));
+        assert_eq!(queue.pop(), Some("other"));
+        assert_eq!(queue.pop(), Some("hog"));
+    }
+
+    #[test]
+    fn charge_after_removal() {
+        let mut queue = FairQueue::new();
+        queue.push("a", NICE_0_WEIGHT, "a");
+        assert_eq!(queue.pop(), Some("a"));
+        // a正在运行时被新分组的入队删除
+        queue.push("b", NICE_0_WEIGHT, "b");
+        assert!(!queue.get_vruntime().contains_key("a"));
+        queue.charge("a", NICE_0_WEIGHT / 2, MS);
+        assert_eq!(queue.get_vruntime()["a"], 2 * MS);
+        queue.push("a", NICE_0_WEIGHT, "a");
+        assert_eq!(queue.pop(), Some("b"));
+    }
+}
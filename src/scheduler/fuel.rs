@@ -0,0 +1,63 @@
+use super::MAX_WORKERS;
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// 新样本的权重
+const ALPHA: f64 = 0.125;
+/// 太少的fuel测不准，不参与校准
+const MIN_FUEL: u64 = 1000;
+
+/**
+ * 每个worker的fuel-时间校准
+ * 按任务实际运行时间和消耗的fuel，用指数移动平均学习每单位fuel的纳秒数，
+ * 只在worker线程完成任务时更新，读取没有锁
+ */
+pub struct FuelCalibration {
+    rates: Vec<AtomicU64>, // f64的位，0表示还没有样本
+}
+
+impl Default for FuelCalibration {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FuelCalibration {
+    pub fn new() -> FuelCalibration {
+        FuelCalibration {
+            rates: (0..MAX_WORKERS).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    /// 一次wasm调用运行了running_time，消耗了fuel
+    pub fn record(&self, worker_id: u8, running_time: Duration, fuel: u64) {
+        let rate = match self.rates.get(worker_id as usize) {
+            Some(rate) if fuel >= MIN_FUEL => rate,
+            _ => return,
+        };
+        let sample = running_time.as_nanos() as f64 / fuel as f64;
+        let next = match self.get(worker_id) {
+            Some(old) => old + ALPHA * (sample - old),
+            None => sample,
+        };
+        rate.store(next.to_bits(), Ordering::Relaxed);
+    }
+
+    /// 每单位fuel的纳秒数，没有样本时返回None
+    pub fn get(&self, worker_id: u8) -> Option<f64> {
+        let bits = self.rates.get(worker_id as usize)?.load(Ordering::Relaxed);
+        if bits == 0 {
+            None
+        } else {
+            Some(f64::from_bits(bits))
+        }
+    }
+
+    /// 在worker上消耗fuel预计的运行时间
+    pub fn estimate(&self, worker_id: u8, fuel: u64) -> Option<Duration> {
+        let rate = self.get(worker_id)?;
+        Some(Duration::from_nanos((rate * fuel as f64).ceil() as u64))
+    }
+}
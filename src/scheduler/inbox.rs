@@ -0,0 +1,148 @@
+use crate::task::Coroutine;
+use crossbeam::queue::SegQueue;
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+/// 收件箱的延迟统计
+#[derive(Clone, Copy, Debug, Default)]
+pub struct InboxLatency {
+    pub submitted: u64,       // 提交的任务数量
+    pub avg_submit: Duration, // 平均提交耗时(入队操作本身)
+    pub max_submit: Duration, // 最大提交耗时
+    pub picked: u64,          // worker取走的任务数量
+    pub avg_pickup: Duration, // 平均取走延迟(入队到worker取走)
+    pub max_pickup: Duration, // 最大取走延迟
+}
+
+#[derive(Default)]
+struct Counter {
+    cnt: AtomicU64,
+    total_ns: AtomicU64,
+    max_ns: AtomicU64,
+}
+
+impl Counter {
+    fn record(&self, time: Duration) {
+        let ns = time.as_nanos() as u64;
+        self.cnt.fetch_add(1, Ordering::Relaxed);
+        self.total_ns.fetch_add(ns, Ordering::Relaxed);
+        self.max_ns.fetch_max(ns, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> (u64, Duration, Duration) {
+        let cnt = self.cnt.load(Ordering::Relaxed);
+        let total = self.total_ns.load(Ordering::Relaxed);
+        let avg = total.checked_div(cnt).unwrap_or(0);
+        (
+            cnt,
+            Duration::from_nanos(avg),
+            Duration::from_nanos(self.max_ns.load(Ordering::Relaxed)),
+        )
+    }
+}
+
+/// 每个worker的实时任务收件箱
+///
+/// 调度器线程无锁地提交已准入的任务，worker取出后放入自己私有的堆中
+#[derive(Default)]
+pub struct Inbox {
+    queue: SegQueue<(Instant, Box<Coroutine>)>,
+    submit: Counter,
+    pickup: Counter,
+}
+
+impl Inbox {
+    pub fn new() -> Inbox {
+        Inbox::default()
+    }
+
+    /// 提交任务，可被多个线程同时调用
+    pub fn push(&self, co: Box<Coroutine>) {
+        let start = Instant::now();
+        self.queue.push((start, co));
+        self.submit.record(start.elapsed());
+    }
+
+    /// 取出任务，只由对应的worker调用
+    pub fn pop(&self) -> Option<Box<Coroutine>> {
+        let (enqueued, co) = self.queue.pop()?;
+        self.pickup.record(enqueued.elapsed());
+        Some(co)
+    }
+
+    pub fn get_latency(&self) -> InboxLatency {
+        let (submitted, avg_submit, max_submit) = self.submit.get();
+        let (picked, avg_pickup, max_pickup) = self.pickup.get();
+        InboxLatency {
+            submitted,
+            avg_submit,
+            max_submit,
+            picked,
+            avg_pickup,
+            max_pickup,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task::stack::StackSize;
+    use std::collections::HashSet;
+
+    const PRODUCERS: usize = 4;
+    const TASKS: usize = 250;
+
+    #[test]
+    fn concurrent_submit_picks_each_task_once() {
+        let inbox = Inbox::new();
+        let latency = inbox.get_latency();
+        assert_eq!((latency.submitted, latency.picked), (0, 0));
+        let mut submitted = HashSet::new();
+        let mut picked = HashSet::new();
+        std::thread::scope(|s| {
+            let producers: Vec<_> = (0..PRODUCERS)
+                .map(|_| {
+                    s.spawn(|| {
+                        (0..TASKS)
+                            .map(|_| {
+                                let co = Coroutine::new(
+                                    Box::new(|| {}),
+                                    StackSize::default(),
+                                    false,
+                                    None,
+                                    None,
+                                );
+                                let id = co.get_co_id();
+                                inbox.push(co);
+                                id
+                            })
+                            .collect::<Vec<u64>>()
+                    })
+                })
+                .collect();
+            // 提交的同时取走
+            while picked.len() < PRODUCERS * TASKS {
+                match inbox.pop() {
+                    Some(co) => assert!(picked.insert(co.get_co_id()), "picked twice"),
+                    None => std::thread::yield_now(),
+                }
+            }
+            for producer in producers {
+                submitted.extend(producer.join().unwrap());
+            }
+        });
+        assert!(inbox.pop().is_none());
+        assert_eq!(submitted.len(), PRODUCERS * TASKS);
+        assert_eq!(picked, submitted);
+
+        let latency = inbox.get_latency();
+        assert_eq!(latency.submitted, (PRODUCERS * TASKS) as u64);
+        assert_eq!(latency.picked, (PRODUCERS * TASKS) as u64);
+        assert!(latency.max_submit >= latency.avg_submit);
+        assert!(latency.max_pickup >= latency.avg_pickup);
+        assert!(latency.max_pickup > Duration::ZERO);
+    }
+}
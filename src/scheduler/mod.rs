@@ -1,38 +1,107 @@
 use crate::{
     cgroupv2,
-    scheduler::worker::{get_worker, Worker},
-    task::{current, Coroutine, SchedulerStatus},
+    cpulist::{CpuLayout, CpuList, CpuTopology},
+    history::{History, HistoryRecord},
+    metrics::{Encoder, Metrics},
+    runtime::{LoPolicy, RetireMode, ShutdownMode},
+    scheduler::{
+        epoch::EpochConfig,
+        estimator::ExecutionEstimator,
+        fair::FairQueue,
+        fuel::FuelCalibration,
+        inbox::{Inbox, InboxLatency},
+        trace::{TraceKind, Tracer},
+        watchdog::WatchdogConfig,
+        worker::{get_worker, try_get_worker, Worker},
+    },
+    task::{current, CoStatus, Coroutine, Criticality, SchedulerStatus},
+    StackSize,
 };
+use anyhow::Error;
 use chrono::{DateTime, Local};
+use crossbeam::queue::SegQueue;
 use nix::{
     sys::{
-        signal::{self, SigEvent, SigHandler, SigevNotify, Signal},
+        signal::{SigEvent, SigevNotify, Signal},
         timer::Timer,
         timer::{Expiration, TimerSetTimeFlags},
     },
     time::ClockId,
-    unistd::gettid,
+    unistd::{gettid, Pid},
 };
+use once_cell::sync::OnceCell;
 use std::{
     cell::Cell,
-    collections::{BTreeMap, BinaryHeap, HashMap, VecDeque},
+    collections::{BTreeMap, BTreeSet, HashMap},
     convert::TryFrom,
+    path::PathBuf,
     ptr,
     sync::{
-        atomic::{AtomicU64, Ordering},
+        atomic::{AtomicU32, AtomicU64, AtomicU8, Ordering},
         Arc, Mutex, RwLock,
     },
     thread::{self, JoinHandle},
     time::{Duration, Instant},
 };
 
+pub mod epoch;
+pub mod estimator;
+pub mod fair;
+pub mod fuel;
+pub mod inbox;
+pub mod trace;
+pub mod watchdog;
 pub mod worker;
+pub use fair::NICE_0_WEIGHT;
+/// 工作核心数量的上限，每个worker的状态在启动时预先分配
+pub const MAX_WORKERS: u8 = 64;
+/// 默认的抢占信号
 pub const PREEMPTY: Signal = Signal::SIGURG;
+/// 默认的定时器信号
 pub const SIG: Signal = Signal::SIGALRM;
+/// 默认的worker本地队列容量
+pub const WORKER_CAPACITY: usize = 256;
+/// 默认保留的已完成任务状态数量
+pub const COMPLETED_CAPACITY: usize = 100000;
+/// 默认的cgroup根目录，进程所在的cgroup，所有Runtime共用，每个Runtime在下面有自己的threaded子树
+pub const CG_ROOT: &str = "/sys/fs/cgroup/hyperwasm";
 
 thread_local! {
     static TIMER: Cell<Option<ptr::NonNull<LocalTimer>>> = Cell::new(None);
-    static START: Cell<Option<(Instant, DateTime<Local>)>> = Cell::new(None);
+}
+
+/// 第一个Runtime启动的时间，用于把Instant换算成本地时间
+static START: OnceCell<(Instant, DateTime<Local>)> = OnceCell::new();
+/// 创建过的Runtime数量，用于命名cgroup子树
+static INSTANCES: AtomicU32 = AtomicU32::new(0);
+/// 正在使用的Runtime名字，名字决定cgroup子树，不能重复
+static NAMES: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+/// 每个cgroup根目录下的Runtime数量，最后一个关闭时删除根目录
+static CG_USERS: Mutex<BTreeMap<PathBuf, usize>> = Mutex::new(BTreeMap::new());
+/// 信号处理函数是进程级的，每个信号只安装一次
+static SIGNALS: Mutex<BTreeSet<i32>> = Mutex::new(BTreeSet::new());
+
+/**
+ * 登记Runtime的名字，None时生成一个没有被使用的runtime{n}
+ * 名字已经被使用时返回错误，Scheduler被drop时释放
+ */
+pub fn claim_name(name: Option<&str>) -> Result<String, Error> {
+    let mut names = NAMES.lock().unwrap();
+    if let Some(name) = name {
+        if !names.insert(name.to_owned()) {
+            return Err(Error::msg(format!(
+                "runtime name {:?} is already in use",
+                name
+            )));
+        }
+        return Ok(name.to_owned());
+    }
+    loop {
+        let name = format!("runtime{}", INSTANCES.fetch_add(1, Ordering::Relaxed));
+        if names.insert(name.clone()) {
+            return Ok(name);
+        }
+    }
 }
 
 fn get_timer() -> ptr::NonNull<LocalTimer> {
@@ -40,38 +109,98 @@ fn get_timer() -> ptr::NonNull<LocalTimer> {
 }
 
 pub fn init_start() {
-    START.with(|cell| {
-        assert!(cell.get().is_none());
-        cell.set(Some((Instant::now(), Local::now())));
-    });
+    get_start();
 }
 
 pub fn get_start() -> (Instant, DateTime<Local>) {
-    START.with(|cell| cell.get()).expect("no start")
+    *START.get_or_init(|| (Instant::now(), Local::now()))
+}
+
+/**
+ * 安装抢占信号和定时器信号的处理函数
+ * 信号发给指定的worker线程，处理函数通过线程局部的Worker找到所属的Runtime
+ */
+fn install_signal_handlers(signals: &[Signal]) {
+    let mut installed = SIGNALS.lock().unwrap();
+    for signal in signals {
+        if !installed.insert(*signal as i32) {
+            continue;
+        }
+        let sa = libc::sigaction {
+            sa_sigaction: signal_handler as libc::sighandler_t,
+            sa_mask: unsafe { std::mem::zeroed() },
+            sa_flags: libc::SA_SIGINFO | libc::SA_RESTART,
+            sa_restorer: None,
+        };
+        unsafe {
+            libc::sigaction(*signal as i32, &sa, std::ptr::null_mut());
+        }
+    }
+}
+
+/// Scheduler的配置，由RuntimeBuilder检查后生成
+#[derive(Clone, Debug)]
+pub struct SchedulerConfig {
+    pub name: Option<String>, // cgroup子树的名字，默认runtime{n}，由claim_name登记
+    pub layout: CpuLayout,    // 各线程的CPU
+    pub worker_capacity: usize, // worker本地队列容量
+    pub completed_capacity: usize, // 保留的已完成任务状态数量
+    pub stack_size: StackSize, // 协程的栈大小
+    pub preempt_signal: Signal, // 抢占信号
+    pub timer_signal: Signal, // 定时器信号
+    pub cgroup_root: PathBuf, // cgroup根目录
+    pub history: Option<Arc<History>>, // 已完成任务的持久化历史，None表示只保留在内存中
+    pub watchdog: Option<WatchdogConfig>, // 看门狗，None表示不启动
+    pub epoch: EpochConfig,   // wasm任务在epoch检查点的处理方式
+    pub learned_admission: bool, // 准入时使用声明的和学习到的p99中较大的执行时间
+}
+
+impl Drop for Scheduler {
+    fn drop(&mut self) {
+        if let Ok(names) = NAMES.lock().as_mut() {
+            names.remove(&self.name);
+        }
+    }
+}
+
+impl SchedulerConfig {
+    /// 除CPU以外都使用默认值
+    pub fn new(layout: CpuLayout) -> SchedulerConfig {
+        SchedulerConfig {
+            name: None,
+            layout,
+            worker_capacity: WORKER_CAPACITY,
+            completed_capacity: COMPLETED_CAPACITY,
+            stack_size: StackSize::default(),
+            preempt_signal: PREEMPTY,
+            timer_signal: SIG,
+            cgroup_root: PathBuf::from(CG_ROOT),
+            history: None,
+            watchdog: None,
+            epoch: EpochConfig::default(),
+            learned_admission: false,
+        }
+    }
 }
 
 struct LocalTimer {
     timer: Timer,
-    expiration: Expiration,
+    quantum: Duration, // 非实时任务的时间片
 }
 
 impl LocalTimer {
-    /// create thread local timer
+    /// create thread local one-shot timer
     ///
-    /// expiration 0: disable timer
+    /// quantum 0: no time slice for best-effort tasks
     ///
-    /// others: enable timer
-    fn new(thread_id: i32, expiration: u64, signal: Signal, timer_type: usize) -> LocalTimer {
-        // let thread_id = get_thread_id();
-        let mut exp = Expiration::Interval(Duration::from_nanos(expiration).into());
-
-        if timer_type == 1 {
-            exp = Expiration::OneShot(Duration::from_nanos(expiration).into());
-        }
-        let timer = Self::set_timer(thread_id, exp, signal);
+    /// others: time slice in nanoseconds
+    ///
+    /// The timer starts disarmed, the worker arms it for its next scheduling event.
+    fn new(thread_id: i32, quantum: u64, signal: Signal) -> LocalTimer {
+        let timer = Self::set_timer(thread_id, signal);
         LocalTimer {
             timer,
-            expiration: exp,
+            quantum: Duration::from_nanos(quantum),
         }
     }
 
@@ -80,7 +209,7 @@ impl LocalTimer {
         TIMER.with(|t| t.set(Some(timer)));
     }
 
-    fn set_timer(tid: i32, expiration: Expiration, signal: Signal) -> Timer {
+    fn set_timer(tid: i32, signal: Signal) -> Timer {
         let clockid = ClockId::CLOCK_MONOTONIC;
         let sigevent = SigEvent::new(SigevNotify::SigevThreadId {
             signal,
@@ -88,192 +217,648 @@ impl LocalTimer {
             thread_id: tid,
         });
 
-        let mut timer = Timer::new(clockid, sigevent).unwrap();
-        let flags = TimerSetTimeFlags::empty();
-        timer.set(expiration, flags).expect("could not set timer");
-
-        let handler = SigHandler::Handler(signal_handler);
-        unsafe { signal::signal(signal, handler) }.unwrap();
-        timer
+        Timer::new(clockid, sigevent).unwrap()
     }
 
-    pub fn reset_timer(&mut self) {
-        if self.expiration != Expiration::Interval(Duration::from_nanos(0).into()) {
-            let flags = TimerSetTimeFlags::empty();
-            self.timer
-                .set(self.expiration, flags)
-                .expect("could not set timer");
-            // tracing::info!("reset timer");
+    /// 非实时任务的时间片，0表示不轮转
+    pub fn get_quantum(&self) -> Option<Duration> {
+        if self.quantum.is_zero() {
+            None
+        } else {
+            Some(self.quantum)
         }
     }
+
+    /**
+     * 设置下一次调度事件的单次定时器
+     * None表示没有需要处理的事件，关闭定时器
+     */
+    pub fn arm(&mut self, after: Option<Duration>) {
+        let after = match after {
+            // it_value为0会关闭定时器，至少设置1纳秒
+            Some(after) => after.max(Duration::from_nanos(1)),
+            // it_value为0时关闭定时器
+            None => Duration::ZERO,
+        };
+        let flags = TimerSetTimeFlags::empty();
+        self.timer
+            .set(Expiration::OneShot(after.into()), flags)
+            .expect("could not set timer");
+    }
 }
 
 pub struct Scheduler {
+    name: String, // cgroup子树的名字
     worker_threads: u8,
-    start_cpu: u8,
-    ava_time: HashMap<u8, RwLock<HashMap<u64, f64>>>, // 任务结束后及时删除
+    worker_capacity: usize, // worker本地队列容量
+    stack_size: StackSize,  // 协程的栈大小
+    preempt_signal: Signal, // 抢占信号
+    timer_signal: Signal,   // 定时器信号
+    cgroup_root: PathBuf,   // cgroup根目录
+    layout: CpuLayout,      // 启动时各线程的CPU
     slots: HashMap<u8, RwLock<Option<Box<Coroutine>>>>,
-    realtime_queue: HashMap<u8, Mutex<BinaryHeap<Box<Coroutine>>>>,
-    global_queue: Mutex<VecDeque<Box<Coroutine>>>,
+    realtime_queue: HashMap<u8, Inbox>, // 每个worker的无锁收件箱
+    global_queue: Mutex<FairQueue<Box<Coroutine>>>,
     co_status: HashMap<u8, RwLock<BTreeMap<u64, SchedulerStatus>>>,
     completed_status: Mutex<lru::LruCache<u64, SchedulerStatus>>,
     curr_running_id: HashMap<u8, AtomicU64>,
     pthread_ids: RwLock<HashMap<u8, nix::sys::pthread::Pthread>>,
+    crit_mode: HashMap<u8, AtomicU8>,     // 每个worker的关键级别模式
+    lo_policy: RwLock<LoPolicy>,          // HI模式下LO任务的处理方式
+    active_workers: RwLock<BTreeSet<u8>>, // 正在运行的worker
+    retiring: RwLock<HashMap<u8, RetireMode>>, // 正在退役的worker
+    shutdown: RwLock<Option<ShutdownMode>>, // 关闭方式，关闭后不再准入任务
+    worker_cpus: RwLock<HashMap<u8, CpuList>>, // worker绑定的CPU
+    worker_tids: RwLock<HashMap<u8, Pid>>,
+    migrated: SegQueue<Box<Coroutine>>, // 退役worker交还的任务，等待重新准入
+    tracer: Tracer,                     // 调度事件记录
+    history: Option<Arc<History>>,      // 已完成任务的持久化历史
+    metrics: Metrics,                   // 调度指标
+    watchdog: Option<WatchdogConfig>,   // 看门狗的配置
+    running_since: HashMap<u8, AtomicU64>, // 当前任务这次开始运行的时间，0表示空闲
+    watchdog_target: HashMap<u8, AtomicU64>, // 看门狗要求处理的任务
+    epoch: EpochConfig,                 // wasm任务在epoch检查点的处理方式
+    fuel: FuelCalibration,              // 每个worker的fuel-时间校准
+    estimator: ExecutionEstimator,      // 按已完成任务学习的执行时间
+    learned_admission: bool,            // 准入时使用学习到的执行时间
+    completions: AtomicU64,             // 结束或取消的任务数量，有变化时重新测试等待准入的任务
 }
 
 unsafe impl Send for Scheduler {}
 unsafe impl Sync for Scheduler {}
 
 impl Scheduler {
-    pub fn new(mut worker_threads: u8, start_cpu: u8) -> Arc<Scheduler> {
+    pub fn new(mut worker_threads: u8, start_cpu: u16) -> Arc<Scheduler> {
         if worker_threads == 0 {
             worker_threads = 1;
         }
-        let mut ava_time = HashMap::new();
+        Self::with_layout(
+            CpuLayout::contiguous(worker_threads, start_cpu).expect("start_cpu out of range"),
+        )
+    }
+
+    /// 按CpuLayout创建，每个worker使用一个CPU集合
+    pub fn with_layout(layout: CpuLayout) -> Arc<Scheduler> {
+        Self::with_config(SchedulerConfig::new(layout))
+    }
+
+    pub fn with_config(config: SchedulerConfig) -> Arc<Scheduler> {
+        let SchedulerConfig {
+            name,
+            layout,
+            worker_capacity,
+            completed_capacity,
+            stack_size,
+            preempt_signal,
+            timer_signal,
+            cgroup_root,
+            history,
+            watchdog,
+            epoch,
+            learned_admission,
+        } = config;
+        let worker_threads = layout.workers.len().clamp(1, MAX_WORKERS as usize) as u8;
         let mut slots = HashMap::new();
         let mut realtime_queue = HashMap::new();
         let mut co_status = HashMap::new();
         let mut curr_running_id = HashMap::new();
-        for i in 0..worker_threads {
-            ava_time.insert(i, RwLock::new(HashMap::new()));
+        let mut crit_mode = HashMap::new();
+        let mut running_since = HashMap::new();
+        let mut watchdog_target = HashMap::new();
+        // 预先分配所有可能的worker的状态，运行时增减worker不需要修改这些map
+        for i in 0..MAX_WORKERS {
             slots.insert(i, RwLock::new(None));
-            realtime_queue.insert(i, Mutex::new(BinaryHeap::new()));
+            realtime_queue.insert(i, Inbox::new());
             co_status.insert(i, RwLock::new(BTreeMap::new()));
             curr_running_id.insert(i, AtomicU64::new(0));
+            crit_mode.insert(i, AtomicU8::new(Criticality::LO as u8));
+            running_since.insert(i, AtomicU64::new(0));
+            watchdog_target.insert(i, AtomicU64::new(0));
         }
         Arc::new(Scheduler {
+            name: match name {
+                Some(name) => {
+                    NAMES.lock().unwrap().insert(name.clone());
+                    name
+                }
+                None => claim_name(None).unwrap(),
+            },
             worker_threads,
-            start_cpu,
-            ava_time,
+            worker_capacity: worker_capacity.max(1),
+            stack_size,
+            preempt_signal,
+            timer_signal,
+            cgroup_root,
+            layout,
             slots,
             realtime_queue,
-            global_queue: Mutex::new(VecDeque::new()),
+            global_queue: Mutex::new(FairQueue::new()),
             co_status,
             completed_status: Mutex::new(lru::LruCache::new(
-                std::num::NonZeroUsize::new(100000).unwrap(),
+                std::num::NonZeroUsize::new(completed_capacity.max(1)).unwrap(),
             )),
             curr_running_id,
             pthread_ids: RwLock::new(HashMap::new()),
+            crit_mode,
+            lo_policy: RwLock::new(LoPolicy::default()),
+            active_workers: RwLock::new(BTreeSet::new()),
+            retiring: RwLock::new(HashMap::new()),
+            shutdown: RwLock::new(None),
+            worker_cpus: RwLock::new(HashMap::new()),
+            worker_tids: RwLock::new(HashMap::new()),
+            migrated: SegQueue::new(),
+            tracer: Tracer::new(),
+            history,
+            metrics: Metrics::new(),
+            watchdog,
+            running_since,
+            watchdog_target,
+            epoch,
+            fuel: FuelCalibration::new(),
+            estimator: ExecutionEstimator::new(),
+            learned_admission,
+            completions: AtomicU64::new(0),
         })
     }
 
-    pub fn start(self: &Arc<Scheduler>, timer_exp: u64) -> Vec<JoinHandle<()>> {
-        self.create_cg(self.worker_threads);
-        let scheduler = self.clone();
+    pub fn start(self: &Arc<Scheduler>, timer_exp: u64) -> BTreeMap<u8, JoinHandle<()>> {
+        self.create_cg();
         init_start();
-        let mut v = Vec::new();
+        let mut v = BTreeMap::new();
         for i in 0..self.worker_threads {
-            let scheduler = scheduler.clone();
-            let t = thread::spawn(move || {
-                let pthreadtid = nix::sys::pthread::pthread_self();
-
-                scheduler.set_pthread_ids(i, pthreadtid);
-                let sa = libc::sigaction {
-                    sa_sigaction: signal_handler as libc::sighandler_t,
-                    sa_mask: unsafe { std::mem::zeroed() },
-                    sa_flags: libc::SA_SIGINFO | libc::SA_RESTART,
-                    sa_restorer: None,
-                };
-                unsafe {
-                    libc::sigaction(libc::SIGURG, &sa, std::ptr::null_mut());
+            let cpu = self.layout.workers[i as usize].clone();
+            let t = self.spawn_worker(i, cpu, timer_exp);
+            v.insert(i, t);
+        }
+        if let Some(config) = self.watchdog.clone() {
+            watchdog::spawn(Arc::downgrade(self), config);
+        }
+        v
+    }
+
+    /**
+     * 模拟运行，只登记worker，不创建线程和cgroup
+     */
+    pub fn start_simulated(&self) {
+        for i in 0..self.worker_threads {
+            self.activate_worker(i, self.layout.workers[i as usize].clone());
+        }
+    }
+
+    /**
+     * 创建worker线程
+     * worker退出后会从active_workers中移除
+     */
+    pub fn spawn_worker(
+        self: &Arc<Scheduler>,
+        worker_id: u8,
+        cpu: CpuList,
+        timer_exp: u64,
+    ) -> JoinHandle<()> {
+        self.activate_worker(worker_id, cpu);
+        let scheduler = self.clone();
+        thread::spawn(move || {
+            let pthreadtid = nix::sys::pthread::pthread_self();
+
+            scheduler.set_pthread_ids(worker_id, pthreadtid);
+            install_signal_handlers(&[scheduler.preempt_signal, scheduler.timer_signal]);
+
+            let w = Worker::new(&scheduler, scheduler.worker_capacity, worker_id);
+            let tid = gettid();
+            if let Ok(tids) = scheduler.worker_tids.write().as_mut() {
+                tids.insert(worker_id, tid);
+            }
+            w.set_cgroup(tid);
+            w.init();
+            let w = unsafe { get_worker().as_mut() };
+
+            // 设置线程定时器，由worker按下一次调度事件设置
+            let timer = LocalTimer::new(tid.into(), timer_exp * 1000, scheduler.timer_signal);
+            timer.init();
+            w.run();
+            // worker退役
+            scheduler.remove_worker(worker_id);
+        })
+    }
+
+    /**
+     * 登记worker，之后可以被准入控制选择
+     * 模拟运行时没有worker线程，只登记worker
+     */
+    pub fn activate_worker(&self, worker_id: u8, cpu: CpuList) {
+        self.tracer.init_ring(worker_id);
+        self.metrics.worker_started(worker_id);
+        if let Ok(cpus) = self.worker_cpus.write().as_mut() {
+            cpus.insert(worker_id, cpu);
+        }
+        if let Ok(active) = self.active_workers.write().as_mut() {
+            active.insert(worker_id);
+        }
+    }
+
+    /**
+     * 分配一个空闲的worker id和CPU
+     * cpu为None时选择一个没有被使用的物理核心，物理核心都被使用时选择空闲的逻辑CPU
+     * 在worker_cpus的写锁中登记分配结果，并发的分配不会得到相同的id或CPU
+     */
+    pub fn alloc_worker(&self, cpu: Option<CpuList>) -> Result<(u8, CpuList), Error> {
+        let topology = CpuTopology::read();
+        let mut cpus = self
+            .worker_cpus
+            .write()
+            .map_err(|_| Error::msg("worker cpus poisoned"))?;
+        let active = self
+            .active_workers
+            .read()
+            .map(|a| a.clone())
+            .unwrap_or_default();
+        // 已分配但还没有启动的worker也在worker_cpus中
+        let worker_id = (0..MAX_WORKERS)
+            .find(|id| !active.contains(id) && !cpus.contains_key(id))
+            .ok_or_else(|| Error::msg("too many workers"))?;
+        let cpu = match cpu {
+            Some(cpu) => {
+                if let Ok(topology) = &topology {
+                    let online = |cpu: u16| topology.get_cpus().iter().any(|info| info.cpu == cpu);
+                    if let Some(offline) = cpu.iter().find(|cpu| !online(*cpu)) {
+                        return Err(Error::msg(format!("cpu {} is not online", offline)));
+                    }
                 }
+                cpu
+            }
+            None => {
+                let topology = topology?;
+                let used = cpus
+                    .values()
+                    .fold(self.layout.all(), |used, cpu| used.union(cpu));
+                let free = topology
+                    .physical_cores()
+                    .into_values()
+                    .flatten()
+                    .chain(topology.get_cpus().iter().map(|info| info.cpu))
+                    .find(|cpu| !used.contains(*cpu))
+                    .ok_or_else(|| Error::msg("no free cpu"))?;
+                CpuList::single(free)
+            }
+        };
+        cpus.insert(worker_id, cpu.clone());
+        Ok((worker_id, cpu))
+    }
 
-                let w = Worker::new(&scheduler, 256, i);
-                let tid = gettid();
-                w.set_cgroup(tid);
-                w.init();
-                let w = unsafe { get_worker().as_mut() };
+    /// 已经被使用的CPU
+    fn used_cpus(&self) -> CpuList {
+        let used = self.layout.all();
+        if let Ok(cpus) = self.worker_cpus.read() {
+            cpus.values().fold(used, |used, cpu| used.union(cpu))
+        } else {
+            used
+        }
+    }
 
-                // 设置线程定时器
-                let timer = LocalTimer::new(tid.into(), timer_exp * 1000, SIG, 3);
-                timer.init();
-                w.run();
-            });
-            v.push(t);
+    /**
+     * 扩大hyperwasm的cpuset，使其包含新worker的CPU
+     */
+    pub fn extend_cpuset(&self, cpu: &CpuList) {
+        let cpus = self.used_cpus().union(cpu);
+        let hyperwasm = self.cg_domain();
+        let domain = hyperwasm.cpus().unwrap_or_default().union(&cpus);
+        let cg_runtime = cgroupv2::Controllerv2::new(self.cgroup_root.clone(), self.name.clone());
+        for res in [hyperwasm.set_cpus(&domain), cg_runtime.set_cpus(&cpus)] {
+            if let Err(err) = res {
+                tracing::warn!("failed to extend cpuset: {}", err);
+            }
         }
-        v
     }
 
-    fn create_cg(&self, worker_threads: u8) {
-        let hyperwasm = cgroupv2::Controllerv2::new(
-            std::path::PathBuf::from("/sys/fs/cgroup"),
-            String::from("hyperwasm"),
-        );
-        hyperwasm.set_sub_controller(
-            vec![
-                cgroupv2::ControllerType::CPU,
-                cgroupv2::ControllerType::CPUSET,
-            ],
-            None,
+    /**
+     * 剖析时干扰线程使用的cgroup，和测试线程一样在hyperwasm下
+     * 使用与测试线程共享末级缓存、没有被任何线程使用的CPU，
+     * 没有这样的CPU或者不能设置cpuset时返回None
+     */
+    pub fn interference_cgroup(&self) -> Option<cgroupv2::Controllerv2> {
+        let topology = CpuTopology::read().ok()?;
+        let used = self.used_cpus();
+        let mut cpus = CpuList::new();
+        for cpu in topology.llc_siblings(&self.layout.tester).iter() {
+            if !used.contains(cpu) {
+                cpus.insert(cpu);
+            }
+        }
+        if cpus.is_empty() {
+            return None;
+        }
+        let hyperwasm = self.cg_domain();
+        let domain = hyperwasm
+            .cpus()
+            .unwrap_or_default()
+            .union(&used)
+            .union(&cpus);
+        let cg = cgroupv2::Controllerv2::new(
+            self.cgroup_root.clone(),
+            format!("{}-interference", self.name),
         );
-        hyperwasm.set_cpuset(self.start_cpu, Some(self.start_cpu + worker_threads + 2));
-        hyperwasm.set_cgroup_procs(nix::unistd::gettid());
+        cg.set_threaded();
+        for res in [hyperwasm.set_cpus(&domain), cg.set_cpus(&cpus)] {
+            if let Err(err) = res {
+                tracing::warn!("failed to set cpus of interference: {}", err);
+                return None;
+            }
+        }
+        Some(cg)
+    }
 
-        let cg_main = cgroupv2::Controllerv2::new(
-            std::path::PathBuf::from("/sys/fs/cgroup/hyperwasm"),
-            String::from("main"),
-        );
-        cg_main.set_threaded();
-        cg_main.set_cpuset(self.start_cpu, None);
-        cg_main.set_cgroup_threads(nix::unistd::gettid());
+    /// 所有Runtime共用的cgroup，drop时会尝试删除
+    fn cg_domain(&self) -> cgroupv2::Controllerv2 {
+        let base = self.cgroup_root.parent().unwrap_or(&self.cgroup_root);
+        let name = self.cgroup_root.file_name().unwrap_or_default();
+        cgroupv2::Controllerv2::new(base.to_path_buf(), name.to_string_lossy().into_owned())
     }
 
-    fn set_pthread_ids(&self, worker_id: u8, pthread_id: nix::sys::pthread::Pthread) {
-        if let Ok(ids) = self.pthread_ids.write().as_mut() {
-            ids.insert(worker_id, pthread_id);
+    pub fn get_cgroup_root(&self) -> &std::path::Path {
+        &self.cgroup_root
+    }
+
+    pub fn get_stack_size(&self) -> StackSize {
+        self.stack_size
+    }
+
+    pub fn get_preempt_signal(&self) -> Signal {
+        self.preempt_signal
+    }
+
+    pub fn get_timer_signal(&self) -> Signal {
+        self.timer_signal
+    }
+
+    pub fn get_fuel_calibration(&self) -> &FuelCalibration {
+        &self.fuel
+    }
+
+    pub fn get_epoch(&self) -> &EpochConfig {
+        &self.epoch
+    }
+
+    pub fn get_estimator(&self) -> &ExecutionEstimator {
+        &self.estimator
+    }
+
+    pub fn is_learned_admission(&self) -> bool {
+        self.learned_admission
+    }
+
+    /// Runtime的名字，也是cgroup子树的名字
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Runtime的cgroup子树
+    pub fn get_cg_path(&self) -> PathBuf {
+        self.cgroup_root.join(&self.name)
+    }
+
+    /// 标记worker退役，不再接收新任务
+    pub fn set_retiring(&self, worker_id: u8, mode: RetireMode) {
+        if let Ok(retiring) = self.retiring.write().as_mut() {
+            retiring.insert(worker_id, mode);
         }
     }
 
-    pub fn get_pthread_id(&self, worker_id: u8) -> Option<nix::sys::pthread::Pthread> {
-        if let Ok(ids) = self.pthread_ids.read() {
-            ids.get(&worker_id).copied()
+    pub fn get_retire_mode(&self, worker_id: u8) -> Option<RetireMode> {
+        if let Ok(retiring) = self.retiring.read() {
+            retiring.get(&worker_id).copied()
         } else {
             None
         }
     }
 
-    pub fn update_ava_time(&self, worker_id: u8, co_id: u64, available_time: f64) {
-        if let Some(ava_time) = self.ava_time.get(&worker_id) {
-            if let Ok(ava_time_map) = ava_time.write().as_mut() {
-                ava_time_map.insert(co_id, available_time);
-            }
+    pub fn is_retiring(&self, worker_id: u8) -> bool {
+        self.get_retire_mode(worker_id).is_some()
+    }
+
+    fn remove_worker(&self, worker_id: u8) {
+        if let Ok(ids) = self.pthread_ids.write().as_mut() {
+            ids.remove(&worker_id);
+        }
+        if let Ok(active) = self.active_workers.write().as_mut() {
+            active.remove(&worker_id);
         }
+        if let Ok(retiring) = self.retiring.write().as_mut() {
+            retiring.remove(&worker_id);
+        }
+        if let Ok(cpus) = self.worker_cpus.write().as_mut() {
+            cpus.remove(&worker_id);
+        }
+        self.set_mode(worker_id, Criticality::LO);
     }
 
-    pub fn delete_ava_time(&self, worker_id: u8, co_id: u64) {
-        if let Some(ava_time) = self.ava_time.get(&worker_id) {
-            if let Ok(ava_time_map) = ava_time.write().as_mut() {
-                ava_time_map.remove(&co_id);
+    /**
+     * 删除已退出worker的cgroup
+     */
+    pub fn delete_worker_cg(&self, worker_id: u8) {
+        if let Ok(tids) = self.worker_tids.write().as_mut() {
+            if let Some(tid) = tids.remove(&worker_id) {
+                // drop时删除
+                let _ = cgroupv2::Controllerv2::new(self.get_cg_path(), format!("worker{}", tid));
             }
         }
     }
 
-    pub fn get_ava_time(&self, worker_id: u8) -> Option<HashMap<u64, f64>> {
-        if let Some(ava_time) = self.ava_time.get(&worker_id) {
-            if let Ok(ava_time_map) = ava_time.read().as_deref() {
-                return Some(ava_time_map.clone());
-            }
+    /// 标记关闭，所有worker按mode退出
+    pub fn set_shutdown(&self, mode: ShutdownMode) {
+        if let Ok(shutdown) = self.shutdown.write().as_mut() {
+            **shutdown = Some(mode);
         }
-        None
     }
 
-    pub fn update_ava_time_map(&self, worker_id: u8, ava_time_map_new: HashMap<u64, f64>) {
-        if let Some(ava_time) = self.ava_time.get(&worker_id) {
-            if let Ok(mut ava_time_map) = ava_time.write() {
-                *ava_time_map = ava_time_map_new;
+    pub fn get_shutdown_mode(&self) -> Option<ShutdownMode> {
+        if let Ok(shutdown) = self.shutdown.read() {
+            *shutdown
+        } else {
+            None
+        }
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.get_shutdown_mode().is_some()
+    }
+
+    /**
+     * 取消全局队列和迁移队列中剩下的任务
+     * 所有worker退出后调用
+     */
+    pub fn cancel_queued(&self) {
+        let mut queued = Vec::new();
+        if let Ok(q) = self.global_queue.lock().as_mut() {
+            while let Some(co) = q.pop() {
+                queued.push(co);
             }
         }
+        while let Some(co) = self.migrated.pop() {
+            queued.push(co);
+        }
+        for mut co in queued {
+            co.cancel();
+            // 这些任务的状态不在任何worker上
+            self.update_completed_status(co.get_co_id(), co.get_schedulestatus(), 0);
+        }
+    }
+
+    /**
+     * 删除这个Runtime的cgroup子树
+     * 还有其他Runtime时把子树中的线程移到hyperwasm，
+     * 最后一个Runtime把进程移回根cgroup并删除hyperwasm，否则cgroup不能删除
+     */
+    pub fn delete_cg(&self) {
+        let mut cg_users = CG_USERS.lock().unwrap();
+        let users = cg_users.entry(self.cgroup_root.clone()).or_default();
+        *users = users.saturating_sub(1);
+        let hyperwasm = self.cg_domain();
+        let cg_runtime = cgroupv2::Controllerv2::new(self.cgroup_root.clone(), self.name.clone());
+        if *users == 0 {
+            hyperwasm.move_procs_to_base(nix::unistd::getpid());
+        } else {
+            cg_runtime.move_threads_to(&hyperwasm);
+        }
+        cg_runtime.delete_children();
+        drop(cg_runtime);
+        if *users == 0 {
+            hyperwasm.delete_children();
+        }
+        // drop时删除，还有其他Runtime时会失败
+        if let Ok(tids) = self.worker_tids.write().as_mut() {
+            tids.clear();
+        }
+    }
+
+    /// 可以接收新任务的worker
+    pub fn get_available_workers(&self) -> Vec<u8> {
+        if self.is_shutdown() {
+            return Vec::new();
+        }
+        let retiring = self.retiring.read().map(|r| r.clone()).unwrap_or_default();
+        if let Ok(active) = self.active_workers.read() {
+            active
+                .iter()
+                .filter(|id| !retiring.contains_key(id))
+                .copied()
+                .collect()
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// 正在运行的worker及其CPU
+    pub fn get_workers(&self) -> BTreeMap<u8, CpuList> {
+        let cpus = self
+            .worker_cpus
+            .read()
+            .map(|c| c.clone())
+            .unwrap_or_default();
+        if let Ok(active) = self.active_workers.read() {
+            active
+                .iter()
+                .map(|id| (*id, cpus.get(id).cloned().unwrap_or_default()))
+                .collect()
+        } else {
+            BTreeMap::new()
+        }
+    }
+
+    pub fn get_worker_cpu(&self, worker_id: u8) -> Option<CpuList> {
+        if let Ok(cpus) = self.worker_cpus.read() {
+            cpus.get(&worker_id).cloned()
+        } else {
+            None
+        }
+    }
+
+    /// 启动时各线程的CPU
+    pub fn get_layout(&self) -> &CpuLayout {
+        &self.layout
+    }
+
+    /// 退役worker交还还没开始运行的任务
+    pub fn migrate(&self, co: Box<Coroutine>) {
+        self.migrated.push(co);
+    }
+
+    pub fn pop_migrated(&self) -> Option<Box<Coroutine>> {
+        self.migrated.pop()
+    }
+
+    /**
+     * 创建这个Runtime的cgroup子树
+     * 第一个Runtime把进程放进hyperwasm，之后的Runtime只扩大hyperwasm的cpuset
+     */
+    fn create_cg(&self) {
+        let mut cg_users = CG_USERS.lock().unwrap();
+        let users = cg_users.entry(self.cgroup_root.clone()).or_default();
+        let hyperwasm = self.cg_domain();
+        let controllers = vec![
+            cgroupv2::ControllerType::CPU,
+            cgroupv2::ControllerType::CPUSET,
+        ];
+        let mut results = Vec::new();
+        if *users == 0 {
+            hyperwasm.set_sub_controller(controllers.clone(), None);
+            results.push(hyperwasm.set_cpus(&self.layout.all()));
+            hyperwasm.set_cgroup_procs(nix::unistd::gettid());
+        } else {
+            results.push(
+                hyperwasm.set_cpus(
+                    &hyperwasm
+                        .cpus()
+                        .unwrap_or_default()
+                        .union(&self.layout.all()),
+                ),
+            );
+        }
+        *users += 1;
+
+        let cg_runtime = cgroupv2::Controllerv2::new(self.cgroup_root.clone(), self.name.clone());
+        cg_runtime.set_threaded();
+        results.push(cg_runtime.set_cpus(&self.layout.all()));
+        cg_runtime.set_sub_controller(controllers, None);
+
+        let cg_main = cgroupv2::Controllerv2::new(self.get_cg_path(), String::from("main"));
+        cg_main.set_threaded();
+        results.push(cg_main.set_cpus(&self.layout.listener));
+        cg_main.set_cgroup_threads(nix::unistd::gettid());
+        for err in results.into_iter().filter_map(Result::err) {
+            tracing::warn!("failed to set cpuset: {}", err);
+        }
+    }
+
+    fn set_pthread_ids(&self, worker_id: u8, pthread_id: nix::sys::pthread::Pthread) {
+        if let Ok(ids) = self.pthread_ids.write().as_mut() {
+            ids.insert(worker_id, pthread_id);
+        }
+    }
+
+    pub fn get_pthread_id(&self, worker_id: u8) -> Option<nix::sys::pthread::Pthread> {
+        if let Ok(ids) = self.pthread_ids.read() {
+            ids.get(&worker_id).copied()
+        } else {
+            None
+        }
     }
 
-    pub fn set_slots(&self, worker_id: u8, co: Box<Coroutine>) {
+    /**
+     * 放到worker的抢占槽
+     * 槽里已经有还没被取走的任务时返回Err，把任务交还给调用者排队
+     */
+    pub fn set_slots(&self, worker_id: u8, co: Box<Coroutine>) -> Result<(), Box<Coroutine>> {
         if let Some(slot) = self.slots.get(&worker_id) {
             // 如果有对应worker的slot
             if let Ok(cobox) = slot.write().as_mut() {
                 if cobox.is_none() {
+                    self.trace(worker_id, TraceKind::Enqueue, co.get_co_id(), 2);
                     let _ = cobox.insert(co);
+                    return Ok(());
                 }
             }
         }
+        Err(co)
     }
 
     pub fn get_slots(&self, worker_id: u8) -> Option<Box<Coroutine>> {
@@ -294,15 +879,16 @@ impl Scheduler {
     ) -> Result<(), std::io::Error> {
         if realtime {
             if let Some(realtime_queue) = self.realtime_queue.get(&worker_id) {
-                if let Ok(q) = realtime_queue.lock().as_mut() {
-                    q.push(co);
-                    return Ok(());
-                }
+                self.trace(worker_id, TraceKind::Enqueue, co.get_co_id(), 1);
+                realtime_queue.push(co);
+                return Ok(());
             }
             panic!("realtime queue failed")
         } else {
             if let Ok(q) = self.global_queue.lock().as_mut() {
-                q.push_back(co);
+                let (group, weight) = (co.get_group().to_owned(), co.get_weight());
+                self.trace(trace::SCHEDULER_RING, TraceKind::Enqueue, co.get_co_id(), 0);
+                q.push(&group, weight, co);
                 return Ok(());
             }
         }
@@ -315,21 +901,42 @@ impl Scheduler {
 
     pub fn pop_realtime(&self, worker_id: u8) -> Option<Box<Coroutine>> {
         if let Some(realtime_queue) = self.realtime_queue.get(&worker_id) {
-            if let Ok(q) = realtime_queue.try_lock().as_mut() {
-                return q.pop();
-            }
+            return realtime_queue.pop();
         }
         None
     }
 
+    /// 获取worker收件箱的提交和取走延迟
+    pub fn get_inbox_latency(&self, worker_id: u8) -> Option<InboxLatency> {
+        self.realtime_queue
+            .get(&worker_id)
+            .map(|inbox| inbox.get_latency())
+    }
+
     pub fn pop(&self) -> Option<Box<Coroutine>> {
         if let Ok(q) = self.global_queue.try_lock().as_mut() {
-            q.pop_front()
+            q.pop()
         } else {
             None
         }
     }
 
+    /// 累计分组在全局队列中的虚拟运行时间
+    pub fn charge(&self, group: &str, weight: u32, running_time: Duration) {
+        if let Ok(q) = self.global_queue.lock().as_mut() {
+            q.charge(group, weight, running_time);
+        }
+    }
+
+    /// 获取各分组的虚拟运行时间
+    pub fn get_vruntime(&self) -> HashMap<String, Duration> {
+        if let Ok(q) = self.global_queue.lock() {
+            q.get_vruntime()
+        } else {
+            HashMap::new()
+        }
+    }
+
     pub fn get_length(&self) -> usize {
         if let Ok(q) = self.global_queue.try_lock() {
             q.len()
@@ -346,7 +953,7 @@ impl Scheduler {
         }
     }
 
-    fn delete_status(&self, co_id: u64, worker_id: u8) {
+    pub fn delete_status(&self, co_id: u64, worker_id: u8) {
         if let Some(co_status) = self.co_status.get(&worker_id) {
             if let Ok(status) = co_status.write().as_mut() {
                 status.remove(&co_id);
@@ -390,11 +997,40 @@ impl Scheduler {
     }
 
     pub fn update_completed_status(&self, co_id: u64, stat: SchedulerStatus, worker_id: u8) {
+        let now = crate::clock::now();
+        let missed = stat
+            .get_relative_deadline()
+            .is_some_and(|ddl| now.saturating_duration_since(stat.get_spawn_time()) > ddl);
+        let overrun = stat
+            .get_wcet(Criticality::LO)
+            .is_some_and(|eet| stat.running_time > eet);
+        self.metrics.finished(
+            worker_id,
+            stat.co_status == CoStatus::CANCELLED,
+            missed,
+            overrun,
+        );
+        // 取消或trap的任务运行时间被截断，不参与校准和学习
+        let finished = stat.co_status != CoStatus::CANCELLED && !stat.trapped;
+        if let (Some(fuel), true) = (stat.fuel, finished) {
+            self.fuel.record(worker_id, stat.running_time, fuel);
+        }
+        if let (Some(func), true) = (&stat.func, finished) {
+            self.estimator.record(func, stat.running_time);
+        }
+        if let Some(history) = &self.history {
+            history.record(HistoryRecord::new(co_id, &stat, worker_id, now));
+        }
         if let Ok(status) = self.completed_status.lock().as_mut() {
             status.push(co_id, stat);
         }
         self.delete_status(co_id, worker_id);
-        self.delete_ava_time(worker_id, co_id);
+        self.completions.fetch_add(1, Ordering::Release);
+    }
+
+    /// 结束或取消的任务数量
+    pub fn get_completions(&self) -> u64 {
+        self.completions.load(Ordering::Acquire)
     }
 
     pub fn set_curr_running_id(&self, co_id: u64, worker_id: u8) {
@@ -411,12 +1047,125 @@ impl Scheduler {
             .load(Ordering::SeqCst)
     }
 
+    /// 获取worker当前的关键级别模式
+    pub fn get_mode(&self, worker_id: u8) -> Criticality {
+        match self.crit_mode.get(&worker_id) {
+            Some(mode) if mode.load(Ordering::SeqCst) == Criticality::HI as u8 => Criticality::HI,
+            _ => Criticality::LO,
+        }
+    }
+
+    pub fn set_mode(&self, worker_id: u8, mode: Criticality) {
+        if let Some(crit_mode) = self.crit_mode.get(&worker_id) {
+            crit_mode.store(mode as u8, Ordering::SeqCst);
+        }
+    }
+
+    pub fn get_lo_policy(&self) -> LoPolicy {
+        if let Ok(policy) = self.lo_policy.read() {
+            *policy
+        } else {
+            LoPolicy::default()
+        }
+    }
+
+    pub fn set_lo_policy(&self, policy: LoPolicy) {
+        if let Ok(mut lo_policy) = self.lo_policy.write() {
+            *lo_policy = policy;
+        }
+    }
+
+    /// 记录调度事件，可以在信号处理函数中调用
+    pub fn trace(&self, worker_id: u8, kind: TraceKind, co_id: u64, arg: u64) {
+        self.tracer.record(worker_id, kind, co_id, arg);
+    }
+
+    pub fn get_tracer(&self) -> &Tracer {
+        &self.tracer
+    }
+
+    pub fn get_history(&self) -> Option<&Arc<History>> {
+        self.history.as_ref()
+    }
+
+    pub fn get_metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /**
+     * 导出Prometheus文本格式的指标
+     * 计数器来自Metrics，队列长度和关键级别模式在这里读取
+     */
+    pub fn encode_metrics(&self) -> String {
+        let mut out = Encoder::new(&self.name);
+        let active: Vec<u8> = self
+            .active_workers
+            .read()
+            .map(|active| active.iter().copied().collect())
+            .unwrap_or_default();
+        self.metrics.encode(&mut out, &active);
+
+        out.family(
+            "worker_queue_depth",
+            "gauge",
+            "Tasks admitted to the worker and not finished",
+        );
+        for &id in active.iter() {
+            let depth = self
+                .co_status
+                .get(&id)
+                .and_then(|status| status.read().ok().map(|status| status.len()))
+                .unwrap_or(0);
+            out.sample(
+                "worker_queue_depth",
+                &[("worker", &id.to_string())],
+                depth as f64,
+            );
+        }
+        out.family(
+            "worker_hi_mode",
+            "gauge",
+            "1 if the worker is in HI criticality mode",
+        );
+        for &id in active.iter() {
+            let hi = (self.get_mode(id) == Criticality::HI) as u8;
+            out.sample("worker_hi_mode", &[("worker", &id.to_string())], hi as f64);
+        }
+        out.family(
+            "worker_fuel_nanoseconds",
+            "gauge",
+            "Calibrated nanoseconds per unit of wasm fuel",
+        );
+        for &id in active.iter() {
+            if let Some(rate) = self.fuel.get(id) {
+                out.sample(
+                    "worker_fuel_nanoseconds",
+                    &[("worker", &id.to_string())],
+                    rate,
+                );
+            }
+        }
+        out.family(
+            "global_queue_depth",
+            "gauge",
+            "Best-effort tasks waiting in the global queue",
+        );
+        out.sample("global_queue_depth", &[], self.get_length() as f64);
+        out.family(
+            "migrated_queue_depth",
+            "gauge",
+            "Tasks handed back by retiring workers and waiting for readmission",
+        );
+        out.sample("migrated_queue_depth", &[], self.migrated.len() as f64);
+        out.finish()
+    }
+
     /// 找到worker中任务的最大的绝对截至日期
     pub fn get_end_ddl(&self, worker_id: u8) -> Option<Instant> {
         //找到co_status中任务的最大的绝对截至日期
         if let Some(co_status) = self.co_status.get(&worker_id) {
             if let Ok(status) = co_status.read().as_mut() {
-                let mut max_ddl = Instant::now();
+                let mut max_ddl = crate::clock::now();
                 for (_, stat) in status.iter() {
                     if let Some(ddl) = stat.absolute_deadline {
                         if ddl > max_ddl {
@@ -433,16 +1182,27 @@ impl Scheduler {
 
 extern "C" fn signal_handler(signal: libc::c_int) {
     let signal = Signal::try_from(signal).unwrap();
-    if signal == PREEMPTY {
+    // 信号处理函数是进程级的，不是worker线程时忽略
+    let mut worker = match try_get_worker() {
+        Some(worker) => worker,
+        None => return,
+    };
+    let worker = unsafe { worker.as_mut() };
+    // 正在执行wasm的任务不在这里切换，由epoch回调在检查点处理
+    if worker.defer_signal(signal) {
+        return;
+    }
+    let (preempt_signal, timer_signal) = worker.get_signals();
+    if signal == preempt_signal {
         let mut mask: libc::sigset_t = unsafe { std::mem::zeroed() };
         unsafe {
             libc::sigfillset(&mut mask);
             libc::sigprocmask(libc::SIG_BLOCK, &mask, std::ptr::null_mut());
         }
 
-        let worker = unsafe { get_worker().as_mut() };
-
         if worker.preemptive() {
+            worker.trace_curr(TraceKind::Preempt);
+            worker.count_preemption();
             // let start = Instant::now();
             worker.suspend();
             // let end = Instant::now();
@@ -455,7 +1215,7 @@ extern "C" fn signal_handler(signal: libc::c_int) {
             libc::sigemptyset(&mut mask);
             libc::sigprocmask(libc::SIG_UNBLOCK, &mask, std::ptr::null_mut());
         }
-    } else if signal == SIG {
+    } else if signal == timer_signal {
         let mut mask: libc::sigset_t = unsafe { std::mem::zeroed() };
         unsafe {
             libc::sigfillset(&mut mask);
@@ -463,15 +1223,37 @@ extern "C" fn signal_handler(signal: libc::c_int) {
         }
 
         if let Some(current) = current() {
-            if unsafe { current.as_ref().is_realtime() } {
+            let current = unsafe { current.as_ref() };
+            worker.trace(TraceKind::Timer, current.get_co_id());
+            // 看门狗发现的失控任务
+            if let Some(action) = worker.watchdog_request(current.get_co_id()) {
+                worker.watchdog(action);
+                return;
+            }
+            if current.is_realtime() {
+                // HI任务超出LO级别的预算，回到worker栈上切换到HI模式
+                if current.get_criticality() == Criticality::HI
+                    && current.overrun_lo()
+                    && worker.get_mode() == Criticality::LO
+                {
+                    worker.defer_mode_switch();
+                    return;
+                }
+                // 预算耗尽后继续等待截止时间
+                unsafe { get_timer().as_mut() }.arm(current.next_event(worker.get_mode()));
                 return;
             }
         }
-        let worker = unsafe { get_worker().as_mut() };
+        // 被丢弃的任务已经trap，不在原生代码中挂起它，让它尽快结束
+        let killed = current().is_some_and(|co| unsafe { co.as_ref() }.is_killed());
         worker.get_task();
-        if worker.len > 1 {
+        if worker.len > 1 && !killed {
             worker.set_curr();
             worker.suspend();
+        } else if current().is_some() {
+            // 没有其他任务，继续运行并重新设置时间片
+            let timer = unsafe { get_timer().as_mut() };
+            timer.arm(timer.get_quantum());
         }
 
         // unsafe { get_timer().as_mut() }.reset_timer();
@@ -0,0 +1,415 @@
+use super::MAX_WORKERS;
+use once_cell::sync::OnceCell;
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+/// 每个ring缓存的事件数量，写满后覆盖最早的事件
+pub const TRACE_CAPACITY: usize = 4096;
+/// 不属于任何worker的事件(例如没有可用worker时的准入拒绝)记录在这个ring
+pub const SCHEDULER_RING: u8 = MAX_WORKERS;
+
+/// 调度事件的类型
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TraceKind {
+    /// 准入成功，arg是AdmissionControl(0: NOTREALTIME, 1: PREEMPTIVE, 2: SCHEDULABLE)
+    Admit = 1,
+    /// 准入失败
+    Reject,
+    /// 放入队列，arg为0表示全局队列，1表示实时队列，2表示抢占slot
+    Enqueue,
+    /// 开始或继续运行
+    Resume,
+    /// 让出CPU
+    Suspend,
+    /// 收到SIGURG抢占
+    Preempt,
+    /// 定时器到期
+    Timer,
+    /// 运行结束
+    Complete,
+}
+
+impl TraceKind {
+    fn from_u8(kind: u8) -> Option<TraceKind> {
+        match kind {
+            1 => Some(TraceKind::Admit),
+            2 => Some(TraceKind::Reject),
+            3 => Some(TraceKind::Enqueue),
+            4 => Some(TraceKind::Resume),
+            5 => Some(TraceKind::Suspend),
+            6 => Some(TraceKind::Preempt),
+            7 => Some(TraceKind::Timer),
+            8 => Some(TraceKind::Complete),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            TraceKind::Admit => "admit",
+            TraceKind::Reject => "reject",
+            TraceKind::Enqueue => "enqueue",
+            TraceKind::Resume => "resume",
+            TraceKind::Suspend => "suspend",
+            TraceKind::Preempt => "preempt",
+            TraceKind::Timer => "timer",
+            TraceKind::Complete => "complete",
+        }
+    }
+}
+
+/// 一条调度事件
+#[derive(Clone, Copy, Debug)]
+pub struct TraceEvent {
+    pub ts: Duration, // 相对Tracer创建时间，纳秒精度
+    pub worker_id: u8,
+    pub kind: TraceKind,
+    pub co_id: u64,
+    pub arg: u64,
+}
+
+#[derive(Default)]
+struct Slot {
+    seq: AtomicU64, // 0表示正在写入或为空
+    ts: AtomicU64,
+    kind: AtomicU64,
+    co_id: AtomicU64,
+    arg: AtomicU64,
+}
+
+/// 无锁环形缓冲区
+///
+/// 写入只使用原子操作，可以在信号处理函数中调用，也可以被多个线程同时写入
+struct TraceRing {
+    head: AtomicU64,
+    slots: Box<[Slot]>,
+}
+
+impl TraceRing {
+    fn new(capacity: usize) -> TraceRing {
+        TraceRing {
+            head: AtomicU64::new(0),
+            slots: (0..capacity.max(1)).map(|_| Slot::default()).collect(),
+        }
+    }
+
+    fn push(&self, ts: u64, kind: TraceKind, co_id: u64, arg: u64) {
+        let idx = self.head.fetch_add(1, Ordering::Relaxed);
+        let slot = &self.slots[idx as usize % self.slots.len()];
+        slot.seq.store(0, Ordering::Release);
+        slot.ts.store(ts, Ordering::Relaxed);
+        slot.kind.store(kind as u64, Ordering::Relaxed);
+        slot.co_id.store(co_id, Ordering::Relaxed);
+        slot.arg.store(arg, Ordering::Relaxed);
+        slot.seq.store(idx + 1, Ordering::Release);
+    }
+
+    /// 读取所有完整的事件，正在写入的事件会被跳过
+    fn snapshot(&self, worker_id: u8, events: &mut Vec<TraceEvent>) {
+        for slot in self.slots.iter() {
+            let seq = slot.seq.load(Ordering::Acquire);
+            if seq == 0 {
+                continue;
+            }
+            let ts = slot.ts.load(Ordering::Relaxed);
+            let kind = slot.kind.load(Ordering::Relaxed);
+            let co_id = slot.co_id.load(Ordering::Relaxed);
+            let arg = slot.arg.load(Ordering::Relaxed);
+            if slot.seq.load(Ordering::Acquire) != seq {
+                continue;
+            }
+            if let Some(kind) = TraceKind::from_u8(kind as u8) {
+                events.push(TraceEvent {
+                    ts: Duration::from_nanos(ts),
+                    worker_id,
+                    kind,
+                    co_id,
+                    arg,
+                });
+            }
+        }
+    }
+
+    fn clear(&self) {
+        for slot in self.slots.iter() {
+            slot.seq.store(0, Ordering::Release);
+        }
+    }
+}
+
+/// 每个worker一个环形缓冲区的调度事件记录
+pub struct Tracer {
+    start: Instant,
+    enabled: AtomicBool,
+    rings: Vec<OnceCell<TraceRing>>,
+}
+
+impl Default for Tracer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Tracer {
+    pub fn new() -> Tracer {
+        let tracer = Tracer {
+            start: Instant::now(),
+            enabled: AtomicBool::new(true),
+            rings: (0..=SCHEDULER_RING).map(|_| OnceCell::new()).collect(),
+        };
+        tracer.init_ring(SCHEDULER_RING);
+        tracer
+    }
+
+    /**
+     * 分配worker的ring
+     * 信号处理函数中不能分配内存，所以在worker启动前分配
+     */
+    pub fn init_ring(&self, worker_id: u8) {
+        if let Some(ring) = self.rings.get(worker_id as usize) {
+            ring.get_or_init(|| TraceRing::new(TRACE_CAPACITY));
+        }
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::SeqCst);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// 记录一条事件，没有分配ring的worker的事件会被丢弃
+    pub fn record(&self, worker_id: u8, kind: TraceKind, co_id: u64, arg: u64) {
+        if !self.is_enabled() {
+            return;
+        }
+        if let Some(ring) = self.rings.get(worker_id as usize).and_then(|r| r.get()) {
+            let ts = self.start.elapsed().as_nanos() as u64;
+            ring.push(ts, kind, co_id, arg);
+        }
+    }
+
+    /// 所有事件，按时间排序
+    pub fn get_events(&self) -> Vec<TraceEvent> {
+        let mut events = Vec::new();
+        for (id, ring) in self.rings.iter().enumerate() {
+            if let Some(ring) = ring.get() {
+                ring.snapshot(id as u8, &mut events);
+            }
+        }
+        events.sort_by_key(|e| e.ts);
+        events
+    }
+
+    pub fn clear(&self) {
+        for ring in self.rings.iter().filter_map(|r| r.get()) {
+            ring.clear();
+        }
+    }
+
+    /**
+     * 导出为Chrome Trace Event格式，可以用chrome://tracing或Perfetto打开
+     * 每个worker是一个线程，任务的一次运行(resume到suspend/complete)是一个区间，
+     * 其他事件是瞬时事件
+     */
+    pub fn to_chrome_trace(&self) -> Value {
+        let events = self.get_events();
+        let us = |ts: Duration| ts.as_nanos() as f64 / 1000.0;
+        let mut trace = Vec::new();
+        let mut running: HashMap<u8, TraceEvent> = HashMap::new();
+        let mut tids: Vec<u8> = events.iter().map(|e| e.worker_id).collect();
+        tids.sort();
+        tids.dedup();
+        for tid in tids {
+            let name = if tid == SCHEDULER_RING {
+                String::from("scheduler")
+            } else {
+                format!("worker {}", tid)
+            };
+            trace.push(json!({
+                "name": "thread_name",
+                "ph": "M",
+                "pid": 1,
+                "tid": tid,
+                "args": { "name": name },
+            }));
+        }
+        for e in events.iter() {
+            match e.kind {
+                TraceKind::Resume => {
+                    running.insert(e.worker_id, *e);
+                    continue;
+                }
+                TraceKind::Suspend | TraceKind::Complete => {
+                    if let Some(start) = running.remove(&e.worker_id) {
+                        trace.push(json!({
+                            "name": format!("co {}", start.co_id),
+                            "cat": "run",
+                            "ph": "X",
+                            "pid": 1,
+                            "tid": e.worker_id,
+                            "ts": us(start.ts),
+                            "dur": us(e.ts.saturating_sub(start.ts)),
+                            "args": { "co_id": start.co_id, "end": e.kind.name() },
+                        }));
+                    }
+                }
+                _ => {}
+            }
+            trace.push(json!({
+                "name": e.kind.name(),
+                "cat": "sched",
+                "ph": "i",
+                "s": "t",
+                "pid": 1,
+                "tid": e.worker_id,
+                "ts": us(e.ts),
+                "args": { "co_id": e.co_id, "arg": e.arg },
+            }));
+        }
+        // 还在运行的任务
+        for (tid, start) in running {
+            trace.push(json!({
+                "name": format!("co {}", start.co_id),
+                "cat": "run",
+                "ph": "B",
+                "pid": 1,
+                "tid": tid,
+                "ts": us(start.ts),
+                "args": { "co_id": start.co_id },
+            }));
+        }
+        json!({ "traceEvents": trace, "displayTimeUnit": "ns" })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn co_ids(events: &[TraceEvent]) -> Vec<u64> {
+        events.iter().map(|e| e.co_id).collect()
+    }
+
+    #[test]
+    fn ring_overwrites_oldest() {
+        let ring = TraceRing::new(4);
+        for i in 0..10 {
+            ring.push(i, TraceKind::Enqueue, i, 0);
+        }
+        let mut events = Vec::new();
+        ring.snapshot(0, &mut events);
+        events.sort_by_key(|e| e.ts);
+        // 只保留最后写入的4个事件
+        assert_eq!(co_ids(&events), vec![6, 7, 8, 9]);
+
+        ring.clear();
+        events.clear();
+        ring.snapshot(0, &mut events);
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn events_are_ordered_across_rings() {
+        let tracer = Tracer::new();
+        tracer.init_ring(0);
+        tracer.init_ring(1);
+        let n = TRACE_CAPACITY as u64 + 10;
+        for i in 0..n {
+            tracer.record((i % 2) as u8, TraceKind::Resume, i, 0);
+        }
+        // 没有分配ring的worker的事件被丢弃
+        tracer.record(2, TraceKind::Resume, n, 0);
+        let events = tracer.get_events();
+        assert_eq!(events.len(), n as usize);
+        assert!(events.windows(2).all(|w| w[0].ts <= w[1].ts));
+        assert!(events.iter().all(|e| e.worker_id as u64 == e.co_id % 2));
+        // 同一个ring中的事件按写入顺序
+        for worker_id in 0..2 {
+            let ids: Vec<u64> = events
+                .iter()
+                .filter(|e| e.worker_id == worker_id)
+                .map(|e| e.co_id)
+                .collect();
+            assert_eq!(ids, (worker_id as u64..n).step_by(2).collect::<Vec<u64>>());
+        }
+
+        // 每个ring写满后保留最新的TRACE_CAPACITY个事件
+        for i in n..n + TRACE_CAPACITY as u64 {
+            tracer.record(0, TraceKind::Suspend, i, 0);
+        }
+        let events: Vec<TraceEvent> = tracer
+            .get_events()
+            .into_iter()
+            .filter(|e| e.worker_id == 0)
+            .collect();
+        assert_eq!(
+            co_ids(&events),
+            (n..n + TRACE_CAPACITY as u64).collect::<Vec<u64>>()
+        );
+    }
+
+    #[test]
+    fn chrome_trace_fields() {
+        let tracer = Tracer::new();
+        tracer.init_ring(0);
+        tracer.record(0, TraceKind::Resume, 7, 0);
+        tracer.record(0, TraceKind::Preempt, 7, 0);
+        tracer.record(0, TraceKind::Suspend, 7, 0);
+        tracer.record(0, TraceKind::Resume, 8, 0);
+        tracer.record(SCHEDULER_RING, TraceKind::Reject, 9, 0);
+        // 只看worker 0的事件，和scheduler ring的事件时间相同时顺序不确定
+        let events: Vec<TraceEvent> = tracer
+            .get_events()
+            .into_iter()
+            .filter(|e| e.worker_id == 0)
+            .collect();
+        let us = |ts: Duration| ts.as_nanos() as f64 / 1000.0;
+        let ts = |i: usize| us(events[i].ts);
+
+        let trace = tracer.to_chrome_trace();
+        let trace = trace["traceEvents"].as_array().unwrap();
+        let find = |ph: &str, name: &str| {
+            trace
+                .iter()
+                .find(|e| e["ph"] == ph && e["name"] == name)
+                .unwrap_or_else(|| panic!("no {} event {}", ph, name))
+        };
+        let names: Vec<&Value> = trace.iter().filter(|e| e["ph"] == "M").collect();
+        assert_eq!(names.len(), 2);
+        assert_eq!(names[0]["tid"], 0);
+        assert_eq!(names[0]["args"]["name"], "worker 0");
+        assert_eq!(names[1]["tid"], SCHEDULER_RING);
+        assert_eq!(names[1]["args"]["name"], "scheduler");
+
+        // resume到suspend是一个完整的区间
+        let run = find("X", "co 7");
+        assert_eq!(run["tid"], 0);
+        assert_eq!(run["ts"].as_f64(), Some(ts(0)));
+        assert_eq!(run["dur"].as_f64(), Some(us(events[2].ts - events[0].ts)));
+        assert_eq!(run["args"]["end"], "suspend");
+
+        let preempt = find("i", "preempt");
+        assert_eq!(preempt["tid"], 0);
+        assert_eq!(preempt["ts"].as_f64(), Some(ts(1)));
+        assert_eq!(preempt["args"]["co_id"], 7);
+        let reject = find("i", "reject");
+        assert_eq!(reject["tid"], SCHEDULER_RING);
+        assert!(reject["ts"].as_f64() >= Some(ts(3)));
+
+        // 还在运行的任务只有开始
+        let running = find("B", "co 8");
+        assert_eq!(running["tid"], 0);
+        assert_eq!(running["ts"].as_f64(), Some(ts(3)));
+        assert!(trace
+            .iter()
+            .filter(|e| e["ph"] != "M")
+            .all(|e| e["ts"].is_f64() && e["tid"].is_u64() && e["pid"] == 1));
+    }
+}
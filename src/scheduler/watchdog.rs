@@ -0,0 +1,186 @@
+use super::{get_start, Scheduler};
+use serde::Serialize;
+use std::{
+    sync::{atomic::Ordering, Weak},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// 看门狗发现失控任务后的处理方式
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum WatchdogAction {
+    /// 只在健康检查中报告
+    REPORT,
+    /// 降级为非实时任务，不再占用实时容量，和其他非实时任务轮转
+    DEMOTE,
+    /// 正在执行wasm的任务在epoch检查点trap；原生闭包运行到一半的栈不能安全丢弃，按DEMOTE处理
+    KILL,
+}
+
+/// 看门狗的配置
+#[derive(Clone, Debug)]
+pub struct WatchdogConfig {
+    pub interval: Duration,        // 检查间隔
+    pub budget_factor: u32,        // 运行时间超过预期执行时间的多少倍算失控
+    pub deadline_grace: Duration,  // 超过截止时间多久算失控
+    pub max_run: Option<Duration>, // 非实时任务连续运行的上限，None表示不检查
+    pub action: WatchdogAction,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        WatchdogConfig {
+            interval: Duration::from_millis(100),
+            budget_factor: 10,
+            deadline_grace: Duration::from_secs(1),
+            max_run: None,
+            action: WatchdogAction::REPORT,
+        }
+    }
+}
+
+/// 一个worker的健康状态
+#[derive(Clone, Debug, Serialize)]
+pub struct WorkerHealth {
+    pub worker_id: u8,
+    pub healthy: bool,
+    pub co_id: Option<u64>,      // 正在运行的任务
+    pub running_ms: Option<u64>, // 这次连续运行的时间
+    pub reason: Option<String>,  // 不健康的原因
+}
+
+/// 距第一个Runtime启动的纳秒数加1，0表示worker空闲
+fn since_start(instant: Instant) -> u64 {
+    let d = instant.saturating_duration_since(get_start().0);
+    d.as_nanos().min(u64::MAX as u128 - 1) as u64 + 1
+}
+
+/// 检查线程，Scheduler被drop或者关闭后退出
+pub(super) fn spawn(scheduler: Weak<Scheduler>, config: WatchdogConfig) {
+    let res = thread::Builder::new()
+        .name("watchdog".to_owned())
+        .spawn(move || loop {
+            thread::sleep(config.interval);
+            let scheduler = match scheduler.upgrade() {
+                Some(scheduler) if !scheduler.is_shutdown() => scheduler,
+                _ => return,
+            };
+            for health in scheduler.check_workers(&config) {
+                if let (false, Some(co_id)) = (health.healthy, health.co_id) {
+                    tracing::warn!(
+                        "watchdog: worker {} task {} {}",
+                        health.worker_id,
+                        co_id,
+                        health.reason.as_deref().unwrap_or_default()
+                    );
+                    if config.action != WatchdogAction::REPORT {
+                        scheduler.request_watchdog(health.worker_id, co_id);
+                    }
+                }
+            }
+        });
+    if let Err(err) = res {
+        tracing::error!("failed to spawn watchdog: {}", err);
+    }
+}
+
+impl Scheduler {
+    /// worker开始或结束运行一个任务，None表示空闲
+    pub fn set_running_since(&self, worker_id: u8, since: Option<Instant>) {
+        if let Some(running) = self.running_since.get(&worker_id) {
+            running.store(since.map_or(0, since_start), Ordering::Release);
+        }
+    }
+
+    /// worker当前任务这次开始运行的时间
+    fn get_running_since(&self, worker_id: u8) -> Option<Duration> {
+        let nanos = self.running_since.get(&worker_id)?.load(Ordering::Acquire);
+        let since = Duration::from_nanos(nanos.checked_sub(1)?);
+        Some(get_start().0.elapsed().saturating_sub(since))
+    }
+
+    /**
+     * 检查所有正在运行的worker
+     * 实时任务累计运行时间超过预期执行时间的budget_factor倍，或者超过截止时间deadline_grace，
+     * 非实时任务连续运行超过max_run，都算失控
+     */
+    pub fn check_workers(&self, config: &WatchdogConfig) -> Vec<WorkerHealth> {
+        let workers: Vec<u8> = self
+            .active_workers
+            .read()
+            .map(|active| active.iter().copied().collect())
+            .unwrap_or_default();
+        let now = crate::clock::now();
+        let mut report = Vec::with_capacity(workers.len());
+        for worker_id in workers {
+            let mut health = WorkerHealth {
+                worker_id,
+                healthy: true,
+                co_id: None,
+                running_ms: None,
+                reason: None,
+            };
+            if let Some(running) = self.get_running_since(worker_id) {
+                let co_id = self.get_curr_running_id(worker_id);
+                health.co_id = Some(co_id);
+                health.running_ms = Some(running.as_millis() as u64);
+                if let Some(stat) = self.get_status_by_id(co_id) {
+                    health.reason = match (stat.get_wcet(stat.criticality), stat.absolute_deadline)
+                    {
+                        (Some(eet), Some(ddl)) => {
+                            let total = stat.running_time + running;
+                            if total > eet * config.budget_factor {
+                                Some(format!("ran {:?} with budget {:?}", total, eet))
+                            } else if now > ddl + config.deadline_grace {
+                                Some(format!("missed deadline by {:?}", now - ddl))
+                            } else {
+                                None
+                            }
+                        }
+                        _ => config
+                            .max_run
+                            .filter(|&max_run| running > max_run)
+                            .map(|_| format!("ran {:?} without yielding", running)),
+                    };
+                }
+                health.healthy = health.reason.is_none();
+            }
+            report.push(health);
+        }
+        report
+    }
+
+    /// 让worker处理失控的任务，worker在定时器信号中处理
+    fn request_watchdog(&self, worker_id: u8, co_id: u64) {
+        if let Some(target) = self.watchdog_target.get(&worker_id) {
+            target.store(co_id, Ordering::Release);
+        }
+        if let Some(pthread_id) = self.get_pthread_id(worker_id) {
+            let sigval = libc::sigval {
+                sival_ptr: std::ptr::null_mut(),
+            };
+            unsafe {
+                libc::pthread_sigqueue(pthread_id, self.timer_signal as i32, sigval);
+            }
+        }
+    }
+
+    /**
+     * 取出看门狗要求处理的任务，只有仍在运行时才返回处理方式
+     * 只有原子操作，可以在信号处理函数中调用
+     */
+    pub fn take_watchdog_request(&self, worker_id: u8, co_id: u64) -> Option<WatchdogAction> {
+        let target = self
+            .watchdog_target
+            .get(&worker_id)?
+            .swap(0, Ordering::AcqRel);
+        if target == 0 || target != co_id {
+            return None;
+        }
+        self.watchdog.as_ref().map(|config| config.action)
+    }
+
+    pub fn get_watchdog(&self) -> Option<&WatchdogConfig> {
+        self.watchdog.as_ref()
+    }
+}
@@ -1,14 +1,22 @@
-use super::{get_timer, Scheduler};
+use super::{
+    epoch, fair::FairQueue, get_timer, trace::TraceKind, watchdog::WatchdogAction, Scheduler,
+};
 use crate::{
-    task::{current, current_is_none, CoStatus, Coroutine, SchedulerStatus},
+    runtime::{LoPolicy, RetireMode, ShutdownMode},
+    task::{current, current_is_none, CoStatus, Coroutine, Criticality, SchedulerStatus},
     StackSize,
 };
-use nix::unistd::Pid;
+use anyhow::Error;
+use nix::{sys::signal::Signal, unistd::Pid};
 use std::{
     cell::Cell,
     collections::{BinaryHeap, HashMap, VecDeque},
     ptr,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicU64, AtomicU8, Ordering},
+        Arc,
+    },
+    time::Instant,
 };
 
 thread_local! {
@@ -19,20 +27,31 @@ pub fn get_worker() -> ptr::NonNull<Worker> {
     WORKER.with(|cell| cell.get()).expect("no worker")
 }
 
+/// 当前线程不是worker时返回None
+pub fn try_get_worker() -> Option<ptr::NonNull<Worker>> {
+    WORKER.with(|cell| cell.get())
+}
+
 pub type ArrayQueue<T> = VecDeque<T>;
 
+/// 正在执行wasm时推迟处理的信号
+const DEFERRED_PREEMPT: u8 = 1;
+const DEFERRED_TIMER: u8 = 2;
+
 pub struct Worker {
     worker_id: u8,
-    new_spawned: ArrayQueue<Box<Coroutine>>,
     local_queue: ArrayQueue<ptr::NonNull<Coroutine>>,
-    suspend_queue: ArrayQueue<ptr::NonNull<Coroutine>>,
+    fair_queue: FairQueue<ptr::NonNull<Coroutine>>, // 新生成和被挂起的非实时任务
     realtime_queue: HashMap<u64, ptr::NonNull<Coroutine>>,
     realtime_status: BinaryHeap<SchedulerStatus>,
     scheduler: Arc<Scheduler>,
     curr: Option<ptr::NonNull<Coroutine>>,
     capacity: usize,
     pub len: usize,
-    // signal
+    demote_pending: bool, // 看门狗要求降级当前任务，回到worker栈后降级
+    mode_pending: bool,   // 信号处理函数要求切换到HI模式，回到worker栈后切换
+    deferred: AtomicU8,   // 推迟到epoch回调处理的信号
+    ticks: AtomicU64,     // 这个worker推进epoch的次数，epoch回调只处理自己推进的epoch
 }
 
 unsafe impl Send for Worker {}
@@ -40,23 +59,24 @@ unsafe impl Sync for Worker {}
 
 impl Worker {
     pub fn new(scheduler: &Arc<Scheduler>, capacity: usize, worker_id: u8) -> Arc<Worker> {
-        let new_spawned = ArrayQueue::with_capacity(capacity);
         let local_queue = ArrayQueue::with_capacity(capacity);
-        let suspend_queue = ArrayQueue::with_capacity(capacity);
         let realtime_queue = HashMap::with_capacity(capacity);
         let realtime_status = BinaryHeap::with_capacity(capacity);
 
         Arc::new(Worker {
             worker_id,
-            new_spawned,
             local_queue,
-            suspend_queue,
+            fair_queue: FairQueue::new(),
             realtime_queue,
             realtime_status,
             scheduler: scheduler.clone(),
             curr: None,
             capacity,
             len: 0,
+            demote_pending: false,
+            mode_pending: false,
+            deferred: AtomicU8::new(0),
+            ticks: AtomicU64::new(0),
         })
     }
 
@@ -67,11 +87,15 @@ impl Worker {
 
     pub fn set_cgroup(&self, tid: Pid) {
         let cg_worker = crate::cgroupv2::Controllerv2::new(
-            std::path::PathBuf::from("/sys/fs/cgroup/hyperwasm"),
+            self.scheduler.get_cg_path(),
             format!("worker{}", tid),
         );
         cg_worker.set_threaded();
-        cg_worker.set_cpuset(self.scheduler.start_cpu + self.worker_id + 2, None);
+        if let Some(cpu) = self.scheduler.get_worker_cpu(self.worker_id) {
+            if let Err(err) = cg_worker.set_cpus(&cpu) {
+                tracing::warn!("failed to set cpus of worker {}: {}", self.worker_id, err);
+            }
+        }
         cg_worker.set_cgroup_threads(tid);
     }
 
@@ -81,10 +105,7 @@ impl Worker {
             self.curr = Some(co);
         } else if let Some(co) = self.local_queue.pop_front() {
             self.curr = Some(co);
-        } else if let Some(co) = self.new_spawned.pop_front() {
-            let co = ptr::NonNull::from(Box::leak(Box::new(*co)));
-            self.curr = Some(co);
-        } else if let Some(co) = self.suspend_queue.pop_front() {
+        } else if let Some(co) = self.fair_queue.pop() {
             self.curr = Some(co);
         }
     }
@@ -123,10 +144,20 @@ impl Worker {
             self.add_realtime(co);
             self.len += 1;
         }
+        // 退役中和关闭中的worker不再从全局队列取任务，DRAINALL模式除外
+        if self.scheduler.is_retiring(self.worker_id) {
+            return;
+        }
+        if let Some(mode) = self.scheduler.get_shutdown_mode() {
+            if mode != ShutdownMode::DRAINALL {
+                return;
+            }
+        }
         while !self.is_full() && self.scheduler.get_length() > 0 {
             if let Some(co) = self.scheduler.pop() {
                 // tracing::info!("get coroutine co id = {} from global queue", co.get_co_id());
-                self.new_spawned.push_back(co);
+                let co = ptr::NonNull::from(Box::leak(Box::new(*co)));
+                self.push_fair(co);
                 self.len += 1;
             }
         }
@@ -139,6 +170,15 @@ impl Worker {
     pub fn run(&mut self) {
         loop {
             if current_is_none() {
+                // IMMEDIATE关闭时已经选中的任务也要处理，轮转中的任务不会让worker停不下来
+                if self.curr.is_some()
+                    && self.scheduler.get_shutdown_mode() == Some(ShutdownMode::IMMEDIATE)
+                {
+                    if self.shutdown(ShutdownMode::IMMEDIATE) {
+                        return;
+                    }
+                    self.set_curr();
+                }
                 if let Some(mut co) = self.curr.take() {
                     let co = unsafe { co.as_mut() };
                     if co.get_status() == CoStatus::PENDING {
@@ -152,10 +192,23 @@ impl Worker {
                     // );
                     self.run_co(co.into(), self.worker_id);
                 } else {
-                    if self.len < self.capacity / 2 {
+                    if let Some(mode) = self.scheduler.get_shutdown_mode() {
+                        if self.shutdown(mode) {
+                            return;
+                        }
+                    } else if self.scheduler.is_retiring(self.worker_id) {
+                        // 任务清空后退出
+                        if self.drain() {
+                            return;
+                        }
+                    } else if self.len < self.capacity / 2 {
                         self.get_task();
                     }
                     self.set_curr();
+                    // 空闲时回到LO模式
+                    if self.curr.is_none() && self.len == 0 && self.get_mode() == Criticality::HI {
+                        self.switch_mode(Criticality::LO);
+                    }
                 }
             }
         }
@@ -171,12 +224,409 @@ impl Worker {
                 self.realtime_status.push(curr.get_schedulestatus());
                 self.realtime_queue.insert(curr.get_co_id(), curr.into());
             } else {
-                self.suspend_queue.push_back(curr.into());
+                self.push_fair(curr.into());
             }
             curr.suspend(&self.scheduler, self.worker_id);
         }
     }
 
+    /**
+     * 退役前清空任务
+     * MIGRATE: 还没开始运行的任务交还给调度器，已经开始运行的任务在本地完成
+     * FINISH: 所有任务都在本地完成
+     * 没有任务时返回true
+     */
+    fn drain(&mut self) -> bool {
+        self.get_task();
+        if let Some(co) = self.scheduler.get_slots(self.worker_id) {
+            self.scheduler.migrate(co);
+        }
+        if self.scheduler.get_retire_mode(self.worker_id) == Some(RetireMode::MIGRATE) {
+            let ids: Vec<u64> = self.realtime_queue.keys().copied().collect();
+            for id in ids {
+                let co = self.realtime_queue.get(&id).unwrap();
+                if unsafe { co.as_ref() }.get_status() != CoStatus::PENDING {
+                    continue;
+                }
+                let co = self.realtime_queue.remove(&id).unwrap();
+                let co = unsafe { Box::from_raw(co.as_ptr()) };
+                self.scheduler.delete_status(id, self.worker_id);
+                self.scheduler.migrate(co);
+                self.len -= 1;
+            }
+            self.realtime_status = self
+                .realtime_queue
+                .values()
+                .map(|co| unsafe { co.as_ref() }.get_schedulestatus())
+                .collect();
+
+            let mut started = Vec::new();
+            while let Some(co) = self.fair_queue.pop() {
+                if unsafe { co.as_ref() }.get_status() == CoStatus::PENDING {
+                    let co = unsafe { Box::from_raw(co.as_ptr()) };
+                    let _ = self.scheduler.push(co, false, self.worker_id);
+                    self.len -= 1;
+                } else {
+                    started.push(co);
+                }
+            }
+            for co in started {
+                self.push_fair(co);
+            }
+        }
+        self.len == 0
+    }
+
+    /**
+     * 关闭前清空任务
+     * IMMEDIATE: 取消还没开始运行的任务，已经开始运行的wasm调用在下一个epoch检查点trap，
+     *            已经开始运行的原生闭包不再调度，也不释放它的栈
+     * DRAINADMITTED: 完成已经分发到这个worker的任务
+     * DRAINALL: 完成已经分发到这个worker的任务和全局队列中的任务
+     * 可以退出时返回true
+     */
+    fn shutdown(&mut self, mode: ShutdownMode) -> bool {
+        self.get_task();
+        if mode == ShutdownMode::IMMEDIATE {
+            if let Some(co) = self.scheduler.get_slots(self.worker_id) {
+                self.cancel(ptr::NonNull::from(Box::leak(co)));
+            }
+            let mut queued: Vec<ptr::NonNull<Coroutine>> = std::mem::take(&mut self.realtime_queue)
+                .into_values()
+                .collect();
+            self.realtime_status.clear();
+            queued.extend(self.curr.take());
+            queued.extend(self.local_queue.drain(..));
+            while let Some(co) = self.fair_queue.pop() {
+                queued.push(co);
+            }
+            for mut co in queued {
+                let c = unsafe { co.as_mut() };
+                if !c.is_started() {
+                    self.cancel(co);
+                    self.len -= 1;
+                } else if c.is_cooperative() || c.is_killed() {
+                    // 恢复运行后trap，由任务自己结束
+                    c.kill();
+                    c.degrade();
+                    self.push_fair(co);
+                } else {
+                    self.abandon(co);
+                    self.len -= 1;
+                }
+            }
+            return self.len == 0;
+        }
+        // 抢占槽中的任务已经准入
+        if let Some(co) = self.scheduler.get_slots(self.worker_id) {
+            self.add_realtime(co);
+            self.len += 1;
+        }
+        self.len == 0 && (mode != ShutdownMode::DRAINALL || self.scheduler.get_length() == 0)
+    }
+
+    /// 取消还没开始运行的任务并释放协程
+    fn cancel(&mut self, mut co: ptr::NonNull<Coroutine>) {
+        let c = unsafe { co.as_mut() };
+        debug_assert!(!c.is_started());
+        c.cancel();
+        self.scheduler.update_completed_status(
+            c.get_co_id(),
+            c.get_schedulestatus(),
+            self.worker_id,
+        );
+        Self::drop_coroutine(co);
+    }
+
+    /**
+     * 放弃已经开始运行的原生闭包
+     * 通知调用者任务被取消，但不释放协程，运行到一半的栈不能安全丢弃
+     */
+    fn abandon(&mut self, mut co: ptr::NonNull<Coroutine>) {
+        let c = unsafe { co.as_mut() };
+        tracing::warn!("abandon started task {}", c.get_co_id());
+        c.cancel();
+        self.scheduler.update_completed_status(
+            c.get_co_id(),
+            c.get_schedulestatus(),
+            self.worker_id,
+        );
+    }
+
+    /// 非实时任务按分组放入公平队列
+    fn push_fair(&mut self, co: ptr::NonNull<Coroutine>) {
+        let c = unsafe { co.as_ref() };
+        let (group, weight) = (c.get_group().to_owned(), c.get_weight());
+        self.fair_queue.push(&group, weight, co);
+    }
+
+    /// 所属Runtime的抢占信号和定时器信号
+    pub fn get_signals(&self) -> (nix::sys::signal::Signal, nix::sys::signal::Signal) {
+        (
+            self.scheduler.get_preempt_signal(),
+            self.scheduler.get_timer_signal(),
+        )
+    }
+
+    pub fn get_mode(&self) -> Criticality {
+        self.scheduler.get_mode(self.worker_id)
+    }
+
+    /// 记录调度事件
+    pub fn trace(&self, kind: TraceKind, co_id: u64) {
+        self.scheduler.trace(self.worker_id, kind, co_id, 0);
+    }
+
+    /// 看门狗要求处理的是不是当前任务
+    pub fn watchdog_request(&self, co_id: u64) -> Option<WatchdogAction> {
+        self.scheduler.take_watchdog_request(self.worker_id, co_id)
+    }
+
+    /**
+     * 处理看门狗发现的失控任务，在定时器信号处理函数或epoch回调中调用
+     * 只记录要求并切回worker栈，由run_co降级为非实时任务，释放实时容量
+     * 原生闭包运行到一半的栈不能安全丢弃，KILL只对正在执行的wasm调用有效(在epoch检查点trap)，
+     * 其他情况和DEMOTE一样处理
+     */
+    pub fn watchdog(&mut self, action: WatchdogAction) {
+        if action == WatchdogAction::REPORT {
+            return;
+        }
+        let mut curr = match current() {
+            Some(curr) => curr,
+            None => return,
+        };
+        self.demote_pending = true;
+        unsafe { curr.as_mut() }.suspend(&self.scheduler, self.worker_id);
+    }
+
+    /// 在worker栈上降级被看门狗发现的任务，和其他非实时任务轮转
+    fn demote(&mut self, mut co: ptr::NonNull<Coroutine>) {
+        let c = unsafe { co.as_mut() };
+        if c.is_realtime() {
+            tracing::warn!("watchdog demote task {}", c.get_co_id());
+            c.degrade();
+        }
+        c.set_status(CoStatus::SUSPENDED);
+        self.scheduler
+            .update_status(c.get_co_id(), c.get_schedulestatus(), self.worker_id);
+        self.push_fair(co);
+        self.get_task();
+    }
+
+    /**
+     * 在信号处理函数中要求切换到HI模式
+     * 切换需要分配内存、加锁、释放协程，不能在信号处理函数中进行，
+     * 这里只记录请求并切回worker栈，由run_co切换后把当前任务放回实时队列
+     */
+    pub fn defer_mode_switch(&mut self) {
+        let mut curr = match current() {
+            Some(curr) => curr,
+            None => return,
+        };
+        self.mode_pending = true;
+        unsafe { curr.as_mut() }.suspend(&self.scheduler, self.worker_id);
+    }
+
+    /**
+     * 当前任务正在执行wasm时，只记录信号并推进epoch，返回true
+     * 在信号处理函数中调用，由epoch回调在安全的检查点处理
+     */
+    pub fn defer_signal(&self, signal: Signal) -> bool {
+        match current() {
+            Some(co) if unsafe { co.as_ref() }.is_cooperative() => {}
+            _ => return false,
+        }
+        let (preempt_signal, timer_signal) = self.get_signals();
+        let deferred = if signal == preempt_signal {
+            DEFERRED_PREEMPT
+        } else if signal == timer_signal {
+            DEFERRED_TIMER
+        } else {
+            return false;
+        };
+        self.deferred.fetch_or(deferred, Ordering::Relaxed);
+        self.ticks.fetch_add(1, Ordering::Relaxed);
+        epoch::tick();
+        true
+    }
+
+    /// 这个worker推进epoch的次数
+    pub fn get_ticks(&self) -> u64 {
+        self.ticks.load(Ordering::Relaxed)
+    }
+
+    /**
+     * epoch回调，在协程栈上处理推迟的信号，和信号处理函数的逻辑一致
+     * check为true时wasm还在执行，超出截止时间或预算、被看门狗终止的任务返回错误，让wasm调用trap；
+     * wasm调用返回后check为false，只处理调度
+     */
+    pub fn epoch(&mut self, check: bool) -> Result<(), Error> {
+        self.handle_deferred(check)?;
+        // 挂起期间被丢弃的任务恢复运行后trap
+        match current() {
+            Some(curr) if check && unsafe { curr.as_ref() }.is_killed() => {
+                Err(Error::msg("task dropped"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn handle_deferred(&mut self, check: bool) -> Result<(), Error> {
+        let curr = match current() {
+            Some(curr) => unsafe { curr.as_ref() },
+            None => return Ok(()),
+        };
+        // trap后推迟的信号留给wasm调用返回后处理
+        if check {
+            let config = self.scheduler.get_epoch();
+            if config.trap_deadline && curr.missed_deadline() {
+                return Err(Error::msg("deadline exceeded"));
+            }
+            if config.trap_budget && curr.is_realtime() && curr.overrun(curr.get_criticality()) {
+                return Err(Error::msg("budget exceeded"));
+            }
+        }
+        // 每个信号只清除自己的位，同时推迟的定时器信号在任务恢复运行后继续处理
+        let preempt = self
+            .deferred
+            .fetch_and(!DEFERRED_PREEMPT, Ordering::Relaxed);
+        let preempted = preempt & DEFERRED_PREEMPT != 0 && self.preemptive();
+        if preempted {
+            self.trace_curr(TraceKind::Preempt);
+            self.count_preemption();
+            self.suspend();
+        }
+        let timer = self.deferred.fetch_and(!DEFERRED_TIMER, Ordering::Relaxed);
+        if timer & DEFERRED_TIMER == 0 {
+            return Ok(());
+        }
+        self.trace(TraceKind::Timer, curr.get_co_id());
+        match self.watchdog_request(curr.get_co_id()) {
+            // 正在执行的wasm调用直接trap，不丢弃栈
+            Some(WatchdogAction::KILL) if check => {
+                tracing::warn!("watchdog trap task {}", curr.get_co_id());
+                return Err(Error::msg("killed by watchdog"));
+            }
+            Some(action) => {
+                self.watchdog(action);
+                return Ok(());
+            }
+            None => {}
+        }
+        if curr.is_realtime() {
+            if curr.get_criticality() == Criticality::HI
+                && curr.overrun_lo()
+                && self.get_mode() == Criticality::LO
+            {
+                self.switch_mode(Criticality::HI);
+            }
+            unsafe { get_timer().as_mut() }.arm(curr.next_event(self.get_mode()));
+            return Ok(());
+        }
+        // 被抢占后重新运行时已经重新设置了时间片
+        if preempted {
+            return Ok(());
+        }
+        self.get_task();
+        if self.len > 1 {
+            self.set_curr();
+            self.suspend();
+        } else {
+            // 没有其他任务，继续运行并重新设置时间片
+            let timer = unsafe { get_timer().as_mut() };
+            timer.arm(timer.get_quantum());
+        }
+        Ok(())
+    }
+
+    /// 抢占计数，只有原子操作，可以在信号处理函数中调用
+    pub fn count_preemption(&self) {
+        self.scheduler.get_metrics().preemption(self.worker_id);
+    }
+
+    /// 记录当前选中任务的调度事件
+    pub fn trace_curr(&self, kind: TraceKind) {
+        if let Some(co) = self.curr {
+            self.trace(kind, unsafe { co.as_ref() }.get_co_id());
+        }
+    }
+
+    /**
+     * 切换关键级别模式
+     * 切换到HI模式时，按LoPolicy丢弃或降级所有LO实时任务，已经开始运行的任务不会被释放，
+     * HI任务不再使用虚拟截止时间
+     */
+    pub fn switch_mode(&mut self, mode: Criticality) {
+        self.scheduler.set_mode(self.worker_id, mode);
+        if mode == Criticality::LO {
+            return;
+        }
+        tracing::info!("worker {} switch to HI mode", self.worker_id);
+        // 先把调度器里已经准入的任务取过来
+        self.get_task();
+        let ids: Vec<u64> = self.realtime_queue.keys().copied().collect();
+        for id in ids {
+            let co = self.realtime_queue.remove(&id).unwrap();
+            if let Some(co) = self.shed_lo(co) {
+                self.realtime_queue.insert(id, co);
+            }
+        }
+        if let Some(co) = self.curr.take() {
+            self.curr = self.shed_lo(co);
+        }
+        if let Some(mut curr) = current() {
+            unsafe { curr.as_mut() }.clear_virtual_deadline();
+        }
+        // 按真实截止时间重建堆
+        self.realtime_status = self
+            .realtime_queue
+            .values()
+            .map(|co| unsafe { co.as_ref() }.get_schedulestatus())
+            .collect();
+    }
+
+    /// 处理一个任务，返回仍需按原方式调度的任务
+    fn shed_lo(&mut self, mut co: ptr::NonNull<Coroutine>) -> Option<ptr::NonNull<Coroutine>> {
+        let c = unsafe { co.as_mut() };
+        if !c.is_realtime() {
+            return Some(co);
+        }
+        if c.get_criticality() == Criticality::HI {
+            c.clear_virtual_deadline();
+            self.scheduler
+                .update_status(c.get_co_id(), c.get_schedulestatus(), self.worker_id);
+            return Some(co);
+        }
+        let policy = self.scheduler.get_lo_policy();
+        match policy {
+            // 只释放还没开始运行的任务，运行到一半的栈不能安全丢弃
+            LoPolicy::DROP if !c.is_started() => {
+                c.cancel();
+                self.len -= 1;
+                self.scheduler.update_completed_status(
+                    c.get_co_id(),
+                    c.get_schedulestatus(),
+                    self.worker_id,
+                );
+                Self::drop_coroutine(co);
+                None
+            }
+            LoPolicy::DROP | LoPolicy::DEGRADE => {
+                // 已经开始运行的wasm调用在下一个epoch检查点trap，原生闭包降级后继续运行
+                if policy == LoPolicy::DROP && c.is_cooperative() {
+                    c.kill();
+                }
+                c.degrade();
+                self.scheduler
+                    .update_status(c.get_co_id(), c.get_schedulestatus(), self.worker_id);
+                // 作为非实时任务继续排队
+                self.push_fair(co);
+                None
+            }
+        }
+    }
+
     // TODO: spawn local
     pub fn _spawn_local(&mut self, f: Box<dyn FnOnce()>) {
         let co = Coroutine::new(f, StackSize::default(), true, None, None);
@@ -188,12 +638,56 @@ impl Worker {
 
     fn run_co(&mut self, mut co: ptr::NonNull<Coroutine>, worker_id: u8) {
         // tracing::info!("running coroutine");
-        unsafe { get_timer().as_mut().reset_timer() };
-
         let c = unsafe { co.as_mut() };
-        if c.resume(&self.scheduler, self.worker_id) {
+        // 只为下一次调度事件设置单次定时器
+        let timer = unsafe { get_timer().as_mut() };
+        if c.is_realtime() {
+            // 预算耗尽或截止时间
+            timer.arm(c.next_event(self.get_mode()));
+        } else {
+            // 非实时任务运行时总是设置时间片，之后提交的任务不会被饿死
+            timer.arm(timer.get_quantum());
+        }
+
+        let running_time = c.get_running_time();
+        self.trace(TraceKind::Resume, c.get_co_id());
+        let start = Instant::now();
+        self.scheduler
+            .set_running_since(self.worker_id, Some(start));
+        let resumed = c.resume(&self.scheduler, self.worker_id);
+        self.scheduler.set_running_since(self.worker_id, None);
+        self.scheduler
+            .get_metrics()
+            .context_switch(self.worker_id, start.elapsed());
+        // 被看门狗要求降级的任务
+        if std::mem::take(&mut self.demote_pending) && resumed {
+            self.demote(co);
+            self.trace(TraceKind::Suspend, c.get_co_id());
+            return;
+        }
+        // 当前任务在信号处理函数中让出，先放回实时队列，切换模式时一起处理
+        if std::mem::take(&mut self.mode_pending) {
+            if resumed {
+                c.set_status(CoStatus::SUSPENDED);
+                self.realtime_queue.insert(c.get_co_id(), co);
+            }
+            self.switch_mode(Criticality::HI);
+            if resumed {
+                self.trace(TraceKind::Suspend, c.get_co_id());
+                return;
+            }
+        }
+        if !c.is_realtime() {
+            // 按实际运行时间累计分组的虚拟运行时间
+            let delta = c.get_running_time().saturating_sub(running_time);
+            self.fair_queue.charge(c.get_group(), c.get_weight(), delta);
+            self.scheduler.charge(c.get_group(), c.get_weight(), delta);
+        }
+        if resumed {
+            self.trace(TraceKind::Suspend, c.get_co_id());
             return;
         }
+        self.trace(TraceKind::Complete, c.get_co_id());
         self.len -= 1;
         self.scheduler
             .update_completed_status(c.get_co_id(), c.get_schedulestatus(), worker_id);
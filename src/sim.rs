@@ -0,0 +1,660 @@
+use crate::{
+    clock::VirtualClock,
+    runtime::{AdmissionControl, LoPolicy, Runtime},
+    scheduler::{fair::FairQueue, Scheduler, NICE_0_WEIGHT},
+    task::{get_id, CoStatus, Criticality, SchedulerStatus},
+};
+use anyhow::Error;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BinaryHeap},
+    fmt, fs,
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+/// 与真实worker相同的本地队列容量
+const CAPACITY: usize = 256;
+
+fn default_count() -> u32 {
+    1
+}
+
+/// 工作负载中的一个任务，时间单位都是微秒
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SimTask {
+    pub name: String,
+    #[serde(default)]
+    pub arrival_us: u64, //第一次到达的时间
+    pub execution_time_us: u64, //模拟的实际执行时间
+    #[serde(default)]
+    pub expected_execution_time_us: Option<u64>, //准入控制使用的预期执行时间，默认等于实际执行时间
+    #[serde(default)]
+    pub deadline_us: Option<u64>, //相对截止时间，没有则是非实时任务
+    #[serde(default)]
+    pub criticality: String, //关键级别 LO/HI，默认LO
+    #[serde(default)]
+    pub expected_execution_time_hi_us: Option<u64>, //HI级别的预期执行时间
+    #[serde(default)]
+    pub period_us: u64, //到达周期，0表示只到达一次
+    #[serde(default = "default_count")]
+    pub count: u32, //周期任务到达的次数
+    #[serde(default)]
+    pub group: String, //公平调度的分组，默认使用name
+    #[serde(default)]
+    pub weight: u32, //公平调度的权重，默认1024
+}
+
+/// 模拟运行的工作负载
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Workload {
+    pub workers: u8,
+    #[serde(default)]
+    pub quantum_us: u64, //非实时任务的时间片，0表示不分时间片
+    #[serde(default)]
+    pub lo_policy: String, //HI模式下LO任务的处理方式 drop/degrade，默认degrade
+    pub tasks: Vec<SimTask>,
+}
+
+impl Workload {
+    /// 从JSON文件读取
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Workload, Error> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(str: &str) -> Result<Workload, Error> {
+        Ok(serde_json::from_str(str)?)
+    }
+
+    fn get_lo_policy(&self) -> Result<LoPolicy, Error> {
+        match self.lo_policy.to_ascii_lowercase().as_str() {
+            "" | "degrade" => Ok(LoPolicy::DEGRADE),
+            "drop" => Ok(LoPolicy::DROP),
+            _ => Err(Error::msg("Invalid_lo_policy")),
+        }
+    }
+}
+
+/// 任务的最终结果
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub enum Outcome {
+    Completed,
+    Rejected,
+    Dropped,
+}
+
+/// 一个任务实例的模拟结果
+#[derive(Serialize, Clone, Debug)]
+pub struct SimRecord {
+    pub job: usize,
+    pub name: String,
+    pub worker: Option<u8>,
+    pub admission: String,
+    pub arrival_us: u64,
+    pub start_us: Option<u64>,
+    pub finish_us: Option<u64>,
+    pub deadline_us: Option<u64>, //绝对截止时间
+    pub degraded: bool,
+    pub missed: bool,
+    pub outcome: Outcome,
+}
+
+/// worker上一段连续的运行
+#[derive(Serialize, Clone, Debug)]
+pub struct SimSlice {
+    pub worker: u8,
+    pub job: usize,
+    pub name: String,
+    pub start_us: u64,
+    pub end_us: u64,
+}
+
+/// 模拟运行的结果，相同的工作负载得到相同的结果
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct SimReport {
+    pub jobs: usize,
+    pub realtime: usize,
+    pub admitted: usize,
+    pub preemptive: usize,
+    pub rejected: usize,
+    pub completed: usize,
+    pub dropped: usize,
+    pub degraded: usize,
+    pub deadline_misses: usize,
+    pub admission_ratio: f64, //实时任务的准入比例
+    pub makespan_us: u64,
+    pub records: Vec<SimRecord>,
+    pub schedule: Vec<SimSlice>,
+}
+
+impl fmt::Display for SimReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "jobs: {}, realtime: {}", self.jobs, self.realtime)?;
+        writeln!(
+            f,
+            "admitted: {} (preemptive: {}), rejected: {}, admission ratio: {:.4}",
+            self.admitted, self.preemptive, self.rejected, self.admission_ratio
+        )?;
+        writeln!(
+            f,
+            "completed: {}, dropped: {}, degraded: {}, deadline misses: {}",
+            self.completed, self.dropped, self.degraded, self.deadline_misses
+        )?;
+        writeln!(f, "makespan: {}us", self.makespan_us)
+    }
+}
+
+/**
+ * 在虚拟时钟上模拟运行工作负载
+ * 准入控制使用真实的Runtime，worker按真实worker的规则调度：
+ * 实时任务按EDF运行，PREEMPTIVE的任务立即抢占，非实时任务按分组公平调度，
+ * HI任务超出LO级别预算时切换到HI模式
+ * 虚拟时钟是线程局部的，模拟在调用线程中完成
+ */
+pub fn simulate(workload: &Workload) -> Result<SimReport, Error> {
+    if workload.workers == 0 {
+        return Err(Error::msg("no workers"));
+    }
+    let mut sim = Simulator::new(workload)?;
+    sim.run();
+    Ok(sim.report())
+}
+
+struct Job {
+    name: String,
+    arrival: Duration,
+    exec: Duration,
+    eet: Option<Duration>,
+    ddl: Option<Duration>,
+    criticality: Criticality,
+    eet_hi: Option<Duration>,
+    group: String,
+    weight: u32,
+
+    remaining: Duration,
+    status: SchedulerStatus,
+    worker: Option<u8>,
+    admission: Option<AdmissionControl>,
+    start: Option<Duration>,
+    finish: Option<Duration>,
+    degraded: bool,
+    outcome: Option<Outcome>,
+}
+
+impl Job {
+    fn is_realtime(&self) -> bool {
+        self.status.absolute_deadline.is_some()
+    }
+}
+
+struct SimWorker {
+    curr: Option<usize>,
+    since: Duration,           // curr开始运行的时间
+    quantum: Option<Duration>, // 时间片到期的时间
+    realtime_status: BinaryHeap<SchedulerStatus>,
+    realtime_queue: BTreeMap<u64, usize>, // 按co_id排序，模式切换时按确定的顺序处理
+    fair_queue: FairQueue<usize>,
+}
+
+impl SimWorker {
+    fn len(&self) -> usize {
+        self.realtime_queue.len() + self.fair_queue.len() + self.curr.map_or(0, |_| 1)
+    }
+}
+
+struct Simulator {
+    clock: VirtualClock,
+    runtime: Runtime,
+    jobs: Vec<Job>,
+    workers: Vec<SimWorker>,
+    global_queue: FairQueue<usize>,
+    quantum: Option<Duration>,
+    lo_policy: LoPolicy,
+    schedule: Vec<SimSlice>,
+}
+
+impl Simulator {
+    fn new(workload: &Workload) -> Result<Simulator, Error> {
+        let us = Duration::from_micros;
+        let mut jobs = Vec::new();
+        for task in workload.tasks.iter() {
+            let criticality = match Criticality::parse(&task.criticality) {
+                Some(criticality) => criticality,
+                None => return Err(Error::msg("Invalid_criticality")),
+            };
+            for k in 0..task.count.max(1) as u64 {
+                let exec = us(task.execution_time_us);
+                jobs.push(Job {
+                    name: task.name.clone(),
+                    arrival: us(task.arrival_us + k * task.period_us),
+                    exec,
+                    eet: Some(task.expected_execution_time_us.map_or(exec, us)),
+                    ddl: task.deadline_us.map(us),
+                    criticality,
+                    eet_hi: task.expected_execution_time_hi_us.map(us),
+                    group: if task.group.is_empty() {
+                        task.name.clone()
+                    } else {
+                        task.group.clone()
+                    },
+                    weight: if task.weight == 0 {
+                        NICE_0_WEIGHT
+                    } else {
+                        task.weight
+                    },
+                    remaining: exec,
+                    status: SchedulerStatus::new(None, None),
+                    worker: None,
+                    admission: None,
+                    start: None,
+                    finish: None,
+                    degraded: false,
+                    outcome: None,
+                });
+            }
+        }
+        // 稳定排序，同时到达的任务按工作负载中的顺序
+        jobs.sort_by_key(|job| job.arrival);
+
+        let clock = VirtualClock::start();
+        let runtime = Runtime::simulated(workload.workers);
+        let lo_policy = workload.get_lo_policy()?;
+        runtime.set_lo_policy(lo_policy);
+        let workers = (0..workload.workers)
+            .map(|_| SimWorker {
+                curr: None,
+                since: Duration::ZERO,
+                quantum: None,
+                realtime_status: BinaryHeap::new(),
+                realtime_queue: BTreeMap::new(),
+                fair_queue: FairQueue::new(),
+            })
+            .collect();
+        Ok(Simulator {
+            clock,
+            runtime,
+            jobs,
+            workers,
+            global_queue: FairQueue::new(),
+            quantum: Some(Duration::from_micros(workload.quantum_us)).filter(|q| !q.is_zero()),
+            lo_policy,
+            schedule: Vec::new(),
+        })
+    }
+
+    fn scheduler(&self) -> &Arc<Scheduler> {
+        self.runtime.get_scheduler()
+    }
+
+    fn now(&self) -> Duration {
+        self.clock.elapsed()
+    }
+
+    fn run(&mut self) {
+        let mut next_job = 0;
+        loop {
+            let arrival = self.jobs.get(next_job).map(|job| job.arrival);
+            let event = (0..self.workers.len())
+                .filter_map(|w| self.next_event(w))
+                .min();
+            let t = match (arrival, event) {
+                (Some(arrival), Some(event)) => arrival.min(event),
+                (arrival, event) => match arrival.or(event) {
+                    Some(t) => t,
+                    None => break,
+                },
+            };
+            self.clock.advance_to(t);
+            // 先处理worker上的事件，再处理同时到达的任务
+            for w in 0..self.workers.len() {
+                self.handle_event(w);
+            }
+            self.dispatch_all();
+            while next_job < self.jobs.len() && self.jobs[next_job].arrival == t {
+                self.arrive(next_job);
+                next_job += 1;
+            }
+        }
+    }
+
+    /// worker上下一个事件的时间：任务完成、HI任务超出LO预算或时间片到期
+    fn next_event(&self, w: usize) -> Option<Duration> {
+        let worker = &self.workers[w];
+        let j = worker.curr?;
+        let job = &self.jobs[j];
+        let mut next = worker.since + job.remaining;
+        if let Some(overrun) = self.overrun_at(w) {
+            next = next.min(overrun);
+        }
+        if let Some(quantum) = worker.quantum {
+            next = next.min(quantum);
+        }
+        Some(next)
+    }
+
+    /// LO模式下HI任务超出LO级别预算的时间
+    fn overrun_at(&self, w: usize) -> Option<Duration> {
+        let worker = &self.workers[w];
+        let job = &self.jobs[worker.curr?];
+        if !job.is_realtime()
+            || job.status.criticality != Criticality::HI
+            || self.scheduler().get_mode(w as u8) != Criticality::LO
+        {
+            return None;
+        }
+        let used = job.exec - job.remaining;
+        let budget = job.status.get_wcet(Criticality::LO)?.checked_sub(used)?;
+        if budget < job.remaining {
+            Some(worker.since + budget)
+        } else {
+            None
+        }
+    }
+
+    fn handle_event(&mut self, w: usize) {
+        let now = self.now();
+        let j = match self.workers[w].curr {
+            Some(j) => j,
+            None => return,
+        };
+        if self.workers[w].since + self.jobs[j].remaining <= now {
+            self.complete(w);
+        } else if self.overrun_at(w) == Some(now) {
+            self.switch_mode(w, Criticality::HI);
+        } else if self.workers[w].quantum == Some(now) {
+            self.quantum_expired(w);
+        }
+    }
+
+    fn arrive(&mut self, j: usize) {
+        let job = &self.jobs[j];
+        let res = self
+            .runtime
+            .reserve(job.ddl.and(job.eet), job.ddl, job.criticality, job.eet_hi)
+            .into_result();
+        let ac = res.get_ac();
+        self.jobs[j].admission = Some(ac);
+        match ac {
+            AdmissionControl::NOTREALTIME => {
+                let mut status = SchedulerStatus::new(None, None);
+                status.init(get_id());
+                self.set_status(j, status);
+                let job = &self.jobs[j];
+                self.global_queue.push(&job.group, job.weight, j);
+            }
+            AdmissionControl::PREEMPTIVE | AdmissionControl::SCHEDULABLE => {
+                let w = res.get_worker_id().unwrap_or_default() as usize;
+                self.set_status(j, res.get_costatus().unwrap().clone());
+                self.jobs[j].worker = Some(w as u8);
+                self.update_status(w, j);
+                if ac == AdmissionControl::PREEMPTIVE {
+                    // 与slot抢占相同，挂起当前任务
+                    if self.workers[w].curr.is_some() {
+                        let curr = self.stop(w, CoStatus::SUSPENDED);
+                        self.requeue(w, curr);
+                    }
+                    self.start(w, j);
+                } else {
+                    self.push_realtime(w, j);
+                }
+            }
+            AdmissionControl::UNSCHEDULABLE => {
+                self.jobs[j].outcome = Some(Outcome::Rejected);
+            }
+        }
+        // 准入控制要求有任务的worker一定有正在运行的任务
+        self.dispatch_all();
+    }
+
+    fn set_status(&mut self, j: usize, mut status: SchedulerStatus) {
+        let job = &mut self.jobs[j];
+        status.group = job.group.clone();
+        status.weight = job.weight;
+        job.status = status;
+    }
+
+    fn update_status(&self, w: usize, j: usize) {
+        let status = &self.jobs[j].status;
+        self.scheduler()
+            .update_status(status.get_co_id(), status.clone(), w as u8);
+    }
+
+    fn push_realtime(&mut self, w: usize, j: usize) {
+        let worker = &mut self.workers[w];
+        worker.realtime_status.push(self.jobs[j].status.clone());
+        worker
+            .realtime_queue
+            .insert(self.jobs[j].status.get_co_id(), j);
+    }
+
+    /// 被挂起的任务回到worker的队列
+    fn requeue(&mut self, w: usize, j: usize) {
+        if self.jobs[j].is_realtime() {
+            self.push_realtime(w, j);
+        } else {
+            let job = &self.jobs[j];
+            self.workers[w].fair_queue.push(&job.group, job.weight, j);
+        }
+    }
+
+    fn dispatch_all(&mut self) {
+        for w in 0..self.workers.len() {
+            if self.workers[w].curr.is_none() {
+                self.dispatch(w);
+            }
+        }
+    }
+
+    /// 与Worker::set_curr相同：实时任务优先，然后是公平队列
+    fn dispatch(&mut self, w: usize) {
+        if self.workers[w].len() < CAPACITY / 2 {
+            self.get_task(w);
+        }
+        let worker = &mut self.workers[w];
+        let next = match worker.realtime_status.pop() {
+            Some(stat) => worker.realtime_queue.remove(&stat.get_co_id()),
+            None => worker.fair_queue.pop(),
+        };
+        match next {
+            Some(j) => self.start(w, j),
+            None => {
+                // 空闲时回到LO模式
+                if self.scheduler().get_mode(w as u8) == Criticality::HI {
+                    self.scheduler().set_mode(w as u8, Criticality::LO);
+                }
+            }
+        }
+    }
+
+    /// 从全局队列取非实时任务
+    fn get_task(&mut self, w: usize) {
+        while self.workers[w].len() < CAPACITY {
+            let j = match self.global_queue.pop() {
+                Some(j) => j,
+                None => break,
+            };
+            let job = &self.jobs[j];
+            self.workers[w].fair_queue.push(&job.group, job.weight, j);
+        }
+    }
+
+    fn start(&mut self, w: usize, j: usize) {
+        let now = self.now();
+        let job = &mut self.jobs[j];
+        job.status.start_running(self.clock.at(now));
+        job.worker = Some(w as u8);
+        job.start.get_or_insert(now);
+        let realtime = job.is_realtime();
+        let co_id = job.status.get_co_id();
+        self.update_status(w, j);
+        self.scheduler().set_curr_running_id(co_id, w as u8);
+        let worker = &mut self.workers[w];
+        worker.curr = Some(j);
+        worker.since = now;
+        // 有其他任务时才需要时间片
+        worker.quantum = match self.quantum {
+            Some(quantum) if !realtime && (worker.len() > 1 || self.global_queue.len() > 0) => {
+                Some(now + quantum)
+            }
+            _ => None,
+        };
+    }
+
+    /// 停止当前任务并记录运行的区间
+    fn stop(&mut self, w: usize, stat: CoStatus) -> usize {
+        let now = self.now();
+        let worker = &mut self.workers[w];
+        let j = worker.curr.take().unwrap();
+        worker.quantum = None;
+        let ran = now - worker.since;
+        let job = &mut self.jobs[j];
+        job.remaining = job.remaining.saturating_sub(ran);
+        job.status.stop_running(self.clock.at(now), stat);
+        if !ran.is_zero() {
+            self.schedule.push(SimSlice {
+                worker: w as u8,
+                job: j,
+                name: job.name.clone(),
+                start_us: (now - ran).as_micros() as u64,
+                end_us: now.as_micros() as u64,
+            });
+        }
+        if !job.is_realtime() {
+            self.workers[w]
+                .fair_queue
+                .charge(&job.group, job.weight, ran);
+            self.global_queue.charge(&job.group, job.weight, ran);
+        }
+        if stat != CoStatus::COMPLETED {
+            self.update_status(w, j);
+        }
+        j
+    }
+
+    fn complete(&mut self, w: usize) {
+        let j = self.stop(w, CoStatus::COMPLETED);
+        let job = &mut self.jobs[j];
+        job.finish = Some(self.clock.elapsed());
+        job.outcome = Some(Outcome::Completed);
+        let status = job.status.clone();
+        self.scheduler()
+            .update_completed_status(status.get_co_id(), status, w as u8);
+        self.dispatch(w);
+    }
+
+    fn quantum_expired(&mut self, w: usize) {
+        self.workers[w].quantum = None;
+        self.get_task(w);
+        if self.workers[w].fair_queue.len() > 0 {
+            let j = self.stop(w, CoStatus::SUSPENDED);
+            self.requeue(w, j);
+            self.dispatch(w);
+        }
+    }
+
+    /**
+     * 与Worker::switch_mode相同
+     * HI任务使用真实截止时间，LO实时任务按LoPolicy丢弃或降级
+     */
+    fn switch_mode(&mut self, w: usize, mode: Criticality) {
+        self.scheduler().set_mode(w as u8, mode);
+        if mode == Criticality::LO {
+            return;
+        }
+        let queued: Vec<usize> = std::mem::take(&mut self.workers[w].realtime_queue)
+            .into_values()
+            .collect();
+        self.workers[w].realtime_status.clear();
+        for j in queued.into_iter().chain(self.workers[w].curr) {
+            if self.jobs[j].status.criticality == Criticality::HI {
+                self.jobs[j].status.virtual_deadline = None;
+                self.update_status(w, j);
+                if Some(j) != self.workers[w].curr {
+                    self.push_realtime(w, j);
+                }
+                continue;
+            }
+            let co_id = self.jobs[j].status.get_co_id();
+            match self.lo_policy {
+                LoPolicy::DROP => {
+                    let now = self.clock.at(self.now());
+                    let job = &mut self.jobs[j];
+                    job.status.stop_running(now, CoStatus::CANCELLED);
+                    job.outcome = Some(Outcome::Dropped);
+                    let status = job.status.clone();
+                    self.scheduler()
+                        .update_completed_status(co_id, status, w as u8);
+                }
+                LoPolicy::DEGRADE => {
+                    let job = &mut self.jobs[j];
+                    job.status.degrade();
+                    job.degraded = true;
+                    self.update_status(w, j);
+                    self.requeue(w, j);
+                }
+            }
+        }
+    }
+
+    fn report(&self) -> SimReport {
+        let us = |d: Duration| d.as_micros() as u64;
+        let mut report = SimReport {
+            jobs: self.jobs.len(),
+            schedule: self.schedule.clone(),
+            ..Default::default()
+        };
+        for (j, job) in self.jobs.iter().enumerate() {
+            let deadline = job.ddl.map(|ddl| job.arrival + ddl);
+            let missed = !job.degraded
+                && job.outcome == Some(Outcome::Completed)
+                && matches!((job.finish, deadline), (Some(finish), Some(ddl)) if finish > ddl);
+            if job.ddl.is_some() {
+                report.realtime += 1;
+            }
+            match job.admission {
+                Some(AdmissionControl::SCHEDULABLE) => report.admitted += 1,
+                Some(AdmissionControl::PREEMPTIVE) => {
+                    report.admitted += 1;
+                    report.preemptive += 1;
+                }
+                Some(AdmissionControl::UNSCHEDULABLE) => report.rejected += 1,
+                _ => {}
+            }
+            match job.outcome {
+                Some(Outcome::Completed) => report.completed += 1,
+                Some(Outcome::Dropped) => report.dropped += 1,
+                _ => {}
+            }
+            if job.degraded {
+                report.degraded += 1;
+            }
+            if missed {
+                report.deadline_misses += 1;
+            }
+            if let Some(finish) = job.finish {
+                report.makespan_us = report.makespan_us.max(us(finish));
+            }
+            report.records.push(SimRecord {
+                job: j,
+                name: job.name.clone(),
+                worker: job.worker,
+                admission: job
+                    .admission
+                    .map(|ac| format!("{:?}", ac))
+                    .unwrap_or_default(),
+                arrival_us: us(job.arrival),
+                start_us: job.start.map(us),
+                finish_us: job.finish.map(us),
+                deadline_us: deadline.map(us),
+                degraded: job.degraded,
+                missed,
+                outcome: job.outcome.unwrap_or(Outcome::Rejected),
+            });
+        }
+        if report.realtime > 0 {
+            report.admission_ratio = report.admitted as f64 / report.realtime as f64;
+        }
+        report
+    }
+}
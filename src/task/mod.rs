@@ -4,7 +4,8 @@ pub mod stack;
 use self::context::{Context, Entry};
 use self::stack::StackSize;
 use crate::axum::server::LATENCY;
-use crate::scheduler::Scheduler;
+use crate::clock;
+use crate::scheduler::{estimator::FuncKey, Scheduler};
 use std::cell::{Cell, UnsafeCell};
 use std::collections::BTreeMap;
 use std::sync::atomic::{AtomicU64, Ordering};
@@ -12,11 +13,13 @@ use std::sync::Arc;
 use std::time::{Duration, Instant};
 use std::{fmt, panic};
 use std::{mem, ptr};
+use wasmtime_runtime::AsyncWasmCallState;
 
-pub static mut ID: AtomicU64 = AtomicU64::new(1);
+/// 任务id在进程内唯一，多个Runtime的任务id不会冲突
+static ID: AtomicU64 = AtomicU64::new(1);
 
 pub fn get_id() -> u64 {
-    unsafe { ID.fetch_add(1, Ordering::SeqCst) }
+    ID.fetch_add(1, Ordering::SeqCst)
 }
 
 thread_local! {
@@ -43,6 +46,28 @@ pub enum CoStatus {
     // TODO!
 }
 
+/// 任务的关键级别(mixed-criticality)
+///
+/// LO: 尽力而为的任务，HI模式下会被丢弃或降级
+///
+/// HI: 安全相关的任务，HI模式下按HI级别的WCET保证
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Criticality {
+    #[default]
+    LO,
+    HI,
+}
+
+impl Criticality {
+    pub fn parse(str: &str) -> Option<Criticality> {
+        match str.to_ascii_uppercase().as_str() {
+            "" | "LO" => Some(Criticality::LO),
+            "HI" => Some(Criticality::HI),
+            _ => None,
+        }
+    }
+}
+
 struct Scope {
     co: ptr::NonNull<Coroutine>,
 }
@@ -107,6 +132,16 @@ pub struct SchedulerStatus {
     worst_start_time: Option<Instant>,
     relative_deadline: Option<Duration>,
     pub absolute_deadline: Option<Instant>,
+
+    pub criticality: Criticality,
+    wcet_hi: Option<Duration>, // HI级别的WCET，LO级别的WCET即expected_execution_time
+    pub virtual_deadline: Option<Instant>, // EDF-VD的虚拟截止时间，只在LO模式下生效
+
+    pub group: String,         // 公平调度的分组(模块或租户)
+    pub weight: u32,           // 公平调度的权重
+    pub fuel: Option<u64>,     // wasm调用消耗的fuel，模块没有开启fuel计量时为None
+    pub func: Option<FuncKey>, // wasm调用的模块和导出函数，完成时用于学习执行时间
+    pub trapped: bool,         // wasm调用trap(截止时间、预算、fuel、看门狗)，运行时间被截断
 }
 
 impl SchedulerStatus {
@@ -120,13 +155,110 @@ impl SchedulerStatus {
             co_status: CoStatus::PENDING,
             curr_start_time: None,
             running_time: Duration::from_nanos(0),
-            spawn_time: Instant::now(),
+            spawn_time: clock::now(),
             expected_execution_time,
             expected_remaining_execution_time: expected_execution_time,
             worst_start_time: None,
             relative_deadline,
             absolute_deadline: None,
+            criticality: Criticality::LO,
+            wcet_hi: None,
+            virtual_deadline: None,
+            group: String::new(),
+            weight: crate::scheduler::NICE_0_WEIGHT,
+            fuel: None,
+            func: None,
+            trapped: false,
+        }
+    }
+
+    /**
+     * 设置关键级别和HI级别的WCET
+     * HI级别的WCET不能小于LO级别的WCET
+     */
+    pub fn set_criticality(&mut self, criticality: Criticality, wcet_hi: Option<Duration>) {
+        self.criticality = criticality;
+        if criticality == Criticality::HI {
+            self.wcet_hi = match (wcet_hi, self.expected_execution_time) {
+                (Some(hi), Some(lo)) => Some(hi.max(lo)),
+                (hi, lo) => hi.or(lo),
+            };
+        } else {
+            self.wcet_hi = None;
+        }
+    }
+
+    /// 获取对应级别的WCET
+    pub fn get_wcet(&self, level: Criticality) -> Option<Duration> {
+        match level {
+            Criticality::LO => self.expected_execution_time,
+            Criticality::HI => self.wcet_hi.or(self.expected_execution_time),
+        }
+    }
+
+    /// 获取相对截止时间
+    pub fn get_relative_deadline(&self) -> Option<Duration> {
+        self.relative_deadline
+    }
+
+    /// 获取任务生成的时间
+    pub fn get_spawn_time(&self) -> Instant {
+        self.spawn_time
+    }
+
+    /// EDF调度使用的截止时间，LO模式下HI任务使用虚拟截止时间
+    pub fn effective_deadline(&self) -> Option<Instant> {
+        self.virtual_deadline.or(self.absolute_deadline)
+    }
+
+    /// 是否超出了LO级别的预算
+    pub fn overrun_lo(&self, now: Instant) -> bool {
+        self.overrun(now, Criticality::LO)
+    }
+
+    /// 是否超出了对应级别的预算
+    pub fn overrun(&self, now: Instant, level: Criticality) -> bool {
+        let mut running_time = self.running_time;
+        if let Some(start) = self.curr_start_time {
+            running_time += now - start;
+        }
+        match self.get_wcet(level) {
+            Some(wcet) => running_time > wcet,
+            None => false,
+        }
+    }
+
+    /**
+     * 实时任务的下一个调度事件距现在的时间
+     * 取对应级别预算耗尽和截止时间中较早的一个，都已经过去则返回None
+     */
+    pub fn next_event(&self, now: Instant, level: Criticality) -> Option<Duration> {
+        let mut running_time = self.running_time;
+        if let Some(start) = self.curr_start_time {
+            running_time += now - start;
         }
+        let budget = self
+            .get_wcet(level)
+            .and_then(|wcet| wcet.checked_sub(running_time))
+            .filter(|budget| !budget.is_zero());
+        let deadline = self
+            .absolute_deadline
+            .and_then(|ddl| ddl.checked_duration_since(now))
+            .filter(|deadline| !deadline.is_zero());
+        match (budget, deadline) {
+            (Some(budget), Some(deadline)) => Some(budget.min(deadline)),
+            (budget, deadline) => budget.or(deadline),
+        }
+    }
+
+    /**
+     * 降级为非实时任务
+     * HI模式下LO任务不再保证截止时间
+     */
+    pub fn degrade(&mut self) {
+        self.absolute_deadline = None;
+        self.virtual_deadline = None;
+        self.worst_start_time = None;
     }
 
     pub fn init(&mut self, id: u64) {
@@ -164,12 +296,25 @@ impl SchedulerStatus {
     pub fn get_co_id(&self) -> u64 {
         self.co_id
     }
+
+    /// 开始或继续运行，与Coroutine::resume对状态的更新相同
+    pub fn start_running(&mut self, now: Instant) {
+        self.curr_start_time = Some(now);
+        self.update_status(now, CoStatus::RUNNING);
+    }
+
+    /// 停止运行，与Coroutine::suspend对状态的更新相同
+    pub fn stop_running(&mut self, now: Instant, stat: CoStatus) {
+        self.update_status(now, stat);
+        self.update_running_time(now);
+        self.update_remaining();
+    }
 }
 
 impl Ord for SchedulerStatus {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
         // self.absolute_deadline.cmp(&other.absolute_deadline)
-        match self.absolute_deadline.cmp(&other.absolute_deadline) {
+        match self.effective_deadline().cmp(&other.effective_deadline()) {
             std::cmp::Ordering::Less => std::cmp::Ordering::Greater,
             std::cmp::Ordering::Equal => std::cmp::Ordering::Equal,
             std::cmp::Ordering::Greater => std::cmp::Ordering::Less,
@@ -185,7 +330,7 @@ impl PartialOrd for SchedulerStatus {
 
 impl PartialEq for SchedulerStatus {
     fn eq(&self, other: &Self) -> bool {
-        self.absolute_deadline == other.absolute_deadline
+        self.effective_deadline() == other.effective_deadline()
     }
 }
 
@@ -204,6 +349,12 @@ impl fmt::Display for SchedulerStatus {
             let time = start.1 + chrono::Duration::from_std(duration).unwrap();
             writeln!(f, "{}, deadline", time).unwrap();
         }
+        if self.criticality == Criticality::HI {
+            writeln!(f, "criticality: {:?}", self.criticality).unwrap();
+        }
+        if let Some(fuel) = self.fuel {
+            writeln!(f, "fuel consumed: {}", fuel).unwrap();
+        }
         writeln!(f, "running time: {:?}", self.running_time)
     }
 }
@@ -215,6 +366,10 @@ pub struct Coroutine {
     id: u64,
     stack_size: StackSize,
     schedule_status: SchedulerStatus,
+    cancel_hook: Option<Box<dyn FnOnce()>>,
+    wasm_state: AsyncWasmCallState, // 挂起时正在执行的wasm调用
+    cooperative: bool,              // 正在执行wasm，由epoch回调调度
+    killed: bool,                   // 已经开始运行但被丢弃，在下一个epoch检查点trap
 }
 
 unsafe impl Sync for Coroutine {}
@@ -232,12 +387,21 @@ impl Coroutine {
             id: status.co_id,
             stack_size: StackSize::default(),
             schedule_status: status,
+            cancel_hook: None,
+            wasm_state: AsyncWasmCallState::new(),
+            cooperative: false,
+            killed: false,
         });
         co.schedule_status
             .update_status(co.schedule_status.spawn_time, CoStatus::PENDING);
         co
     }
 
+    /// 设置栈大小，需要在init之前
+    pub fn set_stack_size(&mut self, stack_size: StackSize) {
+        self.stack_size = stack_size;
+    }
+
     pub fn new(
         f: Box<dyn FnOnce()>,
         stack_size: StackSize,
@@ -254,6 +418,10 @@ impl Coroutine {
             id: get_id(),
             stack_size,
             schedule_status: SchedulerStatus::new(expected_execution_time, relative_deadline),
+            cancel_hook: None,
+            wasm_state: AsyncWasmCallState::new(),
+            cooperative: false,
+            killed: false,
         });
         co.schedule_status
             .update_status(co.schedule_status.spawn_time, CoStatus::PENDING);
@@ -271,7 +439,7 @@ impl Coroutine {
             ));
             co.status = CoStatus::READY;
             co.schedule_status
-                .update_status(Instant::now(), CoStatus::READY);
+                .update_status(clock::now(), CoStatus::READY);
         }
         co
     }
@@ -284,6 +452,11 @@ impl Coroutine {
         self.status
     }
 
+    /// 是否已经开始运行，开始运行后栈上有运行到一半的闭包，不能直接释放
+    pub fn is_started(&self) -> bool {
+        !matches!(self.status, CoStatus::PENDING | CoStatus::READY)
+    }
+
     pub fn init(&mut self) {
         let entry = Entry {
             f: Self::main,
@@ -296,7 +469,7 @@ impl Coroutine {
             Some(Context::new(&entry, None)),
         ));
         self.set_status(CoStatus::READY);
-        let now = Instant::now();
+        let now = clock::now();
         self.schedule_status.update_status(now, self.status);
     }
 
@@ -304,7 +477,7 @@ impl Coroutine {
         let co = unsafe { &mut *(arg as *mut Coroutine) };
         co.run();
         co.status = CoStatus::COMPLETED;
-        let now = Instant::now();
+        let now = clock::now();
         co.schedule_status.update_running_time(now);
         co.schedule_status.update_remaining();
         co.schedule_status.update_status(now, CoStatus::COMPLETED);
@@ -330,7 +503,7 @@ impl Coroutine {
     /// Resumes coroutine.
     pub fn resume(&mut self, sched: &Arc<Scheduler>, worker_id: u8) -> bool {
         // tracing::info!("{}, start resume", self.get_co_id());
-        let now = Instant::now();
+        let now = clock::now();
         self.schedule_status.curr_start_time = Some(now);
         self.status = CoStatus::RUNNING;
         self.schedule_status.update_status(now, self.status);
@@ -342,7 +515,11 @@ impl Coroutine {
         if let Some(context) = &self.context {
             // let now = Instant::now();
             // tracing::info!("resume {}, {:?}", self.get_co_id(), now);
+            // wasmtime在线程局部变量中记录正在执行的wasm调用，随协程一起切换
+            let wasm_state = mem::replace(&mut self.wasm_state, AsyncWasmCallState::new());
+            let prev = unsafe { wasm_state.push() };
             ThisThread::resume(&context);
+            self.wasm_state = unsafe { prev.restore() };
         };
 
         match self.status {
@@ -353,7 +530,7 @@ impl Coroutine {
 
     pub fn suspend(&mut self, sched: &Arc<Scheduler>, worker_id: u8) {
         // tracing::info!("{}, start suspend", self.get_co_id());
-        let now = Instant::now();
+        let now = clock::now();
         self.schedule_status.update_status(now, self.status);
         self.schedule_status.update_running_time(now);
         self.schedule_status.update_remaining();
@@ -383,6 +560,115 @@ impl Coroutine {
             && self.schedule_status.expected_execution_time.is_some()
     }
 
+    pub fn get_criticality(&self) -> Criticality {
+        self.schedule_status.criticality
+    }
+
+    /// 设置公平调度的分组和权重
+    pub fn set_group(&mut self, group: &str, weight: u32) {
+        self.schedule_status.group = group.to_owned();
+        self.schedule_status.weight = weight;
+    }
+
+    pub fn get_group(&self) -> &str {
+        &self.schedule_status.group
+    }
+
+    pub fn get_weight(&self) -> u32 {
+        self.schedule_status.weight
+    }
+
+    pub fn get_running_time(&self) -> Duration {
+        self.schedule_status.running_time
+    }
+
+    /// 当前是否超出了LO级别的预算
+    pub fn overrun_lo(&self) -> bool {
+        self.schedule_status.overrun_lo(clock::now())
+    }
+
+    /// 下一个调度事件(预算耗尽或截止时间)距现在的时间
+    pub fn next_event(&self, level: Criticality) -> Option<Duration> {
+        self.schedule_status.next_event(clock::now(), level)
+    }
+
+    /// 当前是否超出了对应级别的预算
+    pub fn overrun(&self, level: Criticality) -> bool {
+        self.schedule_status.overrun(clock::now(), level)
+    }
+
+    /// 是否已经超过截止时间
+    pub fn missed_deadline(&self) -> bool {
+        self.schedule_status
+            .absolute_deadline
+            .is_some_and(|ddl| clock::now() > ddl)
+    }
+
+    /// 任务执行的wasm模块和导出函数
+    pub fn set_func(&mut self, func: FuncKey) {
+        self.schedule_status.func = Some(func);
+    }
+
+    /// 记录wasm调用消耗的fuel
+    pub fn set_fuel(&mut self, fuel: u64) {
+        self.schedule_status.fuel = Some(fuel);
+    }
+
+    /// 记录wasm调用trap
+    pub fn set_trapped(&mut self) {
+        self.schedule_status.trapped = true;
+    }
+
+    /// 正在执行wasm时信号只推进epoch，由epoch回调在检查点调度
+    pub fn set_cooperative(&mut self, cooperative: bool) {
+        self.cooperative = cooperative;
+    }
+
+    pub fn is_cooperative(&self) -> bool {
+        self.cooperative
+    }
+
+    /**
+     * 丢弃已经开始运行的wasm任务
+     * 任务恢复运行后在epoch检查点trap，wasm调用正常返回，栈由任务自己结束
+     */
+    pub fn kill(&mut self) {
+        self.killed = true;
+    }
+
+    pub fn is_killed(&self) -> bool {
+        self.killed
+    }
+
+    /// 任务被取消时执行，用于通知等待结果的调用者
+    pub fn set_cancel_hook(&mut self, hook: Box<dyn FnOnce()>) {
+        self.cancel_hook = Some(hook);
+    }
+
+    /**
+     * 取消任务
+     * 任务不会再被调度，调用者需要负责回收这个coroutine
+     */
+    pub fn cancel(&mut self) {
+        let now = clock::now();
+        self.status = CoStatus::CANCELLED;
+        self.schedule_status.update_running_time(now);
+        self.schedule_status.update_status(now, CoStatus::CANCELLED);
+        if let Some(hook) = self.cancel_hook.take() {
+            hook();
+        }
+    }
+
+    /// 降级为非实时任务
+    pub fn degrade(&mut self) {
+        self.schedule_status.degrade();
+    }
+
+    /// HI模式下HI任务按真实截止时间调度
+    pub fn clear_virtual_deadline(&mut self) {
+        self.schedule_status.virtual_deadline = None;
+    }
+
     // pub fn set_no_realtime(&mut self) {
     //     self.schedule_status.absolute_deadline = None;
     // }
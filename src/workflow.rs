@@ -0,0 +1,411 @@
+use crate::{
+    axum::WorkflowRequest,
+    result::FuncResult,
+    runtime::{FuncKey, Reservation, Runtime},
+    runwasm::{cvt_params, typed_params, zero, Environment},
+    scheduler::epoch,
+    task::Criticality,
+};
+use anyhow::Error;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use wasmtime::{Func, FuncType, Store, Val, ValType};
+use wasmtime_wasi::{sync::WasiCtxBuilder, WasiCtx};
+
+/// 工作流中的一个节点
+#[derive(Clone, Debug)]
+struct Node {
+    name: String,
+    wasm_name: String,
+    export_func: String,
+    params: Vec<Val>,                     //固定参数
+    args: Option<Vec<serde_json::Value>>, //按签名解析的固定参数，绑定导出函数时转换为params
+    wcet: Duration,                       //预期执行时间
+    criticality: Criticality,             //关键级别
+    deps: Vec<usize>,                     //前驱节点
+}
+
+/// 有向无环图描述的工作流，所有节点共用一个端到端的截止时间
+#[derive(Clone, Debug)]
+pub struct Workflow {
+    nodes: Vec<Node>,
+    deadline: Duration,
+}
+
+impl Workflow {
+    pub fn new(request: WorkflowRequest) -> Result<Workflow, Error> {
+        let deadline = match request.expected_deadline.parse::<u64>() {
+            Ok(deadline) if deadline > 0 => Duration::from_millis(deadline),
+            _ => return Err(Error::msg("Invalid_deadline")),
+        };
+        let mut index = HashMap::new();
+        for (i, node) in request.nodes.iter().enumerate() {
+            if index.insert(node.name.clone(), i).is_some() {
+                return Err(Error::msg(format!("Invalid_node_name: {}", node.name)));
+            }
+        }
+        let mut nodes = Vec::with_capacity(request.nodes.len());
+        for node in request.nodes {
+            let wcet = match node.expected_execution_time.parse::<u64>() {
+                Ok(wcet) if wcet > 0 => Duration::from_millis(wcet),
+                _ => return Err(Error::msg(format!("Invalid_execution_time: {}", node.name))),
+            };
+            let criticality = Criticality::parse(&node.criticality)
+                .ok_or_else(|| Error::msg(format!("Invalid_criticality: {}", node.name)))?;
+            let mut deps = Vec::with_capacity(node.deps.len());
+            for dep in node.deps.iter() {
+                match index.get(dep) {
+                    Some(&i) => deps.push(i),
+                    None => return Err(Error::msg(format!("Invalid_dep: {}", dep))),
+                }
+            }
+            nodes.push(Node {
+                params: cvt_params(node.param_type, node.params)?,
+                args: Some(node.args).filter(|args| !args.is_empty()),
+                name: node.name,
+                wasm_name: node.wasm_name,
+                export_func: node.export_func,
+                wcet,
+                criticality,
+                deps,
+            });
+        }
+        if nodes.is_empty() {
+            return Err(Error::msg("Invalid_workflow"));
+        }
+        let workflow = Workflow { nodes, deadline };
+        // 检查是否有环，以及截止时间是否大于关键路径
+        if workflow.sub_deadlines().is_none() {
+            return Err(Error::msg("Invalid_deadline"));
+        }
+        Ok(workflow)
+    }
+
+    /**
+     * 按导出函数的签名检查每个节点的参数
+     * 节点的参数是固定参数加上前驱节点的返回值，数量和类型都要与签名一致，
+     * 给出了args时按签名中固定参数的部分解析
+     */
+    fn bind(&mut self, envs: &HashMap<String, Environment>) -> Result<Vec<FuncType>, Error> {
+        let mut types = Vec::with_capacity(self.nodes.len());
+        for node in self.nodes.iter() {
+            let env = envs
+                .get(&node.wasm_name)
+                .ok_or_else(|| Error::msg(format!("Invalid_wasm_name: {}", node.wasm_name)))?;
+            let ty = env
+                .get_func_type(&node.export_func)
+                .ok_or_else(|| Error::msg(format!("Invalid_export_func: {}", node.export_func)))?;
+            types.push(ty);
+        }
+        for (node, ty) in self.nodes.iter_mut().zip(types.iter()) {
+            let expected: Vec<ValType> = ty.params().collect();
+            let inputs: Vec<ValType> = node
+                .deps
+                .iter()
+                .flat_map(|&dep| types[dep].results())
+                .collect();
+            if let Some(args) = node.args.take() {
+                let fixed = expected.len().saturating_sub(inputs.len());
+                node.params = typed_params(&node.export_func, &expected[..fixed], &args)?;
+            }
+            let given: Vec<ValType> = node.params.iter().map(Val::ty).chain(inputs).collect();
+            if given.len() != expected.len() {
+                return Err(Error::msg(format!(
+                    "Invalid_params: {} expects {} arguments, got {} params and {} outputs of deps",
+                    node.name,
+                    expected.len(),
+                    node.params.len(),
+                    given.len() - node.params.len()
+                )));
+            }
+            for (i, (expected, given)) in expected.iter().zip(given).enumerate() {
+                if *expected != given {
+                    return Err(Error::msg(format!(
+                        "Invalid_params: {} params[{}] expects {}, got {}",
+                        node.name, i, expected, given
+                    )));
+                }
+            }
+        }
+        Ok(types)
+    }
+
+    /// 每个节点的相对截止时间
+    pub fn sub_deadlines(&self) -> Option<Vec<Duration>> {
+        let wcets: Vec<Duration> = self.nodes.iter().map(|node| node.wcet).collect();
+        let deps: Vec<Vec<usize>> = self.nodes.iter().map(|node| node.deps.clone()).collect();
+        split_deadline(&wcets, &deps, self.deadline)
+    }
+}
+
+/**
+ * 把端到端的截止时间分给每个节点
+ * 节点i的截止时间按到它结束为止的最长路径 L_i 等比例分配: D * L_i / L_max，
+ * 前驱节点的截止时间一定早于后继节点，EDF在同一个worker上会按依赖顺序执行
+ * 有环或者截止时间小于关键路径时返回None
+ */
+pub fn split_deadline(
+    wcets: &[Duration],
+    deps: &[Vec<usize>],
+    deadline: Duration,
+) -> Option<Vec<Duration>> {
+    let n = wcets.len();
+    // 拓扑排序
+    let mut waiting: Vec<usize> = deps.iter().map(|d| d.len()).collect();
+    let mut succs = vec![Vec::new(); n];
+    for (i, d) in deps.iter().enumerate() {
+        for &dep in d {
+            succs.get_mut(dep)?.push(i);
+        }
+    }
+    let mut order: Vec<usize> = (0..n).filter(|&i| waiting[i] == 0).collect();
+    let mut k = 0;
+    while k < order.len() {
+        for &s in succs[order[k]].iter() {
+            waiting[s] -= 1;
+            if waiting[s] == 0 {
+                order.push(s);
+            }
+        }
+        k += 1;
+    }
+    if order.len() != n {
+        return None;
+    }
+    // 到每个节点结束为止的最长路径
+    let mut finish = vec![Duration::ZERO; n];
+    for &i in order.iter() {
+        let start = deps[i].iter().map(|&d| finish[d]).max().unwrap_or_default();
+        finish[i] = start + wcets[i];
+    }
+    let critical = finish.iter().max().copied().unwrap_or_default();
+    if critical.is_zero() || critical > deadline {
+        return None;
+    }
+    let ratio = deadline.as_secs_f64() / critical.as_secs_f64();
+    Some(finish.into_iter().map(|f| f.mul_f64(ratio)).collect())
+}
+
+/// 实例化完成、等待前驱节点结束的节点
+struct NodeRun {
+    store: Store<WasiCtx>,
+    func: Func,
+    params: Vec<Val>,
+    results: Vec<Val>,
+    reservation: Reservation<'static>,
+}
+
+struct RunState {
+    pending: Vec<Option<NodeRun>>,  //还没有提交的节点
+    waiting: Vec<usize>,            //每个节点还没结束的前驱数量
+    outputs: Vec<Option<Vec<Val>>>, //每个节点的输出
+    unfinished: usize,
+    failed: bool,
+}
+
+/// 正在运行的工作流
+struct Run {
+    names: Vec<String>,
+    deps: Vec<Vec<usize>>,
+    succs: Vec<Vec<usize>>,
+    func_result: Arc<FuncResult>,
+    state: Mutex<RunState>,
+}
+
+/**
+ * 工作流调用
+ * 先按签名检查参数并实例化所有节点，再原子地为所有节点预留容量，节点可以在不同的worker上，
+ * 没有前驱的节点立即提交，其他节点在所有前驱结束后提交，前驱的输出作为参数
+ * 返回错误时所有节点都没有提交，由调用者设置结果
+ */
+pub fn call_workflow(
+    rt: &'static Runtime,
+    envs: &HashMap<String, Environment>,
+    mut workflow: Workflow,
+    func_result: &Arc<FuncResult>,
+) -> Result<(), Error> {
+    let sub_deadlines = workflow
+        .sub_deadlines()
+        .ok_or_else(|| Error::msg("Invalid_deadline"))?;
+    let types = workflow.bind(envs)?;
+    let mut instances = Vec::with_capacity(workflow.nodes.len());
+    for (node, ty) in workflow.nodes.iter().zip(types) {
+        let env = &envs[&node.wasm_name];
+        let wasi = WasiCtxBuilder::new()
+            .inherit_stdio()
+            .inherit_args()?
+            .build();
+        let mut store = env.new_store(wasi, None)?;
+        let instance = env.get_linker().instantiate(&mut store, env.get_module())?;
+        let func = instance
+            .get_func(&mut store, &node.export_func)
+            .ok_or_else(|| Error::msg(format!("Invalid_export_func: {}", node.export_func)))?;
+        let results = ty.results().map(zero).collect::<Result<Vec<_>, _>>()?;
+        instances.push((store, func, results));
+    }
+
+    // 原子准入，开启了learned_admission时按学习到的执行时间准入
+    let funcs: Vec<FuncKey> = workflow
+        .nodes
+        .iter()
+        .map(|node| FuncKey::new(&node.wasm_name, &node.export_func))
+        .collect();
+    let tasks: Vec<(Duration, Duration, Criticality)> = workflow
+        .nodes
+        .iter()
+        .zip(&funcs)
+        .zip(sub_deadlines)
+        .map(|((node, func), deadline)| {
+            (
+                rt.admission_wcet(func, node.wcet),
+                deadline,
+                node.criticality,
+            )
+        })
+        .collect();
+    let reservations = rt
+        .reserve_group(&tasks)
+        .ok_or_else(|| Error::msg("spawn failed, cause: UNSCHEDULABLE"))?;
+
+    let n = workflow.nodes.len();
+    let mut succs = vec![Vec::new(); n];
+    for (i, node) in workflow.nodes.iter().enumerate() {
+        for &dep in node.deps.iter() {
+            succs[dep].push(i);
+        }
+    }
+    let pending = workflow
+        .nodes
+        .iter()
+        .zip(instances)
+        .zip(reservations.into_iter().zip(funcs))
+        .map(|((node, (store, func, results)), (reservation, key))| {
+            Some(NodeRun {
+                store,
+                func,
+                params: node.params.clone(),
+                results,
+                reservation: reservation.with_func(key),
+            })
+        })
+        .collect();
+    let run = Arc::new(Run {
+        names: workflow
+            .nodes
+            .iter()
+            .map(|node| node.name.clone())
+            .collect(),
+        deps: workflow
+            .nodes
+            .iter()
+            .map(|node| node.deps.clone())
+            .collect(),
+        succs,
+        func_result: func_result.clone(),
+        state: Mutex::new(RunState {
+            pending,
+            waiting: workflow.nodes.iter().map(|node| node.deps.len()).collect(),
+            outputs: vec![None; n],
+            unfinished: n,
+            failed: false,
+        }),
+    });
+    for i in 0..n {
+        if run.deps[i].is_empty() {
+            start(&run, i);
+        }
+    }
+    Ok(())
+}
+
+/// 提交节点，参数是固定参数加上前驱节点的输出
+fn start(run: &Arc<Run>, i: usize) {
+    let node = {
+        let mut state = run.state.lock().unwrap();
+        let mut node = match state.pending[i].take() {
+            Some(node) => node,
+            None => return,
+        };
+        for &dep in run.deps[i].iter() {
+            node.params
+                .extend(state.outputs[dep].iter().flatten().cloned());
+        }
+        node
+    };
+    let NodeRun {
+        mut store,
+        func,
+        params,
+        mut results,
+        reservation,
+    } = node;
+    let run_1 = run.clone();
+    let f = move || match epoch::cooperative(|| {
+        let res = func.call(&mut store, &params, &mut results);
+        epoch::record_fuel(&store);
+        res
+    }) {
+        Ok(()) => finish(&run_1, i, results),
+        Err(err) => {
+            tracing::warn!("run_workflow_error: {}", err);
+            epoch::record_trap();
+            fail(&run_1, &format!("{:?}", err));
+        }
+    };
+    let run_2 = run.clone();
+    let on_cancel = move || fail(&run_2, "cancelled");
+    if let Err(err) = reservation.commit_with_cancel(f, on_cancel) {
+        fail(run, &format!("{:?}", err));
+    }
+}
+
+/// 节点结束，提交所有前驱都已结束的后继节点
+fn finish(run: &Arc<Run>, i: usize, results: Vec<Val>) {
+    let mut ready = Vec::new();
+    {
+        let mut state = run.state.lock().unwrap();
+        if state.failed {
+            return;
+        }
+        state.outputs[i] = Some(results);
+        state.unfinished -= 1;
+        if state.unfinished == 0 {
+            // 工作流的结果是没有后继的节点的输出
+            let result = (0..run.names.len())
+                .filter(|&j| run.succs[j].is_empty())
+                .map(|j| format!("{}: {:?}", run.names[j], state.outputs[j].as_ref().unwrap()))
+                .collect::<Vec<_>>()
+                .join(", ");
+            run.func_result.set_result(&result);
+            run.func_result.set_completed();
+            return;
+        }
+        for &s in run.succs[i].iter() {
+            state.waiting[s] -= 1;
+            if state.waiting[s] == 0 {
+                ready.push(s);
+            }
+        }
+    }
+    for s in ready {
+        start(run, s);
+    }
+}
+
+/// 节点失败或被取消，释放其他节点预留的容量
+fn fail(run: &Arc<Run>, msg: &str) {
+    let pending = {
+        let mut state = run.state.lock().unwrap();
+        if state.failed {
+            return;
+        }
+        state.failed = true;
+        std::mem::take(&mut state.pending)
+    };
+    drop(pending);
+    run.func_result.set_result(msg);
+    run.func_result.set_completed();
+}
@@ -0,0 +1,277 @@
+//! 准入控制的性质测试：随机生成任务，与逐时间片的EDF调度模拟比较
+
+use hyper_scheduler::{
+    analysis::{earliest_deadline, job_slack, Job},
+    runtime::{AdmissionControl, Runtime},
+    sim::{simulate, SimTask, Workload},
+    task::Criticality,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{collections::BTreeMap, time::Duration};
+
+const ROUNDS: usize = 2000;
+
+/// 所有任务同时到达，单处理器按EDF逐个时间单位调度，返回是否没有任务超时
+/// 已经超时的任务(截止时间为0)最先运行，但不检查它们的截止时间
+fn edf_meets_deadlines(jobs: &[Job]) -> bool {
+    let mut jobs: Vec<Job> = jobs.iter().filter(|j| j.remaining > 0).copied().collect();
+    let mut t = 0;
+    while let Some(i) = (0..jobs.len()).min_by_key(|&i| jobs[i].deadline) {
+        jobs[i].remaining -= 1;
+        t += 1;
+        if jobs[i].remaining == 0 {
+            let job = jobs.swap_remove(i);
+            if job.deadline > 0 && t > job.deadline {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+fn random_jobs(rng: &mut StdRng) -> Vec<Job> {
+    (0..rng.gen_range(1..8))
+        .map(|_| Job {
+            remaining: rng.gen_range(0..10),
+            deadline: rng.gen_range(1..40),
+        })
+        .collect()
+}
+
+#[test]
+fn job_slack_matches_edf_schedule() {
+    let mut rng = StdRng::seed_from_u64(35);
+    for _ in 0..ROUNDS {
+        let jobs = random_jobs(&mut rng);
+        assert_eq!(
+            job_slack(&jobs) >= 0,
+            edf_meets_deadlines(&jobs),
+            "{:?}",
+            jobs
+        );
+    }
+}
+
+#[test]
+fn job_slack_ignores_identical_deadlines() {
+    // 截止时间相同的任务是不同的任务，需求要累加
+    let jobs = [
+        Job {
+            remaining: 6,
+            deadline: 10,
+        },
+        Job {
+            remaining: 6,
+            deadline: 10,
+        },
+    ];
+    assert_eq!(job_slack(&jobs), -2);
+    assert!(!edf_meets_deadlines(&jobs));
+}
+
+/// 在`at`时刻，已准入任务还剩下的执行时间
+fn remaining_at(report: &hyper_scheduler::sim::SimReport, job: usize, exec: u64, at: u64) -> u64 {
+    let done: u64 = report
+        .schedule
+        .iter()
+        .filter(|s| s.job == job && s.start_us < at)
+        .map(|s| s.end_us.min(at) - s.start_us)
+        .sum();
+    exec - done
+}
+
+#[test]
+fn runtime_admission_is_exact() {
+    let mut rng = StdRng::seed_from_u64(350);
+    for _ in 0..ROUNDS / 10 {
+        // 到达时间互不相同，执行时间与预期执行时间相同
+        let mut arrivals = BTreeMap::new();
+        for _ in 0..rng.gen_range(1..10) {
+            let arrival = rng.gen_range(0..200u64) * 10;
+            let execution = rng.gen_range(1..30u64) * 10;
+            let deadline = execution + rng.gen_range(0..60u64) * 10;
+            arrivals.insert(arrival, (execution, deadline));
+        }
+        let tasks: Vec<SimTask> = arrivals
+            .iter()
+            .enumerate()
+            .map(|(i, (&arrival, &(execution, deadline)))| SimTask {
+                name: format!("t{}", i),
+                arrival_us: arrival,
+                execution_time_us: execution,
+                deadline_us: Some(deadline),
+                count: 1,
+                ..Default::default()
+            })
+            .collect();
+        let workload = Workload {
+            workers: 1,
+            tasks: tasks.clone(),
+            ..Default::default()
+        };
+        let report = simulate(&workload).unwrap();
+
+        // 准入的任务都不会超时
+        assert_eq!(report.deadline_misses, 0, "{:?}", workload);
+        // 拒绝的任务加入后一定会有任务超时
+        for record in report.records.iter() {
+            if record.worker.is_some() {
+                continue;
+            }
+            let at = record.arrival_us;
+            let mut jobs: Vec<Job> = report
+                .records
+                .iter()
+                .filter(|r| r.worker.is_some() && r.arrival_us < at)
+                .map(|r| Job {
+                    remaining: remaining_at(&report, r.job, tasks[r.job].execution_time_us, at),
+                    deadline: r.deadline_us.unwrap().saturating_sub(at),
+                })
+                .filter(|j| j.remaining > 0)
+                .collect();
+            let task = &tasks[record.job];
+            jobs.push(Job {
+                remaining: task.execution_time_us,
+                deadline: task.deadline_us.unwrap(),
+            });
+            assert!(!edf_meets_deadlines(&jobs), "{:?} {:?}", workload, jobs);
+        }
+    }
+}
+
+#[test]
+fn reservation_holds_capacity_until_dropped() {
+    let rt = Runtime::simulated(1);
+    let ms = Duration::from_millis;
+    let first = rt.reserve(Some(ms(60)), Some(ms(100)), Criticality::LO, None);
+    assert_ne!(first.get_ac(), AdmissionControl::UNSCHEDULABLE);
+    let id = first.get_costatus().unwrap().get_co_id();
+    assert!(rt.get_status_by_id(id).is_some());
+
+    // 预留的容量参与之后的准入控制
+    let second = rt.reserve(Some(ms(60)), Some(ms(100)), Criticality::LO, None);
+    assert_eq!(second.get_ac(), AdmissionControl::UNSCHEDULABLE);
+    drop(second);
+
+    // 没有commit就drop，释放容量
+    drop(first);
+    assert!(rt.get_status_by_id(id).is_none());
+    let third = rt.reserve(Some(ms(60)), Some(ms(100)), Criticality::LO, None);
+    assert_ne!(third.get_ac(), AdmissionControl::UNSCHEDULABLE);
+}
+
+#[test]
+#[allow(deprecated)]
+fn legacy_admission_releases_capacity() {
+    let rt = Runtime::simulated(1);
+    let ms = Duration::from_millis;
+    let first = rt.admission_control_result_with_criticality(
+        Some(ms(60)),
+        Some(ms(100)),
+        Criticality::LO,
+        None,
+    );
+    assert_ne!(first.get_ac(), AdmissionControl::UNSCHEDULABLE);
+    let id = first.get_costatus().unwrap().get_co_id();
+    assert!(rt.get_status_by_id(id).is_some());
+    // 结果还没有交给micro_process，容量仍然被占用
+    let second = rt.admission_control_result(Some(ms(60)), Some(ms(100)));
+    assert_eq!(second.get_ac(), AdmissionControl::UNSCHEDULABLE);
+    drop(second);
+    // 没有使用的结果drop后释放容量
+    drop(first);
+    assert!(rt.get_status_by_id(id).is_none());
+    let third = rt.admission_control_result(Some(ms(60)), Some(ms(100)));
+    assert_ne!(third.get_ac(), AdmissionControl::UNSCHEDULABLE);
+}
+
+#[test]
+fn earliest_deadline_is_tight() {
+    let mut rng = StdRng::seed_from_u64(37);
+    for _ in 0..ROUNDS {
+        let jobs = random_jobs(&mut rng);
+        let remaining = rng.gen_range(1..10);
+        let with = |deadline| {
+            let mut all = jobs.clone();
+            all.push(Job {
+                remaining,
+                deadline,
+            });
+            edf_meets_deadlines(&all)
+        };
+        match earliest_deadline(&jobs, remaining) {
+            Some(deadline) => {
+                assert!(with(deadline), "{:?} {}", jobs, deadline);
+                // 截止时间0表示已经超时，不检查
+                if deadline > 1 {
+                    assert!(!with(deadline - 1), "{:?} {}", jobs, deadline);
+                }
+            }
+            None => assert!(!edf_meets_deadlines(&jobs), "{:?}", jobs),
+        }
+    }
+}
+
+#[test]
+fn counter_offer_is_accepted() {
+    let rt = Runtime::simulated(1);
+    let ms = Duration::from_millis;
+    let first = rt.reserve(Some(ms(60)), Some(ms(100)), Criticality::LO, None);
+    let second = rt.reserve(Some(ms(60)), Some(ms(100)), Criticality::LO, None);
+    assert_eq!(second.get_ac(), AdmissionControl::UNSCHEDULABLE);
+    let offer = second.get_counter_offer().unwrap();
+    assert_eq!(offer.worker_id, 0);
+    assert!(offer.relative_deadline >= ms(119) && offer.relative_deadline <= ms(120));
+
+    let accepted = rt.reserve_on(
+        offer.worker_id,
+        Some(ms(60)),
+        Some(offer.relative_deadline + ms(1)),
+        Criticality::LO,
+        None,
+    );
+    assert_ne!(accepted.get_ac(), AdmissionControl::UNSCHEDULABLE);
+    drop(first);
+}
+
+#[test]
+fn mode_switch_is_deterministic() {
+    // 同一分组的多个LO实时任务排队时HI任务超出LO预算，降级后在分组中的顺序不依赖哈希顺序
+    let mut tasks: Vec<SimTask> = (0..8)
+        .map(|i| SimTask {
+            name: format!("lo{}", i),
+            execution_time_us: 1000,
+            deadline_us: Some(100000 + i * 1000),
+            group: "lo".to_owned(),
+            ..Default::default()
+        })
+        .collect();
+    tasks.push(SimTask {
+        name: "hi".to_owned(),
+        arrival_us: 100,
+        execution_time_us: 3000,
+        expected_execution_time_us: Some(500),
+        expected_execution_time_hi_us: Some(5000),
+        deadline_us: Some(20000),
+        criticality: "HI".to_owned(),
+        ..Default::default()
+    });
+    let workload = Workload {
+        workers: 1,
+        tasks,
+        ..Default::default()
+    };
+    let schedule = |workload: &Workload| -> Vec<(usize, u64, u64)> {
+        let report = simulate(workload).unwrap();
+        assert_eq!(report.degraded, 8);
+        report
+            .schedule
+            .iter()
+            .map(|s| (s.job, s.start_us, s.end_us))
+            .collect()
+    };
+    let first = schedule(&workload);
+    for _ in 0..10 {
+        assert_eq!(schedule(&workload), first);
+    }
+}
@@ -0,0 +1,44 @@
+//! 离线可调度性分析：TOML任务集和处理器需求分析的检查点上限
+
+use hyper_scheduler::analysis::{analyze, demand_test, Demand, TaskSet};
+
+#[test]
+fn toml_task_set_matches_json() {
+    let json = TaskSet::from_file("examples/taskset.json").unwrap();
+    let toml = TaskSet::from_file("examples/taskset.toml").unwrap();
+    assert_eq!(
+        serde_json::to_value(&json).unwrap(),
+        serde_json::to_value(&toml).unwrap()
+    );
+    let report = analyze(&toml).unwrap();
+    assert!(report.edf.feasible && !report.edf.inconclusive);
+
+    assert!(TaskSet::parse_toml(
+        "workers = 1\n[[tasks]]\nname = \"t\"\nwcet_us = 2\ndeadline_us = 1\n"
+    )
+    .is_err());
+    assert!(TaskSet::parse_toml("workers = 1\ntasks = 3\n").is_err());
+}
+
+#[test]
+fn demand_test_cap_is_inconclusive() {
+    // 利用率略高于0.5，需要检查约100万个截止时间点
+    let tasks = [
+        Demand {
+            wcet: 1,
+            deadline: 1,
+            period: 2,
+        },
+        Demand {
+            wcet: 1,
+            deadline: 2_000_003,
+            period: 2_000_003,
+        },
+    ];
+    let result = demand_test(&tasks);
+    assert!(result.inconclusive);
+    assert!(!result.feasible);
+
+    let result = demand_test(&tasks[1..]);
+    assert!(result.feasible && !result.inconclusive);
+}
@@ -0,0 +1,99 @@
+//! 集成测试共用的注册和调用wasm的辅助函数
+#![allow(dead_code)]
+
+use hyper_scheduler::{
+    axum::CallConfigRequest,
+    result::{FuncResult, ResultFuture},
+    runtime::Runtime,
+    runwasm::{call_func, Environment, FuncConfig, RegisterConfig},
+};
+use std::{sync::Arc, time::Duration};
+
+/// count循环n次后返回n
+pub const COUNT_WAT: &str = r#"
+(module
+  (func (export "count") (param $n i32) (result i32)
+    (local $i i32)
+    (block $done
+      (loop $l
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br_if $done (i32.ge_u (local.get $i) (local.get $n)))
+        (br $l)))
+    (local.get $i)))
+"#;
+
+pub fn block_on<F: std::future::Future>(f: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap()
+        .block_on(f)
+}
+
+/// 把wat写到临时文件并注册为名为name的模块
+pub fn environment(name: &str, wat: &str) -> Environment {
+    environment_with(name, wat, |_| {})
+}
+
+/// 注册前可以修改RegisterConfig
+pub fn environment_with(
+    name: &str,
+    wat: &str,
+    configure: impl FnOnce(&mut RegisterConfig),
+) -> Environment {
+    let path = std::env::temp_dir().join(format!("{}-{}.wat", name, std::process::id()));
+    std::fs::write(&path, wat).unwrap();
+    let mut config = RegisterConfig::new(path.to_str().unwrap(), name);
+    configure(&mut config);
+    let env = block_on(Environment::new(&config)).unwrap();
+    let _ = std::fs::remove_file(&path);
+    env
+}
+
+/// 调用COUNT_WAT的count，参数为n
+pub fn request(name: &str, n: &str) -> CallConfigRequest {
+    CallConfigRequest {
+        task_unique_name: name.to_owned(),
+        export_func: "count".to_owned(),
+        param_type: "i32".to_owned(),
+        params: vec![n.to_owned()],
+        results_length: "1".to_owned(),
+        ..Default::default()
+    }
+}
+
+/// 提交一次调用，返回等待结果的future
+pub fn try_call(
+    rt: &Runtime,
+    env: &Environment,
+    request: CallConfigRequest,
+) -> Result<ResultFuture, String> {
+    let result = Arc::new(FuncResult::new());
+    let conf = FuncConfig::new(request).map_err(|err| err.to_string())?;
+    call_func(rt, env.clone(), conf, &result).map_err(|err| err.to_string())?;
+    Ok(ResultFuture { result })
+}
+
+pub fn call(rt: &Runtime, env: &Environment, request: CallConfigRequest) -> ResultFuture {
+    try_call(rt, env, request).unwrap()
+}
+
+/// 最多等待10秒
+pub fn wait(future: ResultFuture) -> String {
+    block_on(async { tokio::time::timeout(Duration::from_secs(10), future).await })
+        .expect("task did not finish")
+}
+
+/// 提交一次调用并等待结果，不能提交时返回错误信息
+pub fn try_run(
+    rt: &Runtime,
+    env: &Environment,
+    request: CallConfigRequest,
+) -> Result<String, String> {
+    try_call(rt, env, request).map(wait)
+}
+
+/// 提交一次调用并等待结果
+pub fn run(rt: &Runtime, env: &Environment, request: CallConfigRequest) -> String {
+    wait(call(rt, env, request))
+}
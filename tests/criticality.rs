@@ -0,0 +1,110 @@
+//! 混合关键级别：HI任务超出LO预算后切换到HI模式，丢弃LO任务
+
+use hyper_scheduler::{
+    runtime::{AdmissionControl, LoPolicy, Runtime, RuntimeBuilder, ShutdownMode},
+    task::Criticality,
+};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+fn spin(d: Duration) {
+    let start = Instant::now();
+    while start.elapsed() < d {
+        std::hint::spin_loop();
+    }
+}
+
+fn wait_for(timeout: Duration, f: impl Fn() -> bool) -> bool {
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        if f() {
+            return true;
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    f()
+}
+
+#[test]
+fn hi_overrun_drops_lo_tasks() {
+    let rt = RuntimeBuilder::new()
+        .name("criticality")
+        .worker_threads(1)
+        .build()
+        .unwrap();
+    rt.set_lo_policy(LoPolicy::DROP);
+    let ms = Duration::from_millis;
+
+    // 已经开始运行的LO任务被抢占后不能释放，降级后继续运行完
+    let started = Arc::new(AtomicBool::new(false));
+    let finished = Arc::new(AtomicBool::new(false));
+    let preempted_cancelled = Arc::new(AtomicBool::new(false));
+    let (started_1, finished_1) = (started.clone(), finished.clone());
+    let preempted_cancelled_1 = preempted_cancelled.clone();
+    let lo = rt.reserve(Some(ms(60)), Some(ms(400)), Criticality::LO, None);
+    lo.commit_with_cancel(
+        move || {
+            started_1.store(true, Ordering::SeqCst);
+            spin(ms(60));
+            finished_1.store(true, Ordering::SeqCst);
+        },
+        move || preempted_cancelled_1.store(true, Ordering::SeqCst),
+    )
+    .unwrap();
+    assert!(wait_for(Duration::from_secs(5), || started.load(Ordering::SeqCst)));
+
+    // 还没开始运行的LO任务被取消
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let cancelled_1 = cancelled.clone();
+    let pending = rt.reserve(Some(ms(20)), Some(ms(450)), Criticality::LO, None);
+    pending
+        .commit_with_cancel(|| {}, move || cancelled_1.store(true, Ordering::SeqCst))
+        .unwrap();
+
+    // 虚拟截止时间更早，抢占LO任务，运行2ms后超出LO预算
+    let done = Arc::new(AtomicBool::new(false));
+    let done_1 = done.clone();
+    let hi = rt.reserve(Some(ms(2)), Some(ms(300)), Criticality::HI, Some(ms(80)));
+    hi.commit(move || {
+        spin(ms(30));
+        done_1.store(true, Ordering::SeqCst);
+    })
+    .unwrap();
+
+    assert!(wait_for(Duration::from_secs(5), || done.load(Ordering::SeqCst)));
+    assert!(wait_for(Duration::from_secs(5), || cancelled.load(Ordering::SeqCst)));
+    assert!(wait_for(Duration::from_secs(5), || finished.load(Ordering::SeqCst)));
+    assert!(!preempted_cancelled.load(Ordering::SeqCst));
+    // 空闲后回到LO模式
+    assert!(wait_for(Duration::from_secs(5), || rt.get_mode(0) == Criticality::LO));
+    rt.shutdown(ShutdownMode::IMMEDIATE, Duration::from_secs(5))
+        .unwrap();
+}
+
+#[test]
+fn virtual_deadlines_share_one_scale() {
+    let rt = Runtime::simulated(1);
+    let ms = Duration::from_millis;
+    let lo = rt.reserve(Some(ms(50)), Some(ms(100)), Criticality::LO, None);
+    assert_ne!(lo.get_ac(), AdmissionControl::UNSCHEDULABLE);
+    // u_lo_lo + u_hi_hi <= 1，不需要虚拟截止时间，x = 1
+    let first = rt.reserve(Some(ms(10)), Some(ms(100)), Criticality::HI, Some(ms(40)));
+    assert_ne!(first.get_ac(), AdmissionControl::UNSCHEDULABLE);
+    assert!(first.get_costatus().unwrap().virtual_deadline.is_none());
+    // 单独看需要 x = 0.3，但已有的HI任务按 x = 1 调度
+    let second = rt.reserve(Some(ms(5)), Some(ms(100)), Criticality::HI, Some(ms(70)));
+    assert_eq!(second.get_ac(), AdmissionControl::UNSCHEDULABLE);
+    drop(second);
+
+    // 没有HI任务后可以重新选择x
+    drop(first);
+    let second = rt.reserve(Some(ms(5)), Some(ms(100)), Criticality::HI, Some(ms(70)));
+    assert_ne!(second.get_ac(), AdmissionControl::UNSCHEDULABLE);
+    assert!(second.get_costatus().unwrap().virtual_deadline.is_some());
+    drop(lo);
+}
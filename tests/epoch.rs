@@ -0,0 +1,172 @@
+//! wasm任务的epoch中断：在检查点轮转，超过截止时间时trap
+
+mod common;
+
+use common::{call, environment, wait};
+use hyper_scheduler::{
+    axum::CallConfigRequest,
+    runtime::{EpochConfig, FuncKey, RuntimeBuilder, ShutdownMode, WatchdogAction, WatchdogConfig},
+};
+use std::time::{Duration, Instant};
+
+/// spin永远不返回，count循环n次后返回n
+const WAT: &str = r#"
+(module
+  (func (export "spin")
+    (loop $l (br $l)))
+  (func (export "count") (param $n i32) (result i32)
+    (local $i i32)
+    (block $done
+      (loop $l
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br_if $done (i32.ge_u (local.get $i) (local.get $n)))
+        (br $l)))
+    (local.get $i)))
+"#;
+
+#[test]
+fn runaway_wasm_traps_at_deadline() {
+    let rt = RuntimeBuilder::new()
+        .name("epoch-trap")
+        .epoch(EpochConfig {
+            trap_deadline: true,
+            trap_budget: false,
+        })
+        .build()
+        .unwrap();
+    let env = environment("epoch-trap", WAT);
+    let spin = call(
+        &rt,
+        &env,
+        CallConfigRequest {
+            task_unique_name: "spin".to_owned(),
+            export_func: "spin".to_owned(),
+            param_type: "void".to_owned(),
+            results_length: "0".to_owned(),
+            expected_execution_time: "2".to_owned(),
+            expected_deadline: "20".to_owned(),
+            ..Default::default()
+        },
+    );
+    assert!(wait(spin).contains("deadline exceeded"));
+
+    // trap后worker继续运行其他wasm任务
+    let count = call(
+        &rt,
+        &env,
+        CallConfigRequest {
+            task_unique_name: "count".to_owned(),
+            export_func: "count".to_owned(),
+            param_type: "i32".to_owned(),
+            params: vec!["1000".to_owned()],
+            results_length: "1".to_owned(),
+            ..Default::default()
+        },
+    );
+    assert_eq!(wait(count), "[I32(1000)]");
+    // trap的调用运行时间被截断，不参与执行时间的学习
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while rt
+        .get_execution_estimate(&FuncKey::new("epoch-trap", "count"))
+        .is_none()
+    {
+        assert!(Instant::now() < deadline, "count not learned");
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    assert!(rt
+        .get_execution_estimate(&FuncKey::new("epoch-trap", "spin"))
+        .is_none());
+    rt.shutdown(ShutdownMode::DRAINALL, Duration::from_secs(5))
+        .unwrap();
+}
+
+#[test]
+fn realtime_wasm_preempts_running_wasm() {
+    let rt = RuntimeBuilder::new().name("epoch-preempt").build().unwrap();
+    let env = environment("epoch-preempt", WAT);
+    let request = |name: &str, n: &str| CallConfigRequest {
+        task_unique_name: name.to_owned(),
+        export_func: "count".to_owned(),
+        param_type: "i32".to_owned(),
+        params: vec![n.to_owned()],
+        results_length: "1".to_owned(),
+        ..Default::default()
+    };
+    let long = call(&rt, &env, request("long", "2000000000"));
+    std::thread::sleep(Duration::from_millis(10));
+    // 实时任务在检查点抢占正在执行的wasm调用，两个调用的状态随协程切换
+    let short = call(
+        &rt,
+        &env,
+        CallConfigRequest {
+            expected_execution_time: "5".to_owned(),
+            expected_deadline: "50".to_owned(),
+            ..request("short", "1000")
+        },
+    );
+    let start = Instant::now();
+    assert_eq!(wait(short), "[I32(1000)]");
+    let short_done = start.elapsed();
+    assert_eq!(wait(long), "[I32(2000000000)]");
+    assert!(start.elapsed() > short_done + Duration::from_millis(100));
+    rt.shutdown(ShutdownMode::DRAINALL, Duration::from_secs(5))
+        .unwrap();
+}
+
+#[test]
+fn watchdog_kill_traps_running_wasm() {
+    let rt = RuntimeBuilder::new()
+        .name("epoch-kill")
+        .watchdog(WatchdogConfig {
+            interval: Duration::from_millis(5),
+            budget_factor: 2,
+            deadline_grace: Duration::from_millis(5),
+            max_run: None,
+            action: WatchdogAction::KILL,
+        })
+        .build()
+        .unwrap();
+    let env = environment("epoch-kill", WAT);
+    let spin = call(
+        &rt,
+        &env,
+        CallConfigRequest {
+            task_unique_name: "spin".to_owned(),
+            export_func: "spin".to_owned(),
+            param_type: "void".to_owned(),
+            results_length: "0".to_owned(),
+            expected_execution_time: "2".to_owned(),
+            expected_deadline: "20".to_owned(),
+            ..Default::default()
+        },
+    );
+    assert!(wait(spin).contains("killed by watchdog"));
+    rt.shutdown(ShutdownMode::DRAINALL, Duration::from_secs(5))
+        .unwrap();
+}
+
+#[test]
+fn immediate_shutdown_traps_started_wasm() {
+    let rt = RuntimeBuilder::new()
+        .name("epoch-shutdown")
+        .worker_threads(1)
+        .timer_exp(2000)
+        .build()
+        .unwrap();
+    let env = environment("epoch-shutdown", WAT);
+    let spin = |name: &str| CallConfigRequest {
+        task_unique_name: name.to_owned(),
+        export_func: "spin".to_owned(),
+        param_type: "void".to_owned(),
+        ..Default::default()
+    };
+    // 两个非实时任务轮转，都已经开始运行并在wasm调用中挂起
+    let first = call(&rt, &env, spin("first"));
+    let second = call(&rt, &env, spin("second"));
+    std::thread::sleep(Duration::from_millis(50));
+    // 运行到一半的栈不被释放，wasm调用在检查点trap后worker退出
+    rt.shutdown(ShutdownMode::IMMEDIATE, Duration::from_secs(5))
+        .unwrap();
+    assert!(wait(first).contains("task dropped"));
+    assert!(wait(second).contains("task dropped"));
+}
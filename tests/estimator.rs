@@ -0,0 +1,120 @@
+//! 在线学习执行时间：按已完成任务的运行时间估计，准入时使用学习到的p99
+
+mod common;
+
+use common::{environment, request, run, COUNT_WAT};
+use hyper_scheduler::{
+    axum::CallConfigRequest,
+    result::FuncResult,
+    runtime::{ExecutionEstimator, FuncKey, RuntimeBuilder, ShutdownMode},
+    runwasm::{call_func, Deferred, FuncConfig},
+    task::Criticality,
+};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+#[test]
+fn sketch_quantiles() {
+    let estimator = ExecutionEstimator::new();
+    let key = FuncKey::new("synthetic", "f");
+    for ms in 1..=100 {
+        estimator.record(&key, Duration::from_millis(ms));
+    }
+    let estimate = estimator.get(&key).unwrap();
+    assert_eq!(estimate.samples, 100);
+    assert_eq!(estimate.max_us, 100000);
+    assert!((49000..=51000).contains(&estimate.p50_us), "{:?}", estimate);
+    assert!(
+        (98000..=100000).contains(&estimate.p99_us),
+        "{:?}",
+        estimate
+    );
+    assert!(estimate.ewma_us > 50000 && estimate.ewma_us < 100000);
+    assert_eq!(
+        estimate.get_p99(),
+        Some(Duration::from_micros(estimate.p99_us))
+    );
+    assert!(estimator.get(&FuncKey::new("synthetic", "g")).is_none());
+}
+
+#[test]
+fn learned_p99_raises_declared_budget() {
+    let rt = RuntimeBuilder::new()
+        .name("estimator")
+        .learned_admission(true)
+        .build()
+        .unwrap();
+    let env = environment("estimator", COUNT_WAT);
+    let key = FuncKey::new("estimator", "count");
+    for i in 0..25 {
+        assert_eq!(
+            run(&rt, &env, request(&format!("learn{}", i), "50000000")),
+            "[I32(50000000)]"
+        );
+    }
+    // 结果在任务函数返回前就已经设置，等待worker记录完成状态
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let estimate = loop {
+        match rt.get_execution_estimate(&key) {
+            Some(estimate) if estimate.samples == 25 => break estimate,
+            _ if Instant::now() > deadline => panic!("estimate not learned"),
+            _ => std::thread::sleep(Duration::from_millis(1)),
+        }
+    };
+    assert!(estimate.p99_us <= estimate.max_us);
+    assert!(estimate.p99_us >= estimate.p50_us);
+    let p99 = estimate.get_p99().unwrap();
+    assert_eq!(rt.admission_wcet(&key, Duration::from_millis(1)), p99);
+    let generous = p99 + Duration::from_secs(1);
+    assert_eq!(rt.admission_wcet(&key, generous), generous);
+
+    // 声明的执行时间远小于实际时，按学习到的p99准入
+    let result = Arc::new(FuncResult::new());
+    let conf = FuncConfig::new(CallConfigRequest {
+        expected_execution_time: "1".to_owned(),
+        expected_deadline: (p99.as_millis() as u64 / 2).max(2).to_string(),
+        ..request("underdeclared", "50000000")
+    })
+    .unwrap();
+    if p99 > Duration::from_millis(4) {
+        assert!(call_func(&rt, env.clone(), conf, &result).is_err());
+    }
+
+    // 等待准入的请求按学习到的p99计算最晚开始时间
+    let ms = Duration::from_millis;
+    let busy = rt.reserve(Some(ms(4997)), Some(ms(5000)), Criticality::LO, None);
+    let worker_id = busy.get_worker_id().unwrap();
+    let mut conf = FuncConfig::new(CallConfigRequest {
+        expected_execution_time: "1".to_owned(),
+        expected_deadline: "5000".to_owned(),
+        worker_id: worker_id.to_string(),
+        wait: true,
+        ..request("waiting", "50000000")
+    })
+    .unwrap();
+    conf.fix_deadline(Instant::now());
+    if p99 > ms(4) {
+        let err = call_func(&rt, env.clone(), conf.clone(), &result).unwrap_err();
+        let deferred = err.downcast_ref::<Deferred>().unwrap();
+        assert_eq!(deferred.admission_wcet, Some(p99));
+        conf.set_admission_wcet(deferred.admission_wcet);
+        assert_eq!(
+            conf.get_worst_start_time(),
+            conf.get_deadline_at().map(|d| d - p99)
+        );
+    }
+    drop(busy);
+    rt.shutdown(ShutdownMode::DRAINALL, Duration::from_secs(5))
+        .unwrap();
+
+    // 默认不使用学习结果
+    let rt = RuntimeBuilder::new().name("estimator-off").build().unwrap();
+    assert_eq!(
+        rt.admission_wcet(&key, Duration::from_millis(1)),
+        Duration::from_millis(1)
+    );
+    rt.shutdown(ShutdownMode::DRAINALL, Duration::from_secs(5))
+        .unwrap();
+}
@@ -0,0 +1,79 @@
+//! wasm调用的fuel计量：记录消耗、校准fuel和时间、按预算trap
+
+mod common;
+
+use common::{environment, environment_with, run, COUNT_WAT};
+use hyper_scheduler::{
+    axum::CallConfigRequest,
+    result::FuncResult,
+    runtime::{Runtime, RuntimeBuilder, ShutdownMode},
+    runwasm::{call_func, get_status_by_name, FuncConfig, RegisterConfig},
+    task::{CoStatus, SchedulerStatus},
+};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+fn request(name: &str, n: u32, fuel_budget: &str) -> CallConfigRequest {
+    CallConfigRequest {
+        fuel_budget: fuel_budget.to_owned(),
+        ..common::request(name, &n.to_string())
+    }
+}
+
+/// 结果在任务函数返回前就已经设置，等待worker记录完成状态
+fn completed(rt: &Runtime, name: &str) -> SchedulerStatus {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        if let Some(status) = get_status_by_name(rt, name) {
+            if status.co_status == CoStatus::COMPLETED || Instant::now() > deadline {
+                return status;
+            }
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
+
+#[test]
+fn fuel_is_recorded_and_calibrated() {
+    let rt = RuntimeBuilder::new().name("fuel").build().unwrap();
+    let env = environment_with("fuel", COUNT_WAT, RegisterConfig::set_fuel);
+    assert_eq!(rt.get_fuel_rate(0), None);
+    assert_eq!(
+        run(&rt, &env, request("fuel-count", 1000000, "")),
+        "[I32(1000000)]"
+    );
+    let status = completed(&rt, "fuel-count");
+    assert!(status.fuel.unwrap() >= 1000000);
+    assert!(rt.get_fuel_rate(0).unwrap() > 0.0);
+    assert!(rt.estimate_fuel_time(1000000, None).is_some());
+    assert!(rt
+        .get_metrics()
+        .contains("hyperwasm_worker_fuel_nanoseconds"));
+
+    // 超出预算时trap
+    let res = run(&rt, &env, request("fuel-budget", 1000000000, "100000"));
+    assert!(res.contains("fuel"), "{}", res);
+    let status = completed(&rt, "fuel-budget");
+    assert_eq!(status.fuel, Some(100000));
+    rt.shutdown(ShutdownMode::DRAINALL, Duration::from_secs(5))
+        .unwrap();
+}
+
+#[test]
+fn fuel_budget_requires_metering() {
+    let rt = RuntimeBuilder::new().name("fuel-off").build().unwrap();
+    let env = environment("fuel-off", COUNT_WAT);
+    let result = Arc::new(FuncResult::new());
+    let conf = FuncConfig::new(request("no-fuel", 1000, "1000")).unwrap();
+    assert!(call_func(&rt, env.clone(), conf, &result).is_err());
+    assert_eq!(
+        run(&rt, &env, request("no-fuel-ok", 1000, "")),
+        "[I32(1000)]"
+    );
+    assert_eq!(completed(&rt, "no-fuel-ok").fuel, None);
+    assert!(FuncConfig::new(request("bad-fuel", 1000, "lots")).is_err());
+    rt.shutdown(ShutdownMode::DRAINALL, Duration::from_secs(5))
+        .unwrap();
+}
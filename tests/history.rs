@@ -0,0 +1,126 @@
+//! 已完成任务的持久化历史：JSON Lines文件、轮转、查询和CSV导出
+
+use hyper_scheduler::{
+    history::{self, History, HistoryConfig, HistoryQuery, HistoryRecord},
+    runtime::{RuntimeBuilder, ShutdownMode},
+    task::Criticality,
+};
+use std::{path::PathBuf, time::Duration};
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+fn record(
+    co_id: u64,
+    worker_id: u8,
+    module: &str,
+    outcome: &str,
+    finish_time: i64,
+) -> HistoryRecord {
+    HistoryRecord {
+        co_id,
+        worker_id,
+        module: module.to_owned(),
+        outcome: outcome.to_owned(),
+        criticality: "LO".to_owned(),
+        spawn_time: finish_time - 10,
+        finish_time,
+        response_time: 10000,
+        running_time: 5000,
+        expected_execution_time: Some(5000),
+        relative_deadline: None,
+    }
+}
+
+#[test]
+fn history_rotates_and_filters() {
+    let dir = temp_dir("history-rotate");
+    let mut config = HistoryConfig::new(&dir);
+    config.max_file_size = 1;
+    let history = History::open(config).unwrap();
+    let now = chrono::Local::now().timestamp_millis();
+    history.record(record(1, 0, "fib.wasm", "COMPLETED", now - 30));
+    history.record(record(2, 1, "fib.wasm", "MISSED", now - 20));
+    history.record(record(3, 1, "a,b", "CANCELLED", now - 10));
+    history.flush().unwrap();
+    // 每条记录都超过了大小上限，各自一个文件
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 3);
+
+    let all = history.query(&HistoryQuery::default()).unwrap();
+    assert_eq!(all.iter().map(|r| r.co_id).collect::<Vec<_>>(), [1, 2, 3]);
+    let worker = HistoryQuery {
+        worker_id: Some(1),
+        outcome: Some("missed".to_owned()),
+        ..Default::default()
+    };
+    assert_eq!(history.query(&worker).unwrap(), [all[1].clone()]);
+    let range = HistoryQuery {
+        from: Some(now - 20),
+        to: Some(now - 10),
+        ..Default::default()
+    };
+    assert_eq!(history.query(&range).unwrap(), [all[1].clone()]);
+    let limit = HistoryQuery {
+        limit: Some(2),
+        ..Default::default()
+    };
+    assert_eq!(history.query(&limit).unwrap(), all[..2]);
+
+    let csv = history::to_csv(&all);
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines.len(), 4);
+    assert!(lines[0].starts_with("co_id,worker_id,module,outcome"));
+    assert!(lines[3].starts_with("3,1,\"a,b\",CANCELLED,LO,"));
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn prune_replaces_expired_active_segment() {
+    let dir = temp_dir("history-prune");
+    let mut config = HistoryConfig::new(&dir);
+    config.ttl = Duration::from_millis(50);
+    let history = History::open(config).unwrap();
+    let now = chrono::Local::now().timestamp_millis();
+    history.record(record(1, 0, "fib.wasm", "COMPLETED", now));
+    history.flush().unwrap();
+    std::thread::sleep(Duration::from_millis(100));
+    history.prune();
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+    // 之后的记录写入新文件
+    let now = chrono::Local::now().timestamp_millis();
+    history.record(record(2, 0, "fib.wasm", "COMPLETED", now));
+    let records = history.query(&HistoryQuery::default()).unwrap();
+    assert_eq!(records.iter().map(|r| r.co_id).collect::<Vec<_>>(), [2]);
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn completed_tasks_are_persisted() {
+    let dir = temp_dir("history-runtime");
+    let rt = RuntimeBuilder::new()
+        .history(HistoryConfig::new(&dir))
+        .build()
+        .unwrap();
+    for _ in 0..2 {
+        rt.reserve(None, None, Criticality::LO, None)
+            .commit(|| {})
+            .unwrap();
+    }
+    rt.shutdown(ShutdownMode::DRAINALL, Duration::from_secs(5))
+        .unwrap();
+
+    let records = rt.query_history(&HistoryQuery::default()).unwrap();
+    assert_eq!(records.len(), 2);
+    assert!(records.iter().all(|r| r.outcome == "COMPLETED"));
+    assert!(records.iter().all(|r| r.spawn_time <= r.finish_time));
+    // 重启后从文件中读到同样的记录
+    drop(rt);
+    let history = History::open(HistoryConfig::new(&dir)).unwrap();
+    assert_eq!(history.query(&HistoryQuery::default()).unwrap(), records);
+    let _ = std::fs::remove_dir_all(&dir);
+}
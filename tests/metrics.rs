@@ -0,0 +1,99 @@
+//! Prometheus格式的调度指标
+
+use hyper_scheduler::{
+    runtime::{AdmissionControl, RuntimeBuilder, ShutdownMode},
+    task::Criticality,
+};
+use std::time::Duration;
+
+/// 取出一个样本的值
+fn sample(text: &str, series: &str) -> f64 {
+    text.lines()
+        .find_map(|line| line.strip_prefix(series)?.strip_prefix(' '))
+        .unwrap_or_else(|| panic!("{} not found in\n{}", series, text))
+        .parse()
+        .unwrap()
+}
+
+#[test]
+fn metrics_count_admissions_and_misses() {
+    let rt = RuntimeBuilder::new().name("metrics").build().unwrap();
+    let ms = Duration::from_millis;
+    // 实时任务运行时间超过预期执行时间和截止时间
+    let reservation = rt.reserve(Some(ms(1)), Some(ms(5)), Criticality::LO, None);
+    assert_ne!(reservation.get_ac(), AdmissionControl::UNSCHEDULABLE);
+    reservation
+        .commit(move || std::thread::sleep(ms(20)))
+        .unwrap();
+    rt.reserve(None, None, Criticality::LO, None)
+        .commit(|| {})
+        .unwrap();
+    rt.shutdown(ShutdownMode::DRAINALL, Duration::from_secs(5))
+        .unwrap();
+
+    let text = rt.get_metrics();
+    assert!(text.contains("# TYPE hyperwasm_admissions_total counter"));
+    let admitted = |outcome: &str| {
+        sample(
+            &text,
+            &format!(
+                "hyperwasm_admissions_total{{runtime=\"metrics\",outcome=\"{}\"}}",
+                outcome
+            ),
+        )
+    };
+    assert_eq!(admitted("NOTREALTIME"), 1.0);
+    assert_eq!(admitted("SCHEDULABLE") + admitted("PREEMPTIVE"), 1.0);
+    assert_eq!(admitted("UNSCHEDULABLE"), 0.0);
+    let worker = |name: &str| {
+        sample(
+            &text,
+            &format!("hyperwasm_{}{{runtime=\"metrics\",worker=\"0\"}}", name),
+        )
+    };
+    assert_eq!(worker("tasks_completed_total"), 2.0);
+    assert_eq!(worker("deadline_misses_total"), 1.0);
+    assert_eq!(worker("overruns_total"), 1.0);
+    assert!(worker("context_switches_total") >= 2.0);
+    assert!(worker("worker_busy_seconds_total") >= 0.02);
+    assert_eq!(
+        sample(&text, "hyperwasm_global_queue_depth{runtime=\"metrics\"}"),
+        0.0
+    );
+}
+
+#[test]
+fn group_admission_counts_each_task_once() {
+    let rt = RuntimeBuilder::new()
+        .name("metrics-group")
+        .worker_threads(2)
+        .build_simulated()
+        .unwrap();
+    let ms = Duration::from_millis;
+    let admitted = |outcome: &str| {
+        sample(
+            &rt.get_metrics(),
+            &format!(
+                "hyperwasm_admissions_total{{runtime=\"metrics-group\",outcome=\"{}\"}}",
+                outcome
+            ),
+        )
+    };
+    // 两个worker能准入前两个任务，但放不下第三个，失败的尝试不计数
+    let lo = Criticality::LO;
+    assert!(rt
+        .reserve_group(&[
+            (ms(60), ms(100), lo),
+            (ms(60), ms(100), lo),
+            (ms(60), ms(100), lo)
+        ])
+        .is_none());
+    assert_eq!(admitted("UNSCHEDULABLE"), 3.0);
+    assert_eq!(admitted("SCHEDULABLE") + admitted("PREEMPTIVE"), 0.0);
+    let group = rt
+        .reserve_group(&[(ms(10), ms(100), lo), (ms(10), ms(100), lo)])
+        .unwrap();
+    assert_eq!(admitted("SCHEDULABLE") + admitted("PREEMPTIVE"), 2.0);
+    assert_eq!(admitted("UNSCHEDULABLE"), 3.0);
+    drop(group);
+}
@@ -0,0 +1,136 @@
+//! 统计WCET剖析：多次运行的统计结果、极值理论的WCET界，以及作为默认预期执行时间
+
+mod common;
+
+use common::{environment, request, wait, COUNT_WAT};
+use hyper_scheduler::{
+    axum::CallConfigRequest,
+    profile::{self, ProfileKey, ProfileStats},
+    result::{FuncResult, ResultFuture},
+    runtime::{RuntimeBuilder, ShutdownMode},
+    runwasm::{call_func, run_profile, Deferred, FuncConfig, ProfileJob},
+    task::Criticality,
+};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+fn key(module: &str, n: &str) -> ProfileKey {
+    ProfileKey {
+        module: module.to_owned(),
+        export: "count".to_owned(),
+        param_class: profile::param_class("i32", &[n.to_owned()]),
+    }
+}
+
+#[test]
+fn stats_and_evt_bound() {
+    let samples: Vec<Duration> = (1..=200)
+        .map(|i| Duration::from_micros(100 + (i * 37) % 50))
+        .collect();
+    let stats = ProfileStats::from_samples(key("synthetic", "1"), &samples, 0).unwrap();
+    assert_eq!(stats.iterations, 200);
+    assert_eq!(stats.min_us, 100);
+    assert_eq!(stats.max_us, 149);
+    assert!(stats.p99_us >= 148 && stats.p99_us <= 149);
+    assert!(stats.mean_us > 120 && stats.mean_us < 130);
+    assert!(stats.wcet_us > stats.max_us);
+
+    // 样本太少时WCET界就是最大值
+    let stats = ProfileStats::from_samples(key("synthetic", "2"), &samples[..20], 0).unwrap();
+    assert_eq!(stats.wcet_us, stats.max_us);
+    assert!(ProfileStats::from_samples(key("synthetic", "3"), &[], 0).is_none());
+}
+
+#[test]
+fn profile_defaults_execution_time() {
+    let rt = RuntimeBuilder::new().name("profile").build().unwrap();
+    let env = environment("profile", COUNT_WAT);
+
+    let job = ProfileJob {
+        env: env.clone(),
+        conf: FuncConfig::new(request("anon", "100000")).unwrap(),
+        iterations: 60,
+        interference: 1,
+        result: Arc::new(FuncResult::new()),
+    };
+    let stats = run_profile(&rt, &job).unwrap();
+    assert_eq!(stats.iterations, 60);
+    assert!(stats.min_us <= stats.mean_us && stats.mean_us <= stats.max_us);
+    assert!(stats.wcet_us >= stats.max_us);
+    assert_eq!(rt.get_profile(&key("profile", "100000")), Some(stats));
+
+    // 剖析得到的WCET界超过截止时间时不可调度
+    let slow = ProfileStats {
+        wcet_us: 500000,
+        ..ProfileStats::from_samples(key("profile", "7"), &[Duration::from_millis(1)], 0).unwrap()
+    };
+    rt.record_profile(slow);
+    let conf = FuncConfig::new(CallConfigRequest {
+        expected_deadline: "50".to_owned(),
+        ..request("slow", "7")
+    })
+    .unwrap();
+    let result = Arc::new(FuncResult::new());
+    assert!(call_func(&rt, env.clone(), conf, &result).is_err());
+
+    // 参数类别不同时不使用这个结果
+    let conf = FuncConfig::new(CallConfigRequest {
+        expected_deadline: "50".to_owned(),
+        ..request("fast", "8")
+    })
+    .unwrap();
+    call_func(&rt, env, conf, &result).unwrap();
+    let res = wait(ResultFuture {
+        result: result.clone(),
+    });
+    assert_eq!(res, "[I32(8)]");
+    assert_eq!(rt.get_profiles().len(), 2);
+    rt.shutdown(ShutdownMode::DRAINALL, Duration::from_secs(5))
+        .unwrap();
+}
+
+#[test]
+fn deferred_request_uses_profiled_time() {
+    let rt = RuntimeBuilder::new()
+        .name("profile-wait")
+        .worker_threads(1)
+        .build()
+        .unwrap();
+    let env = environment("profile-wait", COUNT_WAT);
+    let profiled = ProfileStats {
+        wcet_us: 30000,
+        ..ProfileStats::from_samples(key("profile-wait", "9"), &[Duration::from_millis(1)], 0)
+            .unwrap()
+    };
+    rt.record_profile(profiled);
+    // 其他Runtime的剖析结果不可见
+    assert_eq!(rt.get_profiles().len(), 1);
+    // 占满worker，等待模式的请求不能马上准入
+    let ms = Duration::from_millis;
+    let busy = rt.reserve(Some(ms(90)), Some(ms(100)), Criticality::LO, None);
+    assert!(busy.get_worker_id().is_some());
+
+    let mut conf = FuncConfig::new(CallConfigRequest {
+        expected_deadline: "110".to_owned(),
+        wait: true,
+        ..request("wait", "9")
+    })
+    .unwrap();
+    conf.fix_deadline(Instant::now());
+    let result = Arc::new(FuncResult::new());
+    let err = call_func(&rt, env, conf.clone(), &result).unwrap_err();
+    // 最晚开始时间按剖析得到的30ms计算，而不是截止时间本身
+    let deferred = err.downcast_ref::<Deferred>().unwrap();
+    assert_eq!(deferred.expected_execution_time, 30);
+    assert_eq!(deferred.admission_wcet, Some(ms(30)));
+    conf.set_expected_execution_time(deferred.expected_execution_time);
+    assert_eq!(
+        conf.get_worst_start_time(),
+        conf.get_deadline_at().map(|d| d - ms(30))
+    );
+    drop(busy);
+    rt.shutdown(ShutdownMode::DRAINALL, Duration::from_secs(5))
+        .unwrap();
+}
@@ -0,0 +1,235 @@
+//! 同一个进程中的多个Runtime互相独立
+
+use hyper_scheduler::{
+    cpulist::{CpuLayout, CpuList},
+    runtime::{AdmissionControl, Runtime, RuntimeBuilder, ShutdownMode},
+    task::{stack::StackSize, Criticality},
+};
+use nix::sys::signal::Signal;
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+#[test]
+fn runtimes_are_isolated() {
+    let hard = Runtime::new(Some(1), None, None);
+    let batch = Runtime::new(Some(1), None, None);
+    assert_ne!(hard.get_name(), batch.get_name());
+
+    let ms = Duration::from_millis;
+    let done = Arc::new(AtomicU32::new(0));
+    for rt in [&hard, &batch] {
+        let reservation = rt.reserve(Some(ms(10)), Some(ms(200)), Criticality::LO, None);
+        assert_ne!(reservation.get_ac(), AdmissionControl::UNSCHEDULABLE);
+        let id = reservation.get_costatus().unwrap().get_co_id();
+        // 状态只在自己的Runtime中
+        assert!(rt.get_status_by_id(id).is_some());
+        let other = if std::ptr::eq(rt, &hard) {
+            &batch
+        } else {
+            &hard
+        };
+        assert!(other.get_status_by_id(id).is_none());
+        let done = done.clone();
+        reservation
+            .commit(move || {
+                done.fetch_add(1, Ordering::SeqCst);
+            })
+            .unwrap();
+    }
+
+    hard.shutdown(ShutdownMode::DRAINADMITTED, Duration::from_secs(5))
+        .unwrap();
+    // 关闭一个Runtime不影响另一个
+    let reservation = batch.reserve(Some(ms(10)), Some(ms(200)), Criticality::LO, None);
+    assert_ne!(reservation.get_ac(), AdmissionControl::UNSCHEDULABLE);
+    drop(reservation);
+    batch
+        .shutdown(ShutdownMode::DRAINADMITTED, Duration::from_secs(5))
+        .unwrap();
+    assert_eq!(done.load(Ordering::SeqCst), 2);
+
+    // 关闭后可以再创建新的Runtime
+    let again = Runtime::new(Some(1), None, None);
+    drop(again);
+}
+
+#[test]
+fn builder_rejects_invalid_config() {
+    let invalid = [
+        RuntimeBuilder::new().worker_threads(0),
+        RuntimeBuilder::new().worker_threads(65),
+        RuntimeBuilder::new().worker_capacity(0),
+        RuntimeBuilder::new().completed_capacity(0),
+        RuntimeBuilder::new().scheduler_cpu_offset(0),
+        RuntimeBuilder::new()
+            .scheduler_cpu_offset(3)
+            .tester_cpu_offset(3),
+        RuntimeBuilder::new()
+            .start_cpu(u16::MAX - 4)
+            .worker_threads(8),
+        RuntimeBuilder::new().layout(CpuLayout {
+            listener: CpuList::single(0),
+            scheduler: CpuList::single(1),
+            tester: CpuList::single(2),
+            workers: vec![CpuList::range(2, 3)],
+        }),
+        RuntimeBuilder::new().timer_signal(Signal::SIGURG),
+        RuntimeBuilder::new().preempt_signal(Signal::SIGKILL),
+        RuntimeBuilder::new().cgroup_root("hyperwasm"),
+        RuntimeBuilder::new().name("a/b"),
+    ];
+    for builder in invalid {
+        assert!(builder.build_simulated().is_err(), "{:?}", builder);
+    }
+
+    // 偏移决定scheduler和tester的CPU，worker使用其余的CPU
+    let rt = RuntimeBuilder::new()
+        .worker_threads(2)
+        .start_cpu(4)
+        .scheduler_cpu_offset(3)
+        .tester_cpu_offset(1)
+        .name("offsets")
+        .build_simulated()
+        .unwrap();
+    let layout = rt.get_layout();
+    assert_eq!(layout.listener, CpuList::single(4));
+    assert_eq!(layout.tester, CpuList::single(5));
+    assert_eq!(layout.scheduler, CpuList::single(7));
+    assert_eq!(layout.workers, vec![CpuList::single(6), CpuList::single(8)]);
+    assert_eq!(rt.get_name(), "offsets");
+
+    // 名字决定cgroup子树，正在使用的名字不能重复，包括自动生成的名字
+    let err = match RuntimeBuilder::new().name("offsets").build_simulated() {
+        Ok(_) => panic!("duplicate runtime name"),
+        Err(err) => err,
+    };
+    assert!(err.to_string().contains("already in use"), "{}", err);
+    let generated = Runtime::simulated(1);
+    assert!(RuntimeBuilder::new()
+        .name(generated.get_name())
+        .build_simulated()
+        .is_err());
+    drop(rt);
+    RuntimeBuilder::new()
+        .name("offsets")
+        .build_simulated()
+        .unwrap();
+}
+
+#[test]
+fn builder_configures_runtime() {
+    let rt = RuntimeBuilder::new()
+        .name("custom")
+        .worker_capacity(8)
+        .completed_capacity(16)
+        .stack_size(StackSize::with_size(256 * 1024))
+        .preempt_signal(Signal::SIGUSR1)
+        .timer_signal(Signal::SIGUSR2)
+        .timer_exp(1000)
+        .build()
+        .unwrap();
+    let done = Arc::new(AtomicU32::new(0));
+    for _ in 0..4 {
+        let done = done.clone();
+        rt.reserve(None, None, Criticality::LO, None)
+            .commit(move || {
+                done.fetch_add(1, Ordering::SeqCst);
+            })
+            .unwrap();
+    }
+    rt.shutdown(ShutdownMode::DRAINALL, Duration::from_secs(5))
+        .unwrap();
+    assert_eq!(done.load(Ordering::SeqCst), 4);
+}
+
+#[test]
+fn best_effort_tasks_take_turns() {
+    let rt = RuntimeBuilder::new()
+        .name("best-effort")
+        .worker_threads(1)
+        .timer_exp(1000)
+        .build()
+        .unwrap();
+    // 第一个任务单独运行，直到第二个任务运行后才结束
+    let stop = Arc::new(AtomicBool::new(false));
+    let stop_1 = stop.clone();
+    rt.reserve(None, None, Criticality::LO, None)
+        .commit(move || {
+            while !stop_1.load(Ordering::SeqCst) {
+                std::hint::spin_loop();
+            }
+        })
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(20));
+    let done = Arc::new(AtomicBool::new(false));
+    let (stop_2, done_1) = (stop.clone(), done.clone());
+    rt.reserve(None, None, Criticality::LO, None)
+        .commit(move || {
+            done_1.store(true, Ordering::SeqCst);
+            stop_2.store(true, Ordering::SeqCst);
+        })
+        .unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !done.load(Ordering::SeqCst) && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    stop.store(true, Ordering::SeqCst);
+    assert!(done.load(Ordering::SeqCst));
+    rt.shutdown(ShutdownMode::DRAINALL, Duration::from_secs(5))
+        .unwrap();
+}
+
+#[test]
+fn preemptive_commits_share_one_slot() {
+    let rt = RuntimeBuilder::new()
+        .name("slots")
+        .worker_threads(1)
+        .build()
+        .unwrap();
+    let ms = Duration::from_millis;
+    let stop = Arc::new(AtomicBool::new(false));
+    let stop_1 = stop.clone();
+    rt.reserve(None, None, Criticality::LO, None)
+        .commit(move || {
+            while !stop_1.load(Ordering::SeqCst) {
+                std::hint::spin_loop();
+            }
+        })
+        .unwrap();
+    std::thread::sleep(ms(10));
+    // 两个任务都抢占正在运行的非实时任务，第二个任务不能因为槽被占用而丢失
+    let done = Arc::new(AtomicU32::new(0));
+    let reservations = [
+        rt.reserve(Some(ms(5)), Some(ms(200)), Criticality::LO, None),
+        rt.reserve(Some(ms(5)), Some(ms(100)), Criticality::LO, None),
+    ];
+    for reservation in reservations {
+        assert_ne!(reservation.get_ac(), AdmissionControl::UNSCHEDULABLE);
+        let done = done.clone();
+        reservation
+            .commit(move || {
+                done.fetch_add(1, Ordering::SeqCst);
+            })
+            .unwrap();
+    }
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while done.load(Ordering::SeqCst) < 2 && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    stop.store(true, Ordering::SeqCst);
+    assert_eq!(done.load(Ordering::SeqCst), 2);
+    rt.shutdown(ShutdownMode::DRAINALL, Duration::from_secs(5))
+        .unwrap();
+}
+
+#[test]
+fn add_worker_rejects_offline_cpu() {
+    let rt = Runtime::simulated(1);
+    let err = rt.add_worker(Some(CpuList::single(250))).unwrap_err();
+    assert!(err.to_string().contains("cpu 250 is not online"), "{}", err);
+}
@@ -0,0 +1,47 @@
+//! Runtime关闭：排空任务、停止准入、drop不阻塞
+
+use hyper_scheduler::{
+    runtime::{AdmissionControl, Runtime, ShutdownMode},
+    task::Criticality,
+};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+#[test]
+fn shutdown_stops_admission() {
+    let rt = Runtime::simulated(1);
+    let ms = Duration::from_millis;
+    let before = rt.reserve(Some(ms(10)), Some(ms(100)), Criticality::LO, None);
+    assert_ne!(before.get_ac(), AdmissionControl::UNSCHEDULABLE);
+    drop(before);
+
+    rt.shutdown(ShutdownMode::DRAINADMITTED, Duration::ZERO)
+        .unwrap();
+    let rt_task = rt.reserve(Some(ms(10)), Some(ms(100)), Criticality::LO, None);
+    assert_eq!(rt_task.get_ac(), AdmissionControl::UNSCHEDULABLE);
+    let best_effort = rt.reserve(None, None, Criticality::LO, None);
+    assert_eq!(best_effort.get_ac(), AdmissionControl::UNSCHEDULABLE);
+}
+
+#[test]
+fn drain_all_finishes_queued_tasks() {
+    let rt = Runtime::new(Some(1), None, None);
+    let done = Arc::new(AtomicBool::new(false));
+    let done_1 = done.clone();
+    rt.reserve(None, None, Criticality::LO, None)
+        .commit(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            done_1.store(true, Ordering::SeqCst);
+        })
+        .unwrap();
+    rt.shutdown(ShutdownMode::DRAINALL, Duration::from_secs(5))
+        .unwrap();
+    assert!(done.load(Ordering::SeqCst));
+    // 关闭后drop不再等待worker
+    drop(rt);
+}
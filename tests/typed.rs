@@ -0,0 +1,88 @@
+//! 按导出函数的签名解析参数、分配返回值
+
+mod common;
+
+use common::{environment, try_run};
+use hyper_scheduler::{
+    axum::CallConfigRequest,
+    runtime::{RuntimeBuilder, ShutdownMode},
+};
+use serde_json::json;
+use std::time::Duration;
+
+/// mix混合i32和i64参数，half返回f32，count只有一个i32参数
+const WAT: &str = r#"
+(module
+  (func (export "mix") (param $a i32) (param $b i64) (result i64)
+    (i64.add (i64.extend_i32_s (local.get $a)) (local.get $b)))
+  (func (export "half") (param $x f64) (result f32)
+    (f32.demote_f64 (f64.div (local.get $x) (f64.const 2))))
+  (func (export "count") (param $n i32) (result i32)
+    (local.get $n)))
+"#;
+
+fn request(name: &str, export: &str, args: serde_json::Value) -> CallConfigRequest {
+    CallConfigRequest {
+        task_unique_name: name.to_owned(),
+        export_func: export.to_owned(),
+        args: serde_json::from_value(args).unwrap(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn typed_args_follow_signature() {
+    let rt = RuntimeBuilder::new().name("typed").build().unwrap();
+    let env = environment("typed", WAT);
+
+    assert_eq!(
+        try_run(
+            &rt,
+            &env,
+            request("mix", "mix", json!([3, {"i64": "9000000000"}]))
+        )
+        .unwrap(),
+        "[I64(9000000003)]"
+    );
+    assert_eq!(
+        try_run(&rt, &env, request("mix-plain", "mix", json!([-1, 1]))).unwrap(),
+        "[I64(0)]"
+    );
+    assert_eq!(
+        try_run(&rt, &env, request("half", "half", json!([2.5]))).unwrap(),
+        format!("[F32({})]", 1.25f32.to_bits())
+    );
+
+    let err = try_run(&rt, &env, request("arity", "mix", json!([1]))).unwrap_err();
+    assert!(err.contains("mix expects 2 arguments, got 1"), "{}", err);
+    let err = try_run(&rt, &env, request("tag", "mix", json!([1, {"i32": 2}]))).unwrap_err();
+    assert!(err.contains("args[1] expects i64, got i32"), "{}", err);
+    let err = try_run(&rt, &env, request("float", "mix", json!([1.5, 2]))).unwrap_err();
+    assert!(err.contains("args[0] expects i32"), "{}", err);
+    let err = try_run(&rt, &env, request("missing", "nope", json!([]))).unwrap_err();
+    assert!(err.contains("Invalid_export_func"), "{}", err);
+    rt.shutdown(ShutdownMode::DRAINALL, Duration::from_secs(5))
+        .unwrap();
+}
+
+#[test]
+fn legacy_params_are_checked() {
+    let rt = RuntimeBuilder::new().name("typed-legacy").build().unwrap();
+    let env = environment("typed-legacy", WAT);
+    let legacy = |name: &str, export: &str, params: &[&str]| CallConfigRequest {
+        task_unique_name: name.to_owned(),
+        export_func: export.to_owned(),
+        param_type: "i32".to_owned(),
+        params: params.iter().map(|p| p.to_string()).collect(),
+        ..Default::default()
+    };
+    // 不需要results_length，返回值按签名分配
+    assert_eq!(
+        try_run(&rt, &env, legacy("count", "count", &["5"])).unwrap(),
+        "[I32(5)]"
+    );
+    let err = try_run(&rt, &env, legacy("mix", "mix", &["1", "2"])).unwrap_err();
+    assert!(err.contains("params[1] expects i64, got i32"), "{}", err);
+    rt.shutdown(ShutdownMode::DRAINALL, Duration::from_secs(5))
+        .unwrap();
+}
@@ -0,0 +1,106 @@
+//! 看门狗：失控的实时任务被报告或取消
+
+use hyper_scheduler::{
+    runtime::{RuntimeBuilder, ShutdownMode, WatchdogAction, WatchdogConfig},
+    task::Criticality,
+};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+fn config(action: WatchdogAction) -> WatchdogConfig {
+    WatchdogConfig {
+        interval: Duration::from_millis(5),
+        budget_factor: 2,
+        deadline_grace: Duration::from_millis(5),
+        max_run: None,
+        action,
+    }
+}
+
+/// 等待条件成立，超时返回false
+fn wait_for(timeout: Duration, f: impl Fn() -> bool) -> bool {
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        if f() {
+            return true;
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    f()
+}
+
+#[test]
+fn watchdog_demotes_native_task_on_kill() {
+    let rt = RuntimeBuilder::new()
+        .name("watchdog-kill")
+        .timer_exp(1000)
+        .watchdog(config(WatchdogAction::KILL))
+        .build()
+        .unwrap();
+    let ms = Duration::from_millis;
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let cancelled_1 = cancelled.clone();
+    let stop = Arc::new(AtomicBool::new(false));
+    let stop_1 = stop.clone();
+    let id = rt
+        .reserve(Some(ms(2)), Some(ms(20)), Criticality::LO, None)
+        .commit_with_cancel(
+            move || {
+                while !stop_1.load(Ordering::SeqCst) {
+                    std::hint::spin_loop();
+                }
+            },
+            move || cancelled_1.store(true, Ordering::SeqCst),
+        )
+        .unwrap();
+    // 原生闭包的栈不能丢弃，降级为非实时任务
+    assert!(wait_for(Duration::from_secs(5), || rt
+        .get_status_by_id(id)
+        .is_some_and(|s| s.absolute_deadline.is_none())));
+    assert!(!cancelled.load(Ordering::SeqCst));
+
+    // 和其他任务轮转
+    let done = Arc::new(AtomicBool::new(false));
+    let done_1 = done.clone();
+    rt.reserve(None, None, Criticality::LO, None)
+        .commit(move || done_1.store(true, Ordering::SeqCst))
+        .unwrap();
+    assert!(wait_for(Duration::from_secs(5), || done.load(Ordering::SeqCst)));
+    assert!(rt.get_health().iter().all(|w| w.healthy));
+    stop.store(true, Ordering::SeqCst);
+    rt.shutdown(ShutdownMode::DRAINADMITTED, Duration::from_secs(5))
+        .unwrap();
+    assert!(!cancelled.load(Ordering::SeqCst));
+}
+
+#[test]
+fn health_reports_overrunning_worker() {
+    let rt = RuntimeBuilder::new()
+        .name("watchdog-report")
+        .watchdog(config(WatchdogAction::REPORT))
+        .build()
+        .unwrap();
+    let ms = Duration::from_millis;
+    rt.reserve(Some(ms(1)), Some(ms(10)), Criticality::LO, None)
+        .commit(move || {
+            let start = Instant::now();
+            while start.elapsed() < ms(300) {
+                std::hint::spin_loop();
+            }
+        })
+        .unwrap();
+    assert!(wait_for(Duration::from_secs(5), || {
+        rt.get_health()
+            .iter()
+            .any(|w| !w.healthy && w.reason.is_some())
+    }));
+    // 只报告，任务运行结束后恢复健康
+    rt.shutdown(ShutdownMode::DRAINADMITTED, Duration::from_secs(5))
+        .unwrap();
+    assert!(rt.get_health().iter().all(|w| w.healthy));
+}
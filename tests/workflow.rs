@@ -0,0 +1,153 @@
+//! 工作流的截止时间划分和原子准入
+
+mod common;
+
+use common::{environment, wait};
+use hyper_scheduler::{
+    axum::{WorkflowNodeRequest, WorkflowRequest},
+    result::{FuncResult, ResultFuture},
+    runtime::{AdmissionControl, Runtime, RuntimeBuilder, ShutdownMode},
+    task::Criticality,
+    workflow::{call_workflow, split_deadline, Workflow},
+};
+use serde_json::json;
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+/// count返回参数，add返回两个i32参数的和，wide只接受i64
+const WAT: &str = r#"
+(module
+  (func (export "count") (param $n i32) (result i32)
+    (local.get $n))
+  (func (export "add") (param $a i32) (param $b i32) (result i32)
+    (i32.add (local.get $a) (local.get $b)))
+  (func (export "wide") (param $x i64) (result i64)
+    (local.get $x)))
+"#;
+
+fn node(name: &str, export: &str, deps: &[&str]) -> WorkflowNodeRequest {
+    WorkflowNodeRequest {
+        name: name.to_owned(),
+        wasm_name: "workflow".to_owned(),
+        export_func: export.to_owned(),
+        expected_execution_time: "1".to_owned(),
+        deps: deps.iter().map(|dep| dep.to_string()).collect(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn split_deadline_follows_critical_path() {
+    let ms = Duration::from_millis;
+    // a -> b -> d, a -> c -> d
+    let wcets = [ms(10), ms(20), ms(10), ms(10)];
+    let deps = vec![vec![], vec![0], vec![0], vec![1, 2]];
+    let sub = split_deadline(&wcets, &deps, ms(80)).unwrap();
+    assert_eq!(sub, vec![ms(20), ms(60), ms(40), ms(80)]);
+    // 前驱的截止时间早于后继
+    for (i, d) in deps.iter().enumerate() {
+        assert!(d.iter().all(|&p| sub[p] < sub[i]));
+    }
+
+    // 截止时间小于关键路径
+    assert!(split_deadline(&wcets, &deps, ms(39)).is_none());
+    // 有环
+    let cyclic = vec![vec![1], vec![0]];
+    assert!(split_deadline(&wcets[..2], &cyclic, ms(80)).is_none());
+}
+
+#[test]
+fn reserve_group_is_atomic() {
+    let rt = Runtime::simulated(1);
+    let ms = Duration::from_millis;
+    let busy = rt.reserve(Some(ms(40)), Some(ms(100)), Criticality::LO, None);
+    assert_ne!(busy.get_ac(), AdmissionControl::UNSCHEDULABLE);
+
+    // 第二个节点放不下，整个工作流被拒绝，第一个节点的预留也被释放
+    let lo = Criticality::LO;
+    assert!(rt
+        .reserve_group(&[(ms(30), ms(50), lo), (ms(40), ms(100), lo)])
+        .is_none());
+    let probe = rt.reserve(Some(ms(50)), Some(ms(100)), Criticality::LO, None);
+    assert_ne!(probe.get_ac(), AdmissionControl::UNSCHEDULABLE);
+    drop(probe);
+
+    let group = rt
+        .reserve_group(&[(ms(20), ms(50), lo), (ms(20), ms(100), lo)])
+        .unwrap();
+    assert_eq!(group.len(), 2);
+    let worker = group[0].get_worker_id();
+    assert!(group.iter().all(|r| r.get_worker_id() == worker));
+}
+
+#[test]
+fn reserve_group_spans_workers() {
+    let rt = Runtime::simulated(2);
+    let ms = Duration::from_millis;
+    // 一个worker放不下两个节点，分到两个worker上
+    let group = rt
+        .reserve_group(&[
+            (ms(60), ms(100), Criticality::HI),
+            (ms(60), ms(100), Criticality::LO),
+        ])
+        .unwrap();
+    assert_ne!(group[0].get_worker_id(), group[1].get_worker_id());
+    // 每个节点按自己的关键级别准入
+    let criticality: Vec<Criticality> = group
+        .iter()
+        .map(|r| r.get_costatus().unwrap().criticality)
+        .collect();
+    assert_eq!(criticality, vec![Criticality::HI, Criticality::LO]);
+    // 两个worker都放不下第三个节点
+    assert!(rt
+        .reserve_group(&[(ms(60), ms(100), Criticality::LO)])
+        .is_none());
+}
+
+#[test]
+fn workflow_params_follow_signature() {
+    let rt: &'static Runtime = Box::leak(Box::new(
+        RuntimeBuilder::new().name("workflow").build().unwrap(),
+    ));
+    let envs = HashMap::from([("workflow".to_owned(), environment("workflow", WAT))]);
+    let source = WorkflowNodeRequest {
+        param_type: "i32".to_owned(),
+        params: vec!["5".to_owned()],
+        ..node("a", "count", &[])
+    };
+    let run = |sink: WorkflowNodeRequest| {
+        let workflow = Workflow::new(WorkflowRequest {
+            expected_deadline: "100".to_owned(),
+            nodes: vec![source.clone(), sink],
+        })
+        .unwrap();
+        let result = Arc::new(FuncResult::new());
+        call_workflow(rt, &envs, workflow, &result)
+            .map(|()| wait(ResultFuture { result }))
+            .map_err(|err| err.to_string())
+    };
+
+    // 固定参数在前驱节点的输出之前
+    let sink = WorkflowNodeRequest {
+        args: serde_json::from_value(json!([1])).unwrap(),
+        ..node("b", "add", &["a"])
+    };
+    assert_eq!(run(sink).unwrap(), "b: [I32(6)]");
+
+    let sink = WorkflowNodeRequest {
+        param_type: "i32".to_owned(),
+        params: vec!["1".to_owned()],
+        ..node("b", "count", &["a"])
+    };
+    let err = run(sink).unwrap_err();
+    assert!(
+        err.contains("b expects 1 arguments, got 1 params and 1 outputs"),
+        "{}",
+        err
+    );
+    let err = run(node("b", "wide", &["a"])).unwrap_err();
+    assert!(err.contains("b params[0] expects i64, got i32"), "{}", err);
+    let err = run(node("b", "nope", &["a"])).unwrap_err();
+    assert!(err.contains("Invalid_export_func"), "{}", err);
+    rt.shutdown(ShutdownMode::DRAINALL, Duration::from_secs(5))
+        .unwrap();
+}